@@ -4,10 +4,20 @@
 //! Клиент для работы с сервером стриминга
 //! Пример запуска:
 //! cargo run -- --server-addr 127.0.0.1:8080 --udp-port 34254 --tickers-path tickers.txt
+//! cargo run -- --server-addr [::1]:8080 --udp-host ::1 --tickers-path tickers.txt
+//! cargo run -- --multicast --multicast-if 127.0.0.1 --tickers-path tickers.txt
+//! cargo run -- --tcp --tickers-path tickers.txt
+//! cargo run -- --depth --tickers-path tickers.txt
+//! cargo run -- --bars 1m --tickers-path tickers.txt
+//! cargo run -- --snapshot --tickers-path tickers.txt
+//! cargo run -- --history 20 --tickers-path tickers.txt
+//! cargo run -- --record session.tsv --tickers-path tickers.txt
+//! cargo run -- replay session.tsv --speed 4
+//! cargo run -- --output jsonl --output-file quotes.jsonl --tickers-path tickers.txt
 
 use std::{
     io::{BufRead, BufReader, Write},
-    net::SocketAddr,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     sync::{
         Arc, Mutex,
         atomic::{AtomicBool, Ordering},
@@ -17,18 +27,116 @@ use std::{
 };
 
 use clap::Parser;
-use quote_lib::{PING_MSG, PONG_MSG, SERVER_OK, STREAM_CMD, StockQuote};
+use quote_lib::{
+    BARS_CMD, BarInterval, DEPTH_CMD, END_MSG, FORMAT_OPTION, HISTORY_CMD, MAX_DATAGRAM_SIZE,
+    MULTICAST_TRANSPORT, Message, MessageKind, PING_MSG, PONG_MSG, QuoteFormat, SEQ_OPTION,
+    SERVER_OK, SNAPSHOT_CMD, STREAM_CMD, TCP_TRANSPORT, TYPES_OPTION,
+};
+
+mod multicast;
+mod output;
+mod recorder;
+
+use output::{OutputFormat, QuoteOutput};
 
 #[derive(Parser)]
+#[command(subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[clap(short, long, default_value = "127.0.0.1:8080")]
     server_addr: String,
 
     #[clap(short, long, default_value = "34254")]
     udp_port: u16,
 
-    #[clap(short, long)]
-    tickers_path: String,
+    /// Адрес, на который сервер будет отправлять котировки (IPv4 или IPv6).
+    #[clap(long, default_value = "127.0.0.1")]
+    udp_host: IpAddr,
+
+    #[clap(short, long, required = true)]
+    tickers_path: Option<String>,
+
+    /// Получать котировки из multicast групп сервера вместо UDP потока.
+    #[clap(long, conflicts_with = "tcp")]
+    multicast: bool,
+
+    /// Получать котировки по управляющему TCP соединению вместо UDP потока.
+    #[clap(long)]
+    tcp: bool,
+
+    /// Получать стакан по управляющему TCP соединению командой `DEPTH` вместо котировок.
+    #[clap(long, conflicts_with_all = ["multicast", "tcp"])]
+    depth: bool,
+
+    /// Получать свечи интервала (`1s`, `1m`, `5m`) командой `BARS` вместо котировок,
+    /// вместе с `--history` - историю свечей.
+    #[clap(long, conflicts_with_all = ["multicast", "tcp", "depth", "snapshot"])]
+    bars: Option<BarInterval>,
+
+    /// Вывести последние котировки и завершиться, без подписки на поток.
+    #[clap(long)]
+    snapshot: bool,
+
+    /// Вывести историю котировок и завершиться: число последних котировок или `since=<время>`.
+    #[clap(long, conflicts_with = "snapshot")]
+    history: Option<String>,
+
+    /// Время в секундах без данных от сервера по TCP, после которого поток считается потерянным.
+    #[clap(long, default_value = "5")]
+    heartbeat_timeout: u64,
+
+    /// Интерфейс для подключения к multicast группам (`127.0.0.1` - только loopback).
+    #[clap(long, default_value = "0.0.0.0")]
+    multicast_if: Ipv4Addr,
+
+    /// Формат котировок от сервера: `v1` (без bid/ask), `v2` или `bin`.
+    /// Двоичный формат только для UDP, по TCP вместо него запрашивается `v2`.
+    #[clap(long, default_value = "v2")]
+    format: QuoteFormat,
+
+    /// Типы рыночных данных через запятую: `quotes`, `trades`, `depth`.
+    #[clap(long, default_value = "quotes")]
+    types: String,
+
+    /// Ограничение потока котировок, можно указать несколько раз: `min-change=0.1%`,
+    /// `max-rate=5`, `conflate=250ms`, для одного тикера - `min-change:AAPL=0.5`.
+    #[clap(long = "filter", conflicts_with_all = ["multicast", "depth", "bars"])]
+    filters: Vec<String>,
+
+    /// Записывать все полученные сообщения с временем получения в файл,
+    /// для UDP с номерами сообщений в потоке от сервера.
+    #[clap(long)]
+    record: Option<String>,
+
+    /// Формат вывода котировок.
+    #[clap(long, value_enum, default_value = "text", global = true)]
+    output: OutputFormat,
+
+    /// Файл для вывода котировок вместо stdout.
+    #[clap(long, global = true)]
+    output_file: Option<String>,
+
+    /// Часовой пояс времени в текстовом выводе: `local`, `UTC` или имя IANA (`Europe/Moscow`).
+    #[clap(long, default_value = "local", global = true)]
+    timezone: String,
+
+    /// Шаблон времени в текстовом выводе в стиле strftime.
+    #[clap(long, default_value = "%Y-%m-%d %H:%M:%S%.3f %Z", global = true)]
+    time_format: String,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Воспроизведение сессии, записанной с `--record`.
+    Replay {
+        file: String,
+
+        /// Множитель скорости воспроизведения, 0 - без задержек.
+        #[clap(long, default_value = "1.0", value_parser = recorder::parse_speed)]
+        speed: f64,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -57,18 +165,93 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     log::info!("Запуск клиента");
 
+    let mut output = QuoteOutput::new(
+        args.output,
+        args.output_file.as_deref(),
+        output::parse_time_zone(&args.timezone)?,
+        args.time_format.clone(),
+    )?;
+
+    if let Some(Command::Replay { file, speed }) = &args.command {
+        return recorder::replay(file, *speed, &mut output, running);
+    }
+
     // Читаем тикеры из файла
-    let tickers = read_tickers_file(&args.tickers_path)?;
+    let tickers = read_tickers_file(args.tickers_path.as_deref().unwrap_or_default())?;
     log::info!("Загружено тикетов: {}", tickers.len());
 
     // Подключаемся к TCP серверу
     let mut stream = std::net::TcpStream::connect(&args.server_addr)?;
     log::info!("Подключено к серверу: {}", args.server_addr);
 
+    let text_format = match args.format {
+        QuoteFormat::Binary => QuoteFormat::TextV2,
+        format => format,
+    };
+
+    if args.snapshot {
+        let command = format!(
+            "{} {} {}{}",
+            SNAPSHOT_CMD,
+            tickers.join(","),
+            FORMAT_OPTION,
+            text_format
+        );
+        return print_quotes_response(stream, &[command], &mut output);
+    }
+
+    if let Some(history) = &args.history {
+        let commands: Vec<String> = tickers
+            .iter()
+            .map(|ticker| match args.bars {
+                Some(interval) => format!("{} {} {} {}", BARS_CMD, interval, ticker, history),
+                None => format!(
+                    "{} {} {} {}{}",
+                    HISTORY_CMD, ticker, history, FORMAT_OPTION, text_format
+                ),
+            })
+            .collect();
+        return print_quotes_response(stream, &commands, &mut output);
+    }
+
     // Формируем и отправляем команду STREAM
-    let local_udp_addr = format!("udp://127.0.0.1:{}", args.udp_port);
+    let udp_addr = SocketAddr::new(args.udp_host, args.udp_port);
+    let local_udp_addr = if args.multicast {
+        MULTICAST_TRANSPORT.to_string()
+    } else if args.tcp {
+        TCP_TRANSPORT.to_string()
+    } else {
+        format!("udp://{}", udp_addr)
+    };
     let tickers_str = tickers.join(",");
-    let command = format!("{} {} {}\n", STREAM_CMD, local_udp_addr, tickers_str);
+    let format = if args.tcp { text_format } else { args.format };
+    let kinds = MessageKind::parse_list(&args.types)?;
+    let kinds_str = kinds
+        .iter()
+        .map(|kind| kind.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    let command = if args.depth {
+        format!("{} {}\n", DEPTH_CMD, tickers_str)
+    } else if let Some(interval) = args.bars {
+        format!("{} {} {}\n", BARS_CMD, interval, tickers_str)
+    } else {
+        let mut command = format!(
+            "{} {} {} {}{} {}{}",
+            STREAM_CMD, local_udp_addr, tickers_str, FORMAT_OPTION, format, TYPES_OPTION, kinds_str
+        );
+        for filter in &args.filters {
+            command.push(' ');
+            command.push_str(filter);
+        }
+        // Номера сообщений от сервера показывают в записи потери и перестановки датаграмм
+        if args.record.is_some() && !args.multicast && !args.tcp {
+            command.push(' ');
+            command.push_str(SEQ_OPTION);
+        }
+        command.push('\n');
+        command
+    };
 
     stream.write_all(command.as_bytes())?;
     stream.flush()?;
@@ -96,8 +279,41 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    let recorder = args
+        .record
+        .as_deref()
+        .map(recorder::Recorder::create)
+        .transpose()?;
+
+    if args.multicast {
+        let groups = multicast::parse_reply(line.trim())?;
+        let sockets = multicast::join_groups(&groups, args.multicast_if)?;
+        return multicast::receive_multicast_loop(
+            sockets,
+            &tickers,
+            &kinds,
+            recorder,
+            &mut output,
+            running,
+        );
+    }
+
+    if args.tcp || args.depth || args.bars.is_some() {
+        return receive_tcp_quotes_loop(
+            reader,
+            Duration::from_secs(args.heartbeat_timeout),
+            recorder,
+            &mut output,
+            running,
+        );
+    }
+
     // Создаем UDP сокет для приема данных
-    let udp_socket = std::net::UdpSocket::bind(format!("0.0.0.0:{}", args.udp_port))?;
+    let bind_ip = match args.udp_host {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let udp_socket = std::net::UdpSocket::bind(SocketAddr::new(bind_ip, args.udp_port))?;
     log::info!("UDP сокет создан на порту: {}", args.udp_port);
 
     udp_socket.set_read_timeout(Some(Duration::from_secs(5)))?;
@@ -114,7 +330,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     });
 
     // Основной цикл приема котировок
-    receive_quotes_loop(udp_socket, server_udp_addr, running)?;
+    receive_quotes_loop(udp_socket, server_udp_addr, recorder, &mut output, running)?;
     handler.join().unwrap();
     Ok(())
 }
@@ -128,6 +344,39 @@ fn read_tickers_file(path: &str) -> Result<Vec<String>, Box<dyn std::error::Erro
         .collect())
 }
 
+/// Отправка команд с многострочным ответом (`SNAPSHOT`, `HISTORY`) и вывод полученных котировок.
+fn print_quotes_response(
+    mut stream: std::net::TcpStream,
+    commands: &[String],
+    output: &mut QuoteOutput,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut lines = BufReader::new(stream.try_clone()?).lines();
+    for command in commands {
+        stream.write_all(format!("{}\n", command).as_bytes())?;
+        stream.flush()?;
+        log::info!("Команда отправлена: {}", command);
+
+        let response = lines.next().transpose()?.unwrap_or_default();
+        if response.trim() != SERVER_OK {
+            log::error!("Ошибка выполнения команды: {}", response.trim());
+            return Err("Ошибка выполнения команды".into());
+        }
+        for line in lines.by_ref() {
+            let line = line?;
+            if line.trim() == END_MSG {
+                break;
+            }
+            match Message::decode(line.trim().as_bytes()) {
+                Ok(message) => output.write_message(&message, jiff::Timestamp::now()),
+                Err(e) => {
+                    log::error!("Ошибка парсинга котировки: {}", e);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 fn send_ping_loop(
     socket: std::net::UdpSocket,
     server_addr: Arc<Mutex<Option<PingData>>>,
@@ -154,12 +403,74 @@ fn send_ping_loop(
     log::info!("Завершение потока Ping");
 }
 
+fn receive_tcp_quotes_loop(
+    mut reader: BufReader<std::net::TcpStream>,
+    heartbeat_timeout: Duration,
+    mut recorder: Option<recorder::Recorder>,
+    output: &mut QuoteOutput,
+    running: Arc<AtomicBool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Устанавливаем таймаут для возможности graceful shutdown
+    reader
+        .get_ref()
+        .set_read_timeout(Some(Duration::from_secs(1)))?;
+    log::info!("Запускаем основной поток");
+    let mut last_data = Instant::now();
+    // Строка накапливается между таймаутами чтения
+    let mut line = String::new();
+    while running.load(Ordering::SeqCst) {
+        if last_data.elapsed() > heartbeat_timeout {
+            log::info!(
+                "Превышено время ожидания данных от сервера ({}сек)",
+                heartbeat_timeout.as_secs()
+            );
+            break;
+        }
+        match reader.read_line(&mut line) {
+            Ok(0) => {
+                log::info!("Сервер отключился");
+                break;
+            }
+            Ok(_) => {
+                last_data = Instant::now();
+                let data = line.trim();
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.record(data.as_bytes());
+                }
+                match Message::decode(data.as_bytes()) {
+                    Ok(message) => output.write_message(&message, jiff::Timestamp::now()),
+                    Err(e) => {
+                        log::error!("Ошибка парсинга котировки: {}", e);
+                    }
+                }
+                line.clear();
+            }
+            Err(e)
+                if e.kind() == std::io::ErrorKind::TimedOut
+                    || e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::Interrupted =>
+            {
+                continue;
+            }
+            Err(e) => {
+                log::error!("{}", e);
+                break;
+            }
+        }
+    }
+
+    log::info!("Завершаем основной поток");
+    Ok(())
+}
+
 fn receive_quotes_loop(
     socket: std::net::UdpSocket,
     server_addr: Arc<Mutex<Option<PingData>>>,
+    mut recorder: Option<recorder::Recorder>,
+    output: &mut QuoteOutput,
     running: Arc<AtomicBool>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut buf = [0; 1024];
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
 
     // Устанавливаем таймаут для возможности graceful shutdown
     socket.set_read_timeout(Some(Duration::from_secs(1)))?;
@@ -189,6 +500,9 @@ fn receive_quotes_loop(
         match socket.recv_from(&mut buf) {
             Ok((size, src_addr)) => {
                 log::info!("Получили: {} байт", size);
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.record(&buf[..size]);
+                }
                 // Обновляем адрес сервера, если он еще не определен
                 {
                     let mut guard = server_addr.lock().unwrap();
@@ -215,18 +529,9 @@ fn receive_quotes_loop(
                     continue;
                 }
 
-                // Парсим котировку
-                match StockQuote::from_string(&String::from_utf8_lossy(&buf[..size])) {
-                    Ok(quote) => {
-                        println!(
-                            "Получена котировка: {} - ${:.2} (объем: {}) время: {}",
-                            // TODO перевести время в более читабельный вид
-                            quote.ticker,
-                            quote.price,
-                            quote.volume,
-                            quote.timestamp
-                        );
-                    }
+                // Парсим сообщение
+                match Message::decode(&buf[..size]) {
+                    Ok(message) => output.write_message(&message, jiff::Timestamp::now()),
                     Err(e) => {
                         log::error!("Ошибка парсинга котировки: {}", e);
                     }
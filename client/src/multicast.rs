@@ -0,0 +1,218 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use crossbeam::channel::{self, RecvTimeoutError};
+use quote_lib::{MAX_DATAGRAM_SIZE, MULTICAST_REPLY, Message, MessageKind};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::{output::QuoteOutput, recorder::Recorder};
+
+/// Multicast группа из ответа сервера.
+#[derive(Debug)]
+pub(crate) struct MulticastGroup {
+    addr: SocketAddr,
+    tickers: Vec<String>,
+}
+
+/// Разбор ответа сервера `OK MULTICAST 239.255.0.1:30001=AAPL,MSFT ...`.
+pub(crate) fn parse_reply(reply: &str) -> Result<Vec<MulticastGroup>, Box<dyn std::error::Error>> {
+    let mut parts = reply
+        .split_whitespace()
+        .skip_while(|p| *p != MULTICAST_REPLY);
+    if parts.next().is_none() {
+        return Err(format!("В ответе нет списка multicast групп: {}", reply).into());
+    }
+    let mut groups = vec![];
+    for part in parts {
+        let Some((addr, tickers)) = part.split_once('=') else {
+            return Err(format!("Некорректная группа: {}", part).into());
+        };
+        groups.push(MulticastGroup {
+            addr: addr.parse()?,
+            tickers: tickers.split(',').map(|s| s.to_string()).collect(),
+        });
+    }
+    Ok(groups)
+}
+
+/// Подключение к группам: по одному сокету на порт, сокет входит во все группы этого порта.
+pub(crate) fn join_groups(
+    groups: &[MulticastGroup],
+    interface: Ipv4Addr,
+) -> Result<Vec<UdpSocket>, std::io::Error> {
+    let mut sockets: HashMap<(bool, u16), UdpSocket> = HashMap::new();
+    for group in groups {
+        let socket = match sockets.entry((group.addr.is_ipv4(), group.addr.port())) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(bind_socket(&group.addr)?),
+        };
+        match group.addr.ip() {
+            IpAddr::V4(ip) => socket.join_multicast_v4(&ip, &interface)?,
+            IpAddr::V6(ip) => socket.join_multicast_v6(&ip, 0)?,
+        }
+        log::info!(
+            "Подключено к multicast группе: {} ({})",
+            group.addr,
+            group.tickers.join(",")
+        );
+    }
+    Ok(sockets.into_values().collect())
+}
+
+fn bind_socket(group: &SocketAddr) -> Result<UdpSocket, std::io::Error> {
+    let socket = Socket::new(
+        Domain::for_address(*group),
+        Type::DGRAM,
+        Some(Protocol::UDP),
+    )?;
+    // Несколько клиентов на одном хосте слушают один порт
+    socket.set_reuse_address(true)?;
+    let bind_ip = match group.ip() {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    socket.bind(&SocketAddr::new(bind_ip, group.port()).into())?;
+    Ok(socket.into())
+}
+
+/// Прием котировок из multicast групп с фильтрацией по своим тикерам.
+pub(crate) fn receive_multicast_loop(
+    sockets: Vec<UdpSocket>,
+    tickers: &[String],
+    kinds: &[MessageKind],
+    mut recorder: Option<Recorder>,
+    output: &mut QuoteOutput,
+    running: Arc<AtomicBool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (sender, receiver) = channel::unbounded();
+    let mut handles = vec![];
+    for socket in sockets {
+        socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let sender = sender.clone();
+        let running = running.clone();
+        handles.push(thread::spawn(move || {
+            let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+            while running.load(Ordering::SeqCst) {
+                match socket.recv_from(&mut buf) {
+                    Ok((size, _)) => {
+                        if sender.send(buf[..size].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e)
+                        if e.kind() == std::io::ErrorKind::TimedOut
+                            || e.kind() == std::io::ErrorKind::WouldBlock
+                            || e.kind() == std::io::ErrorKind::Interrupted =>
+                    {
+                        continue;
+                    }
+                    Err(e) => {
+                        log::error!("{}", e);
+                        break;
+                    }
+                }
+            }
+        }));
+    }
+    drop(sender);
+
+    log::info!("Запускаем основной поток");
+    while running.load(Ordering::SeqCst) {
+        let data = match receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(data) => data,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.record(&data);
+        }
+        match Message::decode(&data) {
+            Ok(message)
+                if message.kind().is_none_or(|kind| kinds.contains(&kind))
+                    && message
+                        .ticker()
+                        .is_none_or(|ticker| tickers.iter().any(|t| t == ticker)) =>
+            {
+                output.write_message(&message, jiff::Timestamp::now())
+            }
+            Ok(_) => {}
+            Err(e) => {
+                log::error!("Ошибка парсинга котировки: {}", e);
+            }
+        }
+    }
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    log::info!("Завершаем основной поток");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use socket2::SockRef;
+
+    use super::*;
+
+    #[test]
+    fn parse_server_reply() {
+        // Ответ в том виде, в котором его формирует сервер
+        let groups =
+            parse_reply("OK MULTICAST 239.255.0.1:30001=AAPL,MSFT [ff15::1]:30002=TSLA").unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].addr, "239.255.0.1:30001".parse().unwrap());
+        assert_eq!(groups[0].tickers, ["AAPL", "MSFT"]);
+        assert_eq!(groups[1].addr, "[ff15::1]:30002".parse().unwrap());
+        assert_eq!(groups[1].tickers, ["TSLA"]);
+        assert!(parse_reply("OK MULTICAST").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_rejects_bad_reply() {
+        for reply in [
+            "OK",
+            "Нет multicast групп",
+            "OK MULTICAST 239.255.0.1:30001",
+            "OK MULTICAST 239.255.0.1=AAPL",
+            "OK MULTICAST group=AAPL",
+        ] {
+            assert!(parse_reply(reply).is_err(), "{}", reply);
+        }
+    }
+
+    /// Подключение к группе и прием на одном хосте только через loopback интерфейс.
+    #[test]
+    fn join_over_loopback() {
+        let port = UdpSocket::bind("0.0.0.0:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let groups = parse_reply(&format!("OK MULTICAST 239.255.77.2:{}=AAPL", port)).unwrap();
+        let sockets = join_groups(&groups, Ipv4Addr::LOCALHOST).unwrap();
+        assert_eq!(sockets.len(), 1);
+        sockets[0]
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let publisher = UdpSocket::bind("127.0.0.1:0").unwrap();
+        SockRef::from(&publisher)
+            .set_multicast_if_v4(&Ipv4Addr::LOCALHOST)
+            .unwrap();
+        publisher.set_multicast_ttl_v4(0).unwrap();
+        publisher.send_to(b"HEARTBEAT", groups[0].addr).unwrap();
+
+        let mut buf = [0; 64];
+        let (size, _) = sockets[0].recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..size], b"HEARTBEAT");
+    }
+}
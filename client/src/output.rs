@@ -0,0 +1,562 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use jiff::{Timestamp, tz::TimeZone};
+use quote_lib::{
+    Bar, BarInterval, BookApply, BookLevel, Message, OrderBook, Price, StockQuote, Trade, Volume,
+};
+use serde::Serialize;
+
+/// Формат вывода котировок.
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// Текст для чтения человеком.
+    Text,
+    /// JSON объект на строку.
+    Jsonl,
+    /// CSV с заголовком.
+    Csv,
+}
+
+/// Котировка или сделка в машиночитаемом выводе, имена полей - часть формата.
+/// У сделок нет bid/ask: в JSON поля отсутствуют, в CSV пустые.
+#[derive(Serialize)]
+struct MarketRecord<'a> {
+    /// `quote` или `trade`.
+    #[serde(rename = "type")]
+    kind: &'static str,
+    ticker: &'a str,
+    price: Price,
+    volume: Volume,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bid: Option<Price>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ask: Option<Price>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bid_size: Option<Volume>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ask_size: Option<Volume>,
+    /// Время котировки в ISO-8601 (UTC).
+    timestamp: String,
+    /// Время получения котировки клиентом в ISO-8601 (UTC).
+    received: String,
+    /// Возраст котировки на момент получения в миллисекундах.
+    age_ms: i64,
+}
+
+/// Состояние стакана в JSON выводе после очередного снимка или обновления.
+#[derive(Serialize)]
+struct BookRecord<'a> {
+    /// Всегда `book`.
+    #[serde(rename = "type")]
+    kind: &'static str,
+    ticker: &'a str,
+    seq: u64,
+    bids: Vec<BookLevel>,
+    asks: Vec<BookLevel>,
+    /// Время изменения стакана в ISO-8601 (UTC).
+    timestamp: String,
+    /// Время получения клиентом в ISO-8601 (UTC).
+    received: String,
+}
+
+/// Свеча в JSON выводе.
+#[derive(Serialize)]
+struct BarRecord<'a> {
+    /// Всегда `bar`.
+    #[serde(rename = "type")]
+    kind: &'static str,
+    ticker: &'a str,
+    interval: BarInterval,
+    /// Начало интервала в ISO-8601 (UTC).
+    start: String,
+    open: Price,
+    high: Price,
+    low: Price,
+    close: Price,
+    volume: Volume,
+    /// Время получения клиентом в ISO-8601 (UTC).
+    received: String,
+}
+
+/// Вывод котировок в stdout или файл, логи при этом идут в stderr.
+/// Машиночитаемые форматы всегда в UTC, текст - в `time_zone` по шаблону `time_format`.
+pub(crate) struct QuoteOutput {
+    format: OutputFormat,
+    writer: Box<dyn Write + Send>,
+    header_written: bool,
+    time_zone: TimeZone,
+    /// Шаблон времени в стиле strftime, например `%Y-%m-%d %H:%M:%S %Z`.
+    time_format: String,
+    /// Стаканы, собранные из снимков и обновлений сервера.
+    books: HashMap<String, OrderBook>,
+}
+
+impl QuoteOutput {
+    pub(crate) fn new(
+        format: OutputFormat,
+        path: Option<&str>,
+        time_zone: TimeZone,
+        time_format: String,
+    ) -> Result<Self, std::io::Error> {
+        let writer: Box<dyn Write + Send> = match path {
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+            None => Box::new(std::io::stdout()),
+        };
+        Ok(Self {
+            format,
+            writer,
+            header_written: false,
+            time_zone,
+            time_format,
+            books: HashMap::new(),
+        })
+    }
+
+    /// Вывод сообщения, `received` - время его получения клиентом.
+    /// Котировки, сделки, стаканы и свечи выводятся в выбранном формате, статусы сервера - в лог.
+    pub(crate) fn write_message(&mut self, message: &Message, received: Timestamp) {
+        let result = match message {
+            Message::Quote(quote) => self.try_write_quote(quote, received),
+            Message::Trade(trade) => self.try_write_trade(trade, received),
+            Message::BookSnapshot(snapshot) => {
+                let book = self.books.entry(snapshot.ticker.clone()).or_default();
+                book.apply_snapshot(snapshot);
+                self.try_write_book(&snapshot.ticker, snapshot.timestamp, received)
+            }
+            Message::BookUpdate(update) => {
+                let book = self.books.entry(update.ticker.clone()).or_default();
+                match book.apply_update(update) {
+                    BookApply::Applied => {
+                        self.try_write_book(&update.ticker, update.timestamp, received)
+                    }
+                    BookApply::Stale => Ok(()),
+                    BookApply::Gap => {
+                        log::warn!(
+                            "Пропуск обновлений стакана {} (номер {}), ожидаем снимок",
+                            update.ticker,
+                            update.seq
+                        );
+                        Ok(())
+                    }
+                }
+            }
+            Message::Bar(bar) => self.try_write_bar(bar, received),
+            Message::Status { message } => {
+                log::info!("Статус сервера: {}", message);
+                Ok(())
+            }
+            Message::Heartbeat => {
+                log::debug!("Получен HEARTBEAT от сервера");
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            log::error!("Ошибка вывода котировки: {}", e);
+        }
+    }
+
+    fn try_write_quote(
+        &mut self,
+        quote: &StockQuote,
+        received: Timestamp,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let record = record(
+            "quote",
+            &quote.ticker,
+            quote.price,
+            quote.volume,
+            quote.timestamp,
+            received,
+        )?;
+        let record = MarketRecord {
+            bid: Some(quote.bid),
+            ask: Some(quote.ask),
+            bid_size: Some(quote.bid_size),
+            ask_size: Some(quote.ask_size),
+            ..record
+        };
+        let text = format!(
+            "Получена котировка: {} - ${} (объем: {}) bid: {} x {} ask: {} x {} время: {} (возраст: {:.1}с)",
+            quote.ticker,
+            quote.price,
+            quote.volume,
+            quote.bid,
+            quote.bid_size,
+            quote.ask,
+            quote.ask_size,
+            self.local_time(quote.timestamp)?,
+            record.age_ms as f64 / 1000.0
+        );
+        self.write_record(&record, &text)
+    }
+
+    fn try_write_trade(
+        &mut self,
+        trade: &Trade,
+        received: Timestamp,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let record = record(
+            "trade",
+            &trade.ticker,
+            trade.price,
+            trade.size,
+            trade.timestamp,
+            received,
+        )?;
+        let text = format!(
+            "Получена сделка: {} - ${} (объем: {}) время: {} (возраст: {:.1}с)",
+            trade.ticker,
+            trade.price,
+            trade.size,
+            self.local_time(trade.timestamp)?,
+            record.age_ms as f64 / 1000.0
+        );
+        self.write_record(&record, &text)
+    }
+
+    /// Свеча: строка в тексте, объект в JSON, в CSV свечи не выводятся.
+    fn try_write_bar(
+        &mut self,
+        bar: &Bar,
+        received: Timestamp,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match self.format {
+            OutputFormat::Text => writeln!(
+                self.writer,
+                "Свеча {} {} {}: open {} high {} low {} close {} объем {}",
+                bar.ticker,
+                bar.interval,
+                self.local_time(bar.start)?,
+                bar.open,
+                bar.high,
+                bar.low,
+                bar.close,
+                bar.volume
+            )?,
+            OutputFormat::Jsonl => {
+                let record = BarRecord {
+                    kind: "bar",
+                    ticker: &bar.ticker,
+                    interval: bar.interval,
+                    start: Timestamp::from_millisecond(bar.start.as_millis() as i64)?.to_string(),
+                    open: bar.open,
+                    high: bar.high,
+                    low: bar.low,
+                    close: bar.close,
+                    volume: bar.volume,
+                    received: received.to_string(),
+                };
+                serde_json::to_writer(&mut self.writer, &record)?;
+                writeln!(self.writer)?;
+            }
+            OutputFormat::Csv => {
+                log::debug!("Свеча {} не выводится в CSV", bar.ticker);
+                return Ok(());
+            }
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Текущий стакан: таблица в тексте, уровни в JSON, в CSV стакан не выводится.
+    fn try_write_book(
+        &mut self,
+        ticker: &str,
+        timestamp: quote_lib::Timestamp,
+        received: Timestamp,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(book) = self.books.get(ticker) else {
+            return Ok(());
+        };
+        match self.format {
+            OutputFormat::Text => {
+                let mut text = format!(
+                    "Стакан {} (номер {}) время: {}\n{:>12} {:>10} | {:<12} {:<10}",
+                    ticker,
+                    book.seq(),
+                    self.local_time(timestamp)?,
+                    "bid",
+                    "объем",
+                    "ask",
+                    "объем"
+                );
+                let mut bids = book.bids();
+                let mut asks = book.asks();
+                loop {
+                    let (bid, ask) = (bids.next(), asks.next());
+                    if bid.is_none() && ask.is_none() {
+                        break;
+                    }
+                    let column = |level: Option<BookLevel>| match level {
+                        Some(level) => (level.price.to_string(), level.size.to_string()),
+                        None => (String::new(), String::new()),
+                    };
+                    let ((bid_price, bid_size), (ask_price, ask_size)) = (column(bid), column(ask));
+                    text.push_str(&format!(
+                        "\n{:>12} {:>10} | {:<12} {:<10}",
+                        bid_price, bid_size, ask_price, ask_size
+                    ));
+                }
+                writeln!(self.writer, "{}", text)?;
+            }
+            OutputFormat::Jsonl => {
+                let record = BookRecord {
+                    kind: "book",
+                    ticker,
+                    seq: book.seq(),
+                    bids: book.bids().collect(),
+                    asks: book.asks().collect(),
+                    timestamp: Timestamp::from_millisecond(timestamp.as_millis() as i64)?
+                        .to_string(),
+                    received: received.to_string(),
+                };
+                serde_json::to_writer(&mut self.writer, &record)?;
+                writeln!(self.writer)?;
+            }
+            OutputFormat::Csv => {
+                log::debug!("Стакан {} не выводится в CSV", ticker);
+                return Ok(());
+            }
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Время для текстового вывода в заданном часовом поясе и шаблоне.
+    fn local_time(&self, timestamp: quote_lib::Timestamp) -> Result<String, jiff::Error> {
+        Ok(Timestamp::from_millisecond(timestamp.as_millis() as i64)?
+            .to_zoned(self.time_zone.clone())
+            .strftime(&self.time_format)
+            .to_string())
+    }
+
+    fn write_record(
+        &mut self,
+        record: &MarketRecord,
+        text: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match self.format {
+            OutputFormat::Text => writeln!(self.writer, "{}", text)?,
+            OutputFormat::Jsonl => {
+                serde_json::to_writer(&mut self.writer, record)?;
+                writeln!(self.writer)?;
+            }
+            OutputFormat::Csv => {
+                if !self.header_written {
+                    writeln!(
+                        self.writer,
+                        "type,ticker,price,volume,bid,ask,bid_size,ask_size,timestamp,received,age_ms"
+                    )?;
+                    self.header_written = true;
+                }
+                let optional = |value: Option<String>| value.unwrap_or_default();
+                writeln!(
+                    self.writer,
+                    "{},{},{},{},{},{},{},{},{},{},{}",
+                    record.kind,
+                    record.ticker,
+                    record.price,
+                    record.volume,
+                    optional(record.bid.map(|v| v.to_string())),
+                    optional(record.ask.map(|v| v.to_string())),
+                    optional(record.bid_size.map(|v| v.to_string())),
+                    optional(record.ask_size.map(|v| v.to_string())),
+                    record.timestamp,
+                    record.received,
+                    record.age_ms
+                )?;
+            }
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Часовой пояс вывода: `local` - системный, `UTC` или имя IANA (`Europe/Moscow`).
+pub(crate) fn parse_time_zone(name: &str) -> Result<TimeZone, jiff::Error> {
+    match name {
+        "local" => Ok(TimeZone::system()),
+        "UTC" | "utc" => Ok(TimeZone::UTC),
+        name => TimeZone::get(name),
+    }
+}
+
+/// Общие поля записи, bid/ask заполняются только для котировок.
+fn record<'a>(
+    kind: &'static str,
+    ticker: &'a str,
+    price: Price,
+    volume: Volume,
+    timestamp: quote_lib::Timestamp,
+    received: Timestamp,
+) -> Result<MarketRecord<'a>, jiff::Error> {
+    let timestamp = Timestamp::from_millisecond(timestamp.as_millis() as i64)?;
+    Ok(MarketRecord {
+        kind,
+        ticker,
+        price,
+        volume,
+        bid: None,
+        ask: None,
+        bid_size: None,
+        ask_size: None,
+        timestamp: timestamp.to_string(),
+        received: received.to_string(),
+        age_ms: received.as_millisecond() - timestamp.as_millisecond(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use quote_lib::BookSnapshot;
+
+    use super::*;
+
+    /// Общий буфер вывода для проверки строк.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn output(format: OutputFormat) -> (QuoteOutput, Buffer) {
+        let buffer = Buffer::default();
+        let output = QuoteOutput {
+            format,
+            writer: Box::new(buffer.clone()),
+            header_written: false,
+            time_zone: TimeZone::UTC,
+            time_format: "%H:%M:%S".to_string(),
+            books: HashMap::new(),
+        };
+        (output, buffer)
+    }
+
+    fn price(value: &str) -> Price {
+        value.parse().unwrap()
+    }
+
+    fn time(ms: u64) -> quote_lib::Timestamp {
+        quote_lib::Timestamp::from_millis(ms)
+    }
+
+    fn received() -> Timestamp {
+        Timestamp::from_millisecond(1_700_000_000_750).unwrap()
+    }
+
+    fn messages() -> Vec<Message> {
+        vec![
+            Message::Quote(StockQuote {
+                ticker: "AAPL".to_string(),
+                price: price("150.25"),
+                volume: Volume::new(1200),
+                timestamp: time(1_700_000_000_250),
+                bid: price("150.24"),
+                ask: price("150.26"),
+                bid_size: Volume::new(300),
+                ask_size: Volume::new(400),
+            }),
+            Message::Trade(Trade {
+                ticker: "MSFT".to_string(),
+                price: price("310.5"),
+                size: Volume::new(75),
+                timestamp: time(1_700_000_000_500),
+            }),
+            Message::BookSnapshot(BookSnapshot {
+                ticker: "AAPL".to_string(),
+                seq: 7,
+                timestamp: time(1_700_000_000_500),
+                bids: vec![BookLevel {
+                    price: price("150.24"),
+                    size: Volume::new(300),
+                }],
+                asks: vec![BookLevel {
+                    price: price("150.26"),
+                    size: Volume::new(400),
+                }],
+            }),
+            Message::Bar(Bar {
+                ticker: "AAPL".to_string(),
+                interval: "1m".parse().unwrap(),
+                start: time(1_699_999_980_000),
+                open: price("150"),
+                high: price("151"),
+                low: price("149.5"),
+                close: price("150.25"),
+                volume: Volume::new(5000),
+            }),
+            Message::Status {
+                message: "Пропущено сообщений: 1".to_string(),
+            },
+        ]
+    }
+
+    fn lines(format: OutputFormat) -> Vec<String> {
+        let (mut output, buffer) = output(format);
+        for message in messages() {
+            output.write_message(&message, received());
+        }
+        let data = buffer.0.lock().unwrap().clone();
+        String::from_utf8(data)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn jsonl_golden_lines() {
+        assert_eq!(
+            lines(OutputFormat::Jsonl),
+            [
+                r#"{"type":"quote","ticker":"AAPL","price":150.25,"volume":1200,"bid":150.24,"ask":150.26,"bid_size":300,"ask_size":400,"timestamp":"2023-11-14T22:13:20.25Z","received":"2023-11-14T22:13:20.75Z","age_ms":500}"#,
+                // У сделки нет полей bid/ask
+                r#"{"type":"trade","ticker":"MSFT","price":310.5,"volume":75,"timestamp":"2023-11-14T22:13:20.5Z","received":"2023-11-14T22:13:20.75Z","age_ms":250}"#,
+                r#"{"type":"book","ticker":"AAPL","seq":7,"bids":[{"price":150.24,"size":300}],"asks":[{"price":150.26,"size":400}],"timestamp":"2023-11-14T22:13:20.5Z","received":"2023-11-14T22:13:20.75Z"}"#,
+                r#"{"type":"bar","ticker":"AAPL","interval":"1m","start":"2023-11-14T22:13:00Z","open":150.0,"high":151.0,"low":149.5,"close":150.25,"volume":5000,"received":"2023-11-14T22:13:20.75Z"}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_golden_lines() {
+        // Заголовок один раз, у сделки пустые bid/ask, стакан и свечи не выводятся
+        assert_eq!(
+            lines(OutputFormat::Csv),
+            [
+                "type,ticker,price,volume,bid,ask,bid_size,ask_size,timestamp,received,age_ms",
+                "quote,AAPL,150.25,1200,150.24,150.26,300,400,2023-11-14T22:13:20.25Z,2023-11-14T22:13:20.75Z,500",
+                "trade,MSFT,310.50,75,,,,,2023-11-14T22:13:20.5Z,2023-11-14T22:13:20.75Z,250",
+            ]
+        );
+    }
+
+    #[test]
+    fn text_golden_lines() {
+        assert_eq!(
+            lines(OutputFormat::Text),
+            [
+                "Получена котировка: AAPL - $150.25 (объем: 1200) bid: 150.24 x 300 ask: 150.26 x 400 время: 22:13:20 (возраст: 0.5с)",
+                "Получена сделка: MSFT - $310.50 (объем: 75) время: 22:13:20 (возраст: 0.2с)",
+                "Стакан AAPL (номер 7) время: 22:13:20",
+                "         bid      объем | ask          объем     ",
+                "      150.24        300 | 150.26       400       ",
+                "Свеча AAPL 1m 22:13:00: open 150.00 high 151.00 low 149.50 close 150.25 объем 5000",
+            ]
+        );
+    }
+}
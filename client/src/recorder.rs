@@ -0,0 +1,290 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use quote_lib::{Message, QuoteFormat};
+
+use crate::output::QuoteOutput;
+
+/// Запись полученных сообщений в файл, строка: `<номер>\t<время получения, мс>\t<сообщение>`.
+/// Номер - номер сообщения в потоке от сервера (опция `seq` для UDP), по нему видны
+/// потерянные и переставленные датаграммы; `-` - поток без номеров.
+pub(crate) struct Recorder {
+    file: BufWriter<File>,
+}
+
+impl Recorder {
+    pub(crate) fn create(path: &str) -> Result<Self, std::io::Error> {
+        log::info!("Запись сессии в {}", path);
+        Ok(Self {
+            file: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    /// Двоичные котировки записываются текстом второй версии, чтобы запись оставалась построчной.
+    pub(crate) fn record(&mut self, data: &[u8]) {
+        let (seq, data) = quote_lib::split_sequence(data);
+        let seq = seq.map_or("-".to_string(), |seq| seq.to_string());
+        let data = match Message::decode(data).map(|m| m.encode(QuoteFormat::TextV2)) {
+            Ok(Some(text)) if quote_lib::is_binary(data) => {
+                String::from_utf8_lossy(&text).into_owned()
+            }
+            _ => String::from_utf8_lossy(data).into_owned(),
+        };
+        let result = writeln!(
+            self.file,
+            "{}\t{}\t{}",
+            seq,
+            quote_lib::get_timestamp().as_millis(),
+            data.trim()
+        )
+        .and_then(|_| self.file.flush());
+        if let Err(e) = result {
+            log::error!("Ошибка записи сессии: {}", e);
+        }
+    }
+}
+
+/// Наименьший множитель скорости воспроизведения, кроме 0.
+const MIN_SPEED: f64 = 0.01;
+
+/// Разбор `--speed`: 0 или конечное число не меньше [`MIN_SPEED`].
+pub(crate) fn parse_speed(value: &str) -> Result<f64, String> {
+    let speed: f64 = value
+        .parse()
+        .map_err(|_| format!("Неверная скорость воспроизведения: {}", value))?;
+    if speed != 0.0 && !(speed.is_finite() && speed >= MIN_SPEED) {
+        return Err(format!(
+            "Скорость воспроизведения должна быть 0 или не меньше {}: {}",
+            MIN_SPEED, value
+        ));
+    }
+    Ok(speed)
+}
+
+/// Строка записи сессии.
+struct RecordedLine<'a> {
+    /// Номер сообщения от сервера, `None` - поток без номеров.
+    seq: Option<u64>,
+    arrival_ms: u64,
+    received: jiff::Timestamp,
+    data: &'a str,
+}
+
+/// Разбор строки записи, `None` - строка повреждена.
+fn parse_line(line: &str) -> Option<RecordedLine<'_>> {
+    let mut parts = line.splitn(3, '\t');
+    let (seq, arrival_ms, data) = (parts.next()?, parts.next()?, parts.next()?);
+    let arrival_ms: u64 = arrival_ms.parse().ok()?;
+    Some(RecordedLine {
+        seq: match seq {
+            "-" => None,
+            seq => Some(seq.parse().ok()?),
+        },
+        arrival_ms,
+        received: jiff::Timestamp::from_millisecond(i64::try_from(arrival_ms).ok()?).ok()?,
+        data,
+    })
+}
+
+/// Учет номеров сообщений от сервера: потерянные и опоздавшие сообщения.
+#[derive(Debug, Default, PartialEq)]
+struct SequenceStats {
+    /// Наибольший полученный номер.
+    last_seq: u64,
+    lost: u64,
+    reordered: u64,
+}
+
+impl SequenceStats {
+    fn observe(&mut self, seq: u64) {
+        if seq <= self.last_seq {
+            log::warn!("Сообщение {} пришло после {}", seq, self.last_seq);
+            self.reordered += 1;
+            // Опоздавшее сообщение было учтено как потерянное
+            self.lost = self.lost.saturating_sub(1);
+        } else {
+            if seq > self.last_seq + 1 {
+                log::warn!("Пропуск в потоке: после {} идет {}", self.last_seq, seq);
+            }
+            self.lost += seq - self.last_seq - 1;
+            self.last_seq = seq;
+        }
+    }
+}
+
+/// Воспроизведение записанной сессии с исходными интервалами, ускоренными в `speed` раз.
+/// При `speed` равном 0 записи выводятся без задержек.
+pub(crate) fn replay(
+    path: &str,
+    speed: f64,
+    output: &mut QuoteOutput,
+    running: Arc<AtomicBool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let started = Instant::now();
+    let mut first_ms = None;
+    let mut played = 0;
+    let mut sequence = SequenceStats::default();
+
+    for line in reader.lines() {
+        if !running.load(Ordering::SeqCst) {
+            break;
+        }
+        let line = line?;
+        let Some(RecordedLine {
+            seq,
+            arrival_ms,
+            received,
+            data,
+        }) = parse_line(&line)
+        else {
+            log::error!("Некорректная строка записи: {}", line);
+            continue;
+        };
+        played += 1;
+        if let Some(seq) = seq {
+            sequence.observe(seq);
+        }
+
+        let first_ms = *first_ms.get_or_insert(arrival_ms);
+        if speed > 0.0 {
+            let elapsed = Duration::from_millis(arrival_ms.saturating_sub(first_ms));
+            let offset =
+                Duration::try_from_secs_f64(elapsed.as_secs_f64() / speed).unwrap_or(Duration::MAX);
+            thread::sleep(offset.saturating_sub(started.elapsed()));
+        }
+
+        match Message::decode(data.as_bytes()) {
+            Ok(message) => output.write_message(&message, received),
+            Err(_) => log::debug!("Служебное сообщение: {}", data),
+        }
+    }
+    log::info!(
+        "Воспроизведено сообщений: {}, потеряно: {}, не по порядку: {}",
+        played,
+        sequence.lost,
+        sequence.reordered
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use jiff::tz::TimeZone;
+
+    use super::*;
+    use crate::output::OutputFormat;
+
+    const QUOTE: &str = "v2|AAPL|150.25|100|1700000000.250|150.24|150.26|300|400";
+
+    #[test]
+    fn speed_bounds() {
+        assert_eq!(parse_speed("0"), Ok(0.0));
+        assert_eq!(parse_speed("2.5"), Ok(2.5));
+        assert_eq!(parse_speed("0.01"), Ok(MIN_SPEED));
+        for value in ["0.001", "-1", "NaN", "inf", "fast"] {
+            assert!(parse_speed(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn parse_lines() {
+        let line = parse_line("7\t1700000000750\tv2|AAPL").unwrap();
+        assert_eq!(line.seq, Some(7));
+        assert_eq!(line.arrival_ms, 1_700_000_000_750);
+        assert_eq!(line.received.as_millisecond(), 1_700_000_000_750);
+        assert_eq!(line.data, "v2|AAPL");
+        assert_eq!(parse_line("-\t1\tHEARTBEAT").unwrap().seq, None);
+        // Сообщение может содержать табуляцию
+        assert_eq!(parse_line("-\t1\ta\tb").unwrap().data, "a\tb");
+
+        for bad in [
+            "",
+            "7\t1700000000750",
+            "x\t1700000000750\tHEARTBEAT",
+            "-7\t1700000000750\tHEARTBEAT",
+            "7\tsoon\tHEARTBEAT",
+            "7\t18446744073709551615\tHEARTBEAT",
+        ] {
+            assert!(parse_line(bad).is_none(), "{:?}", bad);
+        }
+    }
+
+    fn stats(seqs: &[u64]) -> SequenceStats {
+        let mut stats = SequenceStats::default();
+        for &seq in seqs {
+            stats.observe(seq);
+        }
+        stats
+    }
+
+    #[test]
+    fn lost_and_reordered() {
+        let counts = |seqs| {
+            let SequenceStats {
+                lost, reordered, ..
+            } = stats(seqs);
+            (lost, reordered)
+        };
+        assert_eq!(counts(&[1, 2, 3]), (0, 0));
+        assert_eq!(counts(&[1, 2, 5]), (2, 0));
+        // Переставленное сообщение не считается потерянным
+        assert_eq!(counts(&[1, 3, 2, 4]), (0, 1));
+        assert_eq!(counts(&[1, 4, 2, 5]), (1, 1));
+        // Начало потока после подключения тоже видно как пропуск
+        assert_eq!(counts(&[3, 4]), (2, 0));
+        assert_eq!(counts(&[1, 1]), (0, 1));
+    }
+
+    #[test]
+    fn replay_skips_corrupt_lines() {
+        let dir = std::env::temp_dir();
+        let record = dir.join(format!("recorder-test-{}.log", std::process::id()));
+        let csv = dir.join(format!("recorder-test-{}.csv", std::process::id()));
+        let lines = [
+            format!("1\t1700000000500\t{}", QUOTE),
+            "2\tbroken\tHEARTBEAT".to_string(),
+            "3\t1700000000600".to_string(),
+            "x\t1700000000700\tHEARTBEAT".to_string(),
+            format!("5\t1700000000800\t{}", QUOTE),
+        ];
+        std::fs::write(&record, lines.join("\n")).unwrap();
+
+        let mut output = QuoteOutput::new(
+            OutputFormat::Csv,
+            Some(csv.to_str().unwrap()),
+            TimeZone::UTC,
+            "%H:%M:%S".to_string(),
+        )
+        .unwrap();
+        let result = replay(
+            record.to_str().unwrap(),
+            0.0,
+            &mut output,
+            Arc::new(AtomicBool::new(true)),
+        );
+        drop(output);
+        let written = std::fs::read_to_string(&csv).unwrap();
+        let _ = std::fs::remove_file(&record);
+        let _ = std::fs::remove_file(&csv);
+
+        result.unwrap();
+        let received: Vec<&str> = written
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').nth(9).unwrap())
+            .collect();
+        assert_eq!(
+            received,
+            ["2023-11-14T22:13:20.5Z", "2023-11-14T22:13:20.8Z"]
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! Сравнение рассылки сообщения многим клиентам: кодирование для каждого клиента
+//! против одного общего буфера [`SharedMessage`] на формат.
+//!
+//! `cargo bench -p quote_lib --bench fanout`
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use quote_lib::{Message, Price, QuoteFormat, SharedMessage, StockQuote, Timestamp, Volume};
+
+/// Такты генератора в каждом замере.
+const ROUNDS: usize = 20;
+
+fn quotes(tickers: usize) -> Vec<Message> {
+    (0..tickers)
+        .map(|i| {
+            let price = Price::from_f64(100.0 + i as f64 * 1.37);
+            Message::Quote(StockQuote {
+                ticker: format!("T{:04}", i),
+                price,
+                volume: Volume::new(100 + i as u64),
+                timestamp: Timestamp::now(),
+                bid: price - Price::from_f64(0.01),
+                ask: price + Price::from_f64(0.01),
+                bid_size: Volume::new(500),
+                ask_size: Volume::new(700),
+            })
+        })
+        .collect()
+}
+
+/// Каждый клиент получает копию сообщения и кодирует ее сам.
+fn per_client(messages: &[Message], clients: &[QuoteFormat]) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for message in messages {
+            for &format in clients {
+                let copy = black_box(message.clone());
+                black_box(copy.encode(format));
+            }
+        }
+    }
+    start.elapsed()
+}
+
+/// Сообщение кодируется один раз на формат, клиенты разделяют буфер.
+fn shared(messages: &[Message], clients: &[QuoteFormat]) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for message in messages {
+            let shared = SharedMessage::new(message.clone());
+            for &format in clients {
+                let copy = black_box(shared.clone());
+                black_box(copy.encoded(format));
+            }
+        }
+    }
+    start.elapsed()
+}
+
+fn main() {
+    println!(
+        "{:>8} {:>8} {:>14} {:>14} {:>8}",
+        "клиенты", "тикеры", "по клиенту", "общий буфер", "выигрыш"
+    );
+    for tickers in [100, 1000] {
+        let messages = quotes(tickers);
+        for clients in [1, 10, 100, 1000] {
+            // Большинство клиентов получает v2, часть - двоичный формат по UDP
+            let formats: Vec<QuoteFormat> = (0..clients)
+                .map(|i| {
+                    if i % 4 == 3 {
+                        QuoteFormat::Binary
+                    } else {
+                        QuoteFormat::TextV2
+                    }
+                })
+                .collect();
+            let old = per_client(&messages, &formats);
+            let new = shared(&messages, &formats);
+            println!(
+                "{:>8} {:>8} {:>12.1}мс {:>12.1}мс {:>7.1}x",
+                clients,
+                tickers,
+                old.as_secs_f64() * 1000.0,
+                new.as_secs_f64() * 1000.0,
+                old.as_secs_f64() / new.as_secs_f64()
+            );
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! Свечи (OHLCV) по интервалам времени.
+
+use crate::{Price, Timestamp, Volume};
+
+/// Интервал свечи: `1s`, `1m`, `5m`, `1h`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BarInterval(u64);
+
+impl BarInterval {
+    /// Интервал из миллисекунд, нулевой интервал не допускается.
+    pub fn from_millis(millis: u64) -> Option<Self> {
+        (millis > 0).then_some(Self(millis))
+    }
+
+    /// Длительность интервала в миллисекундах.
+    pub fn as_millis(&self) -> u64 {
+        self.0
+    }
+
+    /// Начало интервала, в который попадает время.
+    pub fn start_of(&self, timestamp: Timestamp) -> Timestamp {
+        let millis = timestamp.as_millis();
+        Timestamp::from_millis(millis - millis % self.0)
+    }
+}
+
+impl std::fmt::Display for BarInterval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            ms if ms % 3_600_000 == 0 => write!(f, "{}h", ms / 3_600_000),
+            ms if ms % 60_000 == 0 => write!(f, "{}m", ms / 60_000),
+            ms if ms % 1000 == 0 => write!(f, "{}s", ms / 1000),
+            ms => write!(f, "{}ms", ms),
+        }
+    }
+}
+
+impl std::str::FromStr for BarInterval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (count, unit) = s.split_at(split);
+        let count: u64 = count
+            .parse()
+            .map_err(|_| format!("Некорректный интервал свечи: {}", s))?;
+        let unit = match unit {
+            "ms" => 1,
+            "s" => 1000,
+            "m" => 60_000,
+            "h" => 3_600_000,
+            _ => return Err(format!("Неизвестная единица интервала свечи: {}", s)),
+        };
+        count
+            .checked_mul(unit)
+            .and_then(Self::from_millis)
+            .ok_or_else(|| format!("Некорректный интервал свечи: {}", s))
+    }
+}
+
+impl serde::Serialize for BarInterval {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for BarInterval {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Свеча: цены открытия, максимума, минимума и закрытия и объем сделок за интервал.
+/// Повторная свеча с тем же началом заменяет предыдущую (поправка по опоздавшей сделке).
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Bar {
+    /// Тикер акции.
+    pub ticker: String,
+    /// Интервал свечи.
+    pub interval: BarInterval,
+    /// Начало интервала.
+    pub start: Timestamp,
+    /// Цена первой сделки.
+    pub open: Price,
+    /// Максимальная цена.
+    pub high: Price,
+    /// Минимальная цена.
+    pub low: Price,
+    /// Цена последней сделки.
+    pub close: Price,
+    /// Суммарный объем сделок.
+    pub volume: Volume,
+}
@@ -0,0 +1,188 @@
+//! Стакан заявок (L2): уровни цен, снимки и инкрементальные обновления.
+
+use std::collections::BTreeMap;
+
+use crate::{Price, Timestamp, Volume};
+
+/// Сторона стакана.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    /// Заявки на покупку.
+    Bid,
+    /// Заявки на продажу.
+    Ask,
+}
+
+/// Уровень стакана: цена и суммарный объем заявок на ней.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BookLevel {
+    /// Цена уровня.
+    pub price: Price,
+    /// Объем на уровне.
+    pub size: Volume,
+}
+
+/// Изменение уровня стакана, нулевой объем - уровень удален.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct LevelChange {
+    /// Сторона стакана.
+    pub side: Side,
+    /// Цена уровня.
+    pub price: Price,
+    /// Новый объем на уровне.
+    pub size: Volume,
+}
+
+/// Полный снимок стакана: покупки от лучшей цены вниз, продажи от лучшей цены вверх.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BookSnapshot {
+    /// Тикер акции.
+    pub ticker: String,
+    /// Номер последнего изменения, вошедшего в снимок.
+    pub seq: u64,
+    /// Время снимка.
+    pub timestamp: Timestamp,
+    /// Уровни покупки.
+    pub bids: Vec<BookLevel>,
+    /// Уровни продажи.
+    pub asks: Vec<BookLevel>,
+}
+
+/// Инкрементальное обновление стакана, номера идут подряд после снимка.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BookUpdate {
+    /// Тикер акции.
+    pub ticker: String,
+    /// Номер изменения.
+    pub seq: u64,
+    /// Время изменения.
+    pub timestamp: Timestamp,
+    /// Изменившиеся уровни.
+    pub changes: Vec<LevelChange>,
+}
+
+/// Результат применения обновления к стакану.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookApply {
+    /// Обновление применено.
+    Applied,
+    /// Обновление уже вошло в стакан и пропущено.
+    Stale,
+    /// Пропущены обновления, стакан ждет нового снимка.
+    Gap,
+}
+
+/// Стакан по одному тикеру, собираемый из снимков и обновлений.
+#[derive(Debug, Clone, Default)]
+pub struct OrderBook {
+    bids: BTreeMap<Price, Volume>,
+    asks: BTreeMap<Price, Volume>,
+    seq: u64,
+    /// Стакан получен из снимка и не имеет пропусков.
+    synced: bool,
+}
+
+impl OrderBook {
+    /// Пустой стакан, ожидающий снимка.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Номер последнего примененного изменения.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Согласован ли стакан: был снимок и после него нет пропусков.
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// Уровни покупки от лучшей цены.
+    pub fn bids(&self) -> impl Iterator<Item = BookLevel> + '_ {
+        self.bids.iter().rev().map(|(price, size)| BookLevel {
+            price: *price,
+            size: *size,
+        })
+    }
+
+    /// Уровни продажи от лучшей цены.
+    pub fn asks(&self) -> impl Iterator<Item = BookLevel> + '_ {
+        self.asks.iter().map(|(price, size)| BookLevel {
+            price: *price,
+            size: *size,
+        })
+    }
+
+    /// Объем на уровне, `None` - уровня нет.
+    pub fn level(&self, side: Side, price: Price) -> Option<Volume> {
+        self.side(side).get(&price).copied()
+    }
+
+    /// Снимок стакана с `depth` уровнями с каждой стороны.
+    pub fn snapshot(&self, ticker: &str, depth: usize, timestamp: Timestamp) -> BookSnapshot {
+        BookSnapshot {
+            ticker: ticker.to_string(),
+            seq: self.seq,
+            timestamp,
+            bids: self.bids().take(depth).collect(),
+            asks: self.asks().take(depth).collect(),
+        }
+    }
+
+    /// Замена содержимого стакана снимком.
+    pub fn apply_snapshot(&mut self, snapshot: &BookSnapshot) {
+        self.bids = snapshot.bids.iter().map(|l| (l.price, l.size)).collect();
+        self.asks = snapshot.asks.iter().map(|l| (l.price, l.size)).collect();
+        self.seq = snapshot.seq;
+        self.synced = true;
+    }
+
+    /// Применение обновления, номер должен идти сразу за текущим.
+    pub fn apply_update(&mut self, update: &BookUpdate) -> BookApply {
+        if self.synced && update.seq <= self.seq {
+            return BookApply::Stale;
+        }
+        if !self.synced || update.seq != self.seq + 1 {
+            self.synced = false;
+            return BookApply::Gap;
+        }
+        for change in &update.changes {
+            self.set_level(change.side, change.price, change.size);
+        }
+        self.seq = update.seq;
+        BookApply::Applied
+    }
+
+    /// Установка объема уровня без проверки номеров, нулевой объем удаляет уровень.
+    pub fn set_level(&mut self, side: Side, price: Price, size: Volume) {
+        let levels = self.side_mut(side);
+        if size == Volume::ZERO {
+            levels.remove(&price);
+        } else {
+            levels.insert(price, size);
+        }
+    }
+
+    /// Следующий номер изменения для источника стакана.
+    pub fn next_seq(&mut self) -> u64 {
+        self.seq += 1;
+        self.synced = true;
+        self.seq
+    }
+
+    fn side(&self, side: Side) -> &BTreeMap<Price, Volume> {
+        match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        }
+    }
+
+    fn side_mut(&mut self, side: Side) -> &mut BTreeMap<Price, Volume> {
+        match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        }
+    }
+}
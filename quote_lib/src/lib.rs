@@ -3,44 +3,143 @@
 
 //! Клиент-серверная библиотека для обмена сообщениями о котировках акций.
 
-/// Котировка акции.
-#[derive(Debug, Clone)]
+mod bar;
+mod book;
+mod message;
+mod shared;
+
+pub use bar::{Bar, BarInterval};
+pub use book::{BookApply, BookLevel, BookSnapshot, BookUpdate, LevelChange, OrderBook, Side};
+pub use message::{Message, MessageKind, Trade, is_binary, sequenced, split_sequence};
+pub use shared::SharedMessage;
+
+/// Котировка акции (level-1): лучшие цены покупки и продажи и последняя сделка.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct StockQuote {
     /// Тикер акции.
     pub ticker: String,
-    /// Цена акции.
-    pub price: f64,
-    /// Объем торгов.
-    pub volume: f64,
+    /// Цена последней сделки.
+    pub price: Price,
+    /// Объем последней сделки.
+    pub volume: Volume,
     /// Время торгов.
-    pub timestamp: u64,
+    pub timestamp: Timestamp,
+    /// Лучшая цена покупки.
+    pub bid: Price,
+    /// Лучшая цена продажи.
+    pub ask: Price,
+    /// Объем на лучшей цене покупки.
+    pub bid_size: Volume,
+    /// Объем на лучшей цене продажи.
+    pub ask_size: Volume,
 }
 
 impl StockQuote {
-    /// Создание котировки из строки.
+    /// Создание котировки из строки в текстовом формате первой или второй версии.
+    /// В первой версии нет bid/ask: они равны цене последней сделки, объемы нулевые.
     pub fn from_string(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
         let l: Vec<&str> = s.split("|").collect();
+        if l.first() == Some(&message::TEXT_V2_MARKER) {
+            if l.len() != 9 {
+                return Err(format!("Expected 9 fields, got {}: {:?}", l.len(), l).into());
+            }
+            return Ok(StockQuote {
+                ticker: l[1].to_string(),
+                price: l[2].parse()?,
+                volume: l[3].parse()?,
+                timestamp: l[4].parse()?,
+                bid: l[5].parse()?,
+                ask: l[6].parse()?,
+                bid_size: l[7].parse()?,
+                ask_size: l[8].parse()?,
+            });
+        }
         if l.len() != 4 {
             return Err(format!("Expected 4 fields, got {}: {:?}", l.len(), l).into());
         }
+        let price: Price = l[1].parse()?;
         Ok(StockQuote {
             ticker: l[0].to_string(),
-            price: l[1].parse()?,
+            price,
             volume: l[2].parse()?,
             timestamp: l[3].parse()?,
+            bid: price,
+            ask: price,
+            bid_size: Volume::ZERO,
+            ask_size: Volume::ZERO,
         })
     }
+
+    /// Разница между ценой продажи и покупки.
+    pub fn spread(&self) -> Price {
+        self.ask - self.bid
+    }
 }
 
+/// Текст первой версии `AAPL|цена|объем|время` со временем в целых секундах,
+/// понятный старым клиентам.
 impl std::fmt::Display for StockQuote {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
             "{}|{}|{}|{}",
-            self.ticker, self.price, self.volume, self.timestamp
+            self.ticker,
+            self.price,
+            self.volume,
+            self.timestamp.as_secs()
         )
     }
 }
+
+/// Формат сообщений на проводе, выбирается клиентом опцией `format=` команд.
+/// Клиенты без опции получают первую версию текста.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuoteFormat {
+    /// `v1`: `AAPL|цена|объем|время`, без bid/ask, и статус `v2s|текст`.
+    #[default]
+    TextV1,
+    /// `v2`: котировка `v2|AAPL|цена|объем|время|bid|ask|объем bid|объем ask`,
+    /// сделка `v2t|AAPL|цена|объем|время`, статус `v2s|текст`,
+    /// снимок стакана `v2b|AAPL|номер|время|цена:объем,...|цена:объем,...`,
+    /// обновление стакана `v2u|AAPL|номер|время|B:цена:объем,A:цена:объем`,
+    /// свеча `v2c|AAPL|1m|начало|open|high|low|close|объем`.
+    TextV2,
+    /// `bin`: маркер `0`, версия, тип сообщения, длина и байты тикера,
+    /// затем поля по 8 байт little-endian.
+    /// Только для UDP и multicast, где сообщение занимает целую датаграмму.
+    Binary,
+}
+
+impl QuoteFormat {
+    /// Текстовый ли формат, только такие можно передавать построчно.
+    pub fn is_text(&self) -> bool {
+        !matches!(self, QuoteFormat::Binary)
+    }
+}
+
+impl std::fmt::Display for QuoteFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            QuoteFormat::TextV1 => "v1",
+            QuoteFormat::TextV2 => "v2",
+            QuoteFormat::Binary => "bin",
+        })
+    }
+}
+
+impl std::str::FromStr for QuoteFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v1" => Ok(QuoteFormat::TextV1),
+            "v2" => Ok(QuoteFormat::TextV2),
+            "bin" => Ok(QuoteFormat::Binary),
+            _ => Err(format!("Неизвестный формат котировок: {}", s)),
+        }
+    }
+}
+
 /// Команда клиента для мониторинга.
 pub const PING_MSG: &[u8] = b"PING";
 /// Команда сервера для подтверждения.
@@ -49,11 +148,558 @@ pub const PONG_MSG: &[u8] = b"PONG";
 pub const STREAM_CMD: &str = "STREAM";
 /// Ответ сервера.
 pub const SERVER_OK: &str = "OK";
+/// Команда клиента для получения последних котировок: `SNAPSHOT AAPL,MSFT`.
+pub const SNAPSHOT_CMD: &str = "SNAPSHOT";
+/// Команда клиента для истории котировок: `HISTORY AAPL 100` или `HISTORY AAPL since=<время>`.
+pub const HISTORY_CMD: &str = "HISTORY";
+/// Команда управления воспроизведением журнала: `REPLAY PAUSE|RESUME|SPEED <x>|SEEK <мс>|STATUS`.
+pub const REPLAY_CMD: &str = "REPLAY";
+/// Команда клиента для потока стакана по управляющему TCP соединению: `DEPTH AAPL,MSFT`.
+pub const DEPTH_CMD: &str = "DEPTH";
+/// Учебная заявка: `ORDER BUY AAPL 100` (рыночная) или `ORDER SELL AAPL 100 150.25` (лимитная),
+/// ответ `OK <номер заявки>`, исполнение приходит строками `EXEC`.
+pub const ORDER_CMD: &str = "ORDER";
+/// Отмена ожидающей заявки: `CANCEL <номер заявки>`.
+pub const CANCEL_CMD: &str = "CANCEL";
+/// Позиции и результат сессии, многострочный ответ.
+pub const POSITIONS_CMD: &str = "POSITIONS";
+/// Отчет об исполнении в управляющем соединении:
+/// `EXEC <номер> NEW|FILL|CANCELED <тикер> <BUY|SELL> ...`.
+pub const EXEC_MSG: &str = "EXEC";
+/// Свечи: `BARS 1m AAPL,MSFT` - подписка по управляющему TCP соединению,
+/// `BARS 1m AAPL 100` или `BARS 1m AAPL since=<время>` - история.
+pub const BARS_CMD: &str = "BARS";
+/// Уведомление о цене: `ALERT AAPL above 200`, `ALERT AAPL below 150 repeat`,
+/// `ALERT AAPL pct-move 2.5` (процент от последней цены), ответ `OK <номер>`;
+/// `ALERT DEL <номер>` - удаление. Срабатывание приходит в управляющее соединение строкой
+/// `ALERT <номер> TRIGGERED <тикер> <условие> <цена> <время>`.
+pub const ALERT_CMD: &str = "ALERT";
+/// Действующие уведомления о цене, многострочный ответ.
+pub const ALERTS_CMD: &str = "ALERTS";
+/// Команда административного порта: состояние сервера, многострочный ответ `<параметр> <значение>`.
+pub const STATS_CMD: &str = "STATS";
+/// Команда административного порта: потоки клиентов, многострочный ответ по строке на поток.
+pub const CLIENTS_CMD: &str = "CLIENTS";
+/// Команда административного порта: принудительная остановка потока `KICK <номер клиента>`.
+pub const KICK_CMD: &str = "KICK";
+/// Завершение многострочного ответа сервера.
+pub const END_MSG: &str = "END";
+/// Транспорт потока котировок через multicast: `STREAM multicast AAPL,MSFT`.
+pub const MULTICAST_TRANSPORT: &str = "multicast";
+/// Транспорт потока котировок по управляющему TCP соединению: `STREAM tcp AAPL,MSFT`.
+pub const TCP_TRANSPORT: &str = "tcp";
+/// Опция команд `STREAM`, `SNAPSHOT` и `HISTORY` с форматом котировок: `format=v2`.
+pub const FORMAT_OPTION: &str = "format=";
+/// Опция команды `STREAM` с типами сообщений: `types=quotes,trades,depth,bars`, по умолчанию только котировки.
+pub const TYPES_OPTION: &str = "types=";
+/// Опция команды `STREAM`: минимальное изменение цены котировки, `min-change=0.05`
+/// или `min-change=0.1%`, для одного тикера - `min-change:AAPL=0.5`.
+pub const MIN_CHANGE_OPTION: &str = "min-change";
+/// Опция команды `STREAM`: не больше котировок тикера в секунду, `max-rate=5`.
+pub const MAX_RATE_OPTION: &str = "max-rate";
+/// Опция команды `STREAM`: только последняя котировка тикера за интервал, `conflate=250ms`.
+pub const CONFLATE_OPTION: &str = "conflate";
+/// Опция команды `STREAM` для UDP: каждое сообщение приходит с номером в потоке клиента,
+/// `seq|<номер>|<сообщение>` или двоичный кадр с номером, см. [`sequenced`].
+pub const SEQ_OPTION: &str = "seq";
+/// Сообщение сервера о активности TCP потока при отсутствии котировок.
+pub const HEARTBEAT_MSG: &str = "HEARTBEAT";
+/// Ответ сервера со списком multicast групп: `OK MULTICAST 239.255.0.1:30001=AAPL,MSFT`.
+pub const MULTICAST_REPLY: &str = "MULTICAST";
+/// Наибольший размер UDP датаграммы с сообщением, буфер приема должен быть не меньше.
+pub const MAX_DATAGRAM_SIZE: usize = 65_507;
+/// Наибольшее число уровней стакана с каждой стороны: в двоичном формате число уровней
+/// снимка и изменений обновления занимает байт, снимок помещается в одну датаграмму.
+pub const MAX_BOOK_DEPTH: usize = 100;
 
-/// Получение текущего времени в секундах.
-pub fn get_timestamp() -> u64 {
-    std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .expect("Error get_timestamp")
-        .as_secs()
+/// Время с точностью до миллисекунд от начала эпохи Unix.
+///
+/// В тексте записывается секундами с дробной частью (`1700000000.250`), кроме первой версии
+/// текста котировок - там целые секунды; при разборе принимаются оба вида.
+/// В JSON записывается числом миллисекунд.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(transparent)]
+pub struct Timestamp(u64);
+
+impl Timestamp {
+    /// Текущее время.
+    pub fn now() -> Self {
+        let millis = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("Error get_timestamp")
+            .as_millis();
+        Self(millis as u64)
+    }
+
+    /// Время из миллисекунд.
+    pub fn from_millis(millis: u64) -> Self {
+        Self(millis)
+    }
+
+    /// Время из целых секунд.
+    pub fn from_secs(secs: u64) -> Self {
+        Self(secs.saturating_mul(1000))
+    }
+
+    /// Миллисекунды от начала эпохи.
+    pub fn as_millis(&self) -> u64 {
+        self.0
+    }
+
+    /// Целые секунды от начала эпохи.
+    pub fn as_secs(&self) -> u64 {
+        self.0 / 1000
+    }
+}
+
+impl std::fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{:03}", self.0 / 1000, self.0 % 1000)
+    }
+}
+
+impl std::str::FromStr for Timestamp {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (secs, fraction) = s.split_once('.').unwrap_or((s, ""));
+        let secs: u64 = secs.parse()?;
+        if !fraction.chars().all(|c| c.is_ascii_digit()) {
+            return Err(format!("Некорректное время: {}", s).into());
+        }
+        // Дробная часть точнее миллисекунд отбрасывается
+        let millis: u64 = format!("{:0<3}", &fraction[..fraction.len().min(3)]).parse()?;
+        secs.checked_mul(1000)
+            .and_then(|ms| ms.checked_add(millis))
+            .map(Self)
+            .ok_or_else(|| format!("Некорректное время: {}", s).into())
+    }
+}
+
+/// Получение текущего времени с точностью до миллисекунд.
+pub fn get_timestamp() -> Timestamp {
+    Timestamp::now()
+}
+
+/// Число знаков после запятой в [`Price`].
+pub const PRICE_DECIMALS: u32 = 4;
+const PRICE_SCALE: i64 = 10_i64.pow(PRICE_DECIMALS);
+
+/// Цена с фиксированной точкой: целое число десятитысячных долей.
+///
+/// В тексте записывается десятичной дробью без лишних нулей (`123.45`, `0.0001`),
+/// при разборе старые значения `f64` вида `123.45000000000002` округляются до
+/// [`PRICE_DECIMALS`] знаков. В JSON записывается числом.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Price(i64);
+
+impl Price {
+    /// Нулевая цена.
+    pub const ZERO: Price = Price(0);
+    /// Шаг цены по умолчанию - один цент.
+    pub const DEFAULT_TICK: Price = Price(PRICE_SCALE / 100);
+
+    /// Цена из числа минимальных долей (`10^-PRICE_DECIMALS`).
+    pub fn from_units(units: i64) -> Self {
+        Self(units)
+    }
+
+    /// Число минимальных долей цены.
+    pub fn units(&self) -> i64 {
+        self.0
+    }
+
+    /// Цена из `f64` с округлением до [`PRICE_DECIMALS`] знаков.
+    pub fn from_f64(value: f64) -> Self {
+        Self((value * PRICE_SCALE as f64).round() as i64)
+    }
+
+    /// Приближенное значение цены для вычислений в `f64`.
+    pub fn to_f64(&self) -> f64 {
+        self.0 as f64 / PRICE_SCALE as f64
+    }
+
+    /// Округление до ближайшего кратного шагу цены `tick`, половина - вверх.
+    /// Неположительный шаг оставляет цену без изменений.
+    pub fn round_to_tick(self, tick: Price) -> Self {
+        if tick.0 <= 0 {
+            return self;
+        }
+        let rest = self.0.rem_euclid(tick.0);
+        let down = self.0 - rest;
+        if rest * 2 >= tick.0 {
+            Self(down.saturating_add(tick.0))
+        } else {
+            Self(down)
+        }
+    }
+
+    /// Кратна ли цена шагу `tick`.
+    pub fn is_on_tick(&self, tick: Price) -> bool {
+        tick.0 > 0 && self.0 % tick.0 == 0
+    }
+
+    /// Сложение с проверкой переполнения.
+    pub fn checked_add(self, other: Price) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    /// Вычитание с проверкой переполнения.
+    pub fn checked_sub(self, other: Price) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Self)
+    }
+
+    /// Умножение на целое число (например, на число шагов цены) с проверкой переполнения.
+    pub fn checked_mul(self, factor: i64) -> Option<Self> {
+        self.0.checked_mul(factor).map(Self)
+    }
+
+    /// Стоимость `volume` единиц по этой цене.
+    pub fn notional(&self, volume: Volume) -> f64 {
+        self.to_f64() * volume.0 as f64
+    }
+}
+
+impl std::ops::Add for Price {
+    type Output = Price;
+
+    fn add(self, other: Price) -> Price {
+        Price(self.0 + other.0)
+    }
+}
+
+impl std::ops::Sub for Price {
+    type Output = Price;
+
+    fn sub(self, other: Price) -> Price {
+        Price(self.0 - other.0)
+    }
+}
+
+impl std::ops::Neg for Price {
+    type Output = Price;
+
+    fn neg(self) -> Price {
+        Price(-self.0)
+    }
+}
+
+impl std::ops::AddAssign for Price {
+    fn add_assign(&mut self, other: Price) {
+        self.0 += other.0;
+    }
+}
+
+impl std::ops::SubAssign for Price {
+    fn sub_assign(&mut self, other: Price) {
+        self.0 -= other.0;
+    }
+}
+
+impl std::fmt::Display for Price {
+    /// Без точности - все значащие знаки, но не меньше двух (`820.40`),
+    /// с точностью (`{:.2}`) - округление до указанного числа знаков.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let scale = PRICE_SCALE as u64;
+        let decimals = PRICE_DECIMALS as usize;
+        match f.precision() {
+            Some(0) => write!(f, "{}{}", sign, (abs + scale / 2) / scale),
+            Some(p) if p < decimals => {
+                let div = 10_u64.pow((decimals - p) as u32);
+                let rounded = (abs + div / 2) / div;
+                let frac_scale = 10_u64.pow(p as u32);
+                write!(
+                    f,
+                    "{}{}.{:0p$}",
+                    sign,
+                    rounded / frac_scale,
+                    rounded % frac_scale
+                )
+            }
+            Some(p) => write!(
+                f,
+                "{}{}.{:0decimals$}{:0<zeros$}",
+                sign,
+                abs / scale,
+                abs % scale,
+                "",
+                zeros = p - decimals
+            ),
+            None => {
+                let frac = format!("{:0decimals$}", abs % scale);
+                let frac = frac.trim_end_matches('0');
+                write!(f, "{}{}.{:0<2}", sign, abs / scale, frac)
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Price {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Некорректная цена: {}", s);
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+        if (int.is_empty() && frac.is_empty())
+            || !int.chars().all(|c| c.is_ascii_digit())
+            || !frac.chars().all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid().into());
+        }
+        let int: i64 = if int.is_empty() { 0 } else { int.parse()? };
+        let decimals = PRICE_DECIMALS as usize;
+        let kept: i64 = format!("{:0<decimals$}", &frac[..frac.len().min(decimals)]).parse()?;
+        // Округление по первой отброшенной цифре
+        let round_up = frac.as_bytes().get(decimals).is_some_and(|d| *d >= b'5');
+        let units = int
+            .checked_mul(PRICE_SCALE)
+            .and_then(|u| u.checked_add(kept))
+            .and_then(|u| u.checked_add(round_up as i64))
+            .ok_or_else(invalid)?;
+        Ok(Self(if negative { -units } else { units }))
+    }
+}
+
+impl serde::Serialize for Price {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.to_f64())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Price {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        f64::deserialize(deserializer).map(Price::from_f64)
+    }
+}
+
+/// Объем торгов в целых единицах (акциях).
+///
+/// При разборе дробные значения старого формата округляются до целого.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(transparent)]
+pub struct Volume(u64);
+
+impl Volume {
+    /// Нулевой объем.
+    pub const ZERO: Volume = Volume(0);
+
+    /// Объем из числа единиц.
+    pub fn new(units: u64) -> Self {
+        Self(units)
+    }
+
+    /// Число единиц.
+    pub fn units(&self) -> u64 {
+        self.0
+    }
+
+    /// Сложение с насыщением.
+    pub fn saturating_add(self, other: Volume) -> Self {
+        Self(self.0.saturating_add(other.0))
+    }
+
+    /// Вычитание с насыщением до нуля.
+    pub fn saturating_sub(self, other: Volume) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+}
+
+impl std::ops::Add for Volume {
+    type Output = Volume;
+
+    fn add(self, other: Volume) -> Volume {
+        Volume(self.0 + other.0)
+    }
+}
+
+impl std::ops::AddAssign for Volume {
+    fn add_assign(&mut self, other: Volume) {
+        self.0 += other.0;
+    }
+}
+
+impl std::iter::Sum for Volume {
+    fn sum<I: Iterator<Item = Volume>>(iter: I) -> Volume {
+        iter.fold(Volume::ZERO, |acc, v| acc + v)
+    }
+}
+
+impl std::fmt::Display for Volume {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::str::FromStr for Volume {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(units) = s.parse() {
+            return Ok(Self(units));
+        }
+        let value: f64 = s.parse()?;
+        if !value.is_finite() || value < 0.0 {
+            return Err(format!("Некорректный объем: {}", s).into());
+        }
+        Ok(Self(value.round() as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote() -> StockQuote {
+        StockQuote {
+            ticker: "AAPL".to_string(),
+            price: Price::from_units(1_502_500),
+            volume: Volume::new(1200),
+            timestamp: Timestamp::from_millis(1_700_000_000_250),
+            bid: Price::from_units(1_502_400),
+            ask: Price::from_units(1_502_600),
+            bid_size: Volume::new(300),
+            ask_size: Volume::new(500),
+        }
+    }
+
+    #[test]
+    fn timestamp_parses_whole_and_fractional_seconds() {
+        assert_eq!(
+            "1700000000".parse::<Timestamp>().unwrap(),
+            Timestamp::from_secs(1_700_000_000)
+        );
+        assert_eq!(
+            "1700000000.250".parse::<Timestamp>().unwrap(),
+            Timestamp::from_millis(1_700_000_000_250)
+        );
+        assert_eq!(
+            "1700000000.2".parse::<Timestamp>().unwrap(),
+            Timestamp::from_millis(1_700_000_000_200)
+        );
+        assert!("1700000000.2x".parse::<Timestamp>().is_err());
+    }
+
+    #[test]
+    fn timestamp_display_keeps_millis() {
+        let timestamp = Timestamp::from_millis(1_700_000_000_050);
+        assert_eq!(timestamp.to_string(), "1700000000.050");
+        assert_eq!(
+            timestamp.to_string().parse::<Timestamp>().unwrap(),
+            timestamp
+        );
+    }
+
+    #[test]
+    fn text_v1_keeps_whole_seconds() {
+        let text = String::from_utf8(quote().encode(QuoteFormat::TextV1)).unwrap();
+        assert_eq!(text, "AAPL|150.25|1200|1700000000");
+        // Старые клиенты разбирают время как целое число
+        let fields: Vec<&str> = text.split('|').collect();
+        assert_eq!(fields[3].parse::<u64>().unwrap(), 1_700_000_000);
+
+        let decoded = StockQuote::decode(text.as_bytes()).unwrap();
+        assert_eq!(decoded.price, quote().price);
+        assert_eq!(decoded.timestamp, Timestamp::from_secs(1_700_000_000));
+    }
+
+    fn price(s: &str) -> Price {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn price_parse_and_display() {
+        assert_eq!(price("123.45"), Price::from_units(1_234_500));
+        assert_eq!(price("123.45").to_string(), "123.45");
+        assert_eq!(price("820.4").to_string(), "820.40");
+        assert_eq!(price("0.0001").to_string(), "0.0001");
+        assert_eq!(price(".5"), Price::from_units(5_000));
+        assert_eq!(price("7"), Price::from_units(70_000));
+        for text in ["0.01", "150.2575", "99999.9999"] {
+            assert_eq!(
+                price(text).to_string().parse::<Price>().unwrap(),
+                price(text)
+            );
+        }
+    }
+
+    #[test]
+    fn price_sign() {
+        assert_eq!(price("-0.5"), Price::from_units(-5_000));
+        assert_eq!(price("-0.5").to_string(), "-0.50");
+        assert_eq!(price("-12.3456").to_string(), "-12.3456");
+        assert_eq!(-price("1.25"), price("-1.25"));
+        assert!("+1.25".parse::<Price>().is_err());
+        assert!("--1".parse::<Price>().is_err());
+    }
+
+    #[test]
+    fn price_rounds_past_four_decimals() {
+        // Старые значения f64
+        assert_eq!(price("123.45000000000002"), price("123.45"));
+        assert_eq!(price("0.00005"), Price::from_units(1));
+        assert_eq!(price("0.00004999"), Price::ZERO);
+        assert_eq!(price("1.99995"), price("2"));
+        assert_eq!(price("-1.00005"), Price::from_units(-10_001));
+    }
+
+    #[test]
+    fn price_rejects_invalid_and_overflow() {
+        for text in ["", ".", "-", "abc", "1.2.3", "1,5", "1e3", " 1"] {
+            assert!(text.parse::<Price>().is_err(), "{:?}", text);
+        }
+        assert_eq!(
+            price("922337203685477"),
+            Price::from_units(9_223_372_036_854_770_000)
+        );
+        assert!("922337203685478".parse::<Price>().is_err());
+        assert!("922337203685477.5808".parse::<Price>().is_err());
+        assert!("99999999999999999999".parse::<Price>().is_err());
+    }
+
+    #[test]
+    fn price_display_precision() {
+        let value = price("123.4567");
+        assert_eq!(format!("{:.2}", value), "123.46");
+        assert_eq!(format!("{:.0}", value), "123");
+        assert_eq!(format!("{:.4}", value), "123.4567");
+        assert_eq!(format!("{:.6}", value), "123.456700");
+        assert_eq!(format!("{:.2}", -value), "-123.46");
+        assert_eq!(format!("{:.1}", price("0.96")), "1.0");
+    }
+
+    #[test]
+    fn price_tick_size() {
+        let tick = price("0.05");
+        assert_eq!(price("10.03").round_to_tick(tick), price("10.05"));
+        assert_eq!(price("10.02").round_to_tick(tick), price("10.00"));
+        assert_eq!(price("10.025").round_to_tick(tick), price("10.05"));
+        assert_eq!(price("-10.03").round_to_tick(tick), price("-10.05"));
+        assert_eq!(price("10.03").round_to_tick(Price::ZERO), price("10.03"));
+        assert!(price("10.05").is_on_tick(tick));
+        assert!(!price("10.03").is_on_tick(tick));
+        assert!(!price("10.05").is_on_tick(Price::ZERO));
+        assert!(price("10.03").is_on_tick(Price::DEFAULT_TICK));
+        assert_eq!(tick.checked_mul(3), Some(price("0.15")));
+        assert_eq!(Price::from_units(i64::MAX).checked_add(tick), None);
+    }
 }
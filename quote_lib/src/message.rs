@@ -0,0 +1,841 @@
+//! Сообщения потока рыночных данных и их кодирование на проводе.
+
+use crate::{
+    Price, QuoteFormat, StockQuote, Timestamp, Volume,
+    bar::{Bar, BarInterval},
+    book::{BookLevel, BookSnapshot, BookUpdate, LevelChange, Side},
+};
+
+/// Маркер второй версии текстового формата котировки, первое поле строки.
+pub(crate) const TEXT_V2_MARKER: &str = "v2";
+/// Маркер сделки во второй версии текстового формата.
+const TEXT_V2_TRADE_MARKER: &str = "v2t";
+/// Маркер статуса в текстовых форматах: первая версия передает статус той же строкой,
+/// чтобы клиенты без опции формата получали сообщения о пропусках.
+const TEXT_V2_STATUS_MARKER: &str = "v2s";
+/// Маркер снимка стакана во второй версии текстового формата.
+const TEXT_V2_BOOK_SNAPSHOT_MARKER: &str = "v2b";
+/// Маркер обновления стакана во второй версии текстового формата.
+const TEXT_V2_BOOK_UPDATE_MARKER: &str = "v2u";
+/// Маркер свечи во второй версии текстового формата.
+const TEXT_V2_BAR_MARKER: &str = "v2c";
+/// Маркер кадра с номером в потоке клиента: `seq|<номер>|<сообщение>`.
+const TEXT_SEQUENCE_MARKER: &str = "seq|";
+/// Первый байт двоичного формата, текст с него не начинается.
+const BINARY_MARKER: u8 = 0;
+/// Первая версия двоичного формата: только котировки, без типа сообщения.
+const BINARY_VERSION_1: u8 = 1;
+/// Текущая версия двоичного формата: после версии идет байт типа сообщения.
+const BINARY_VERSION: u8 = 2;
+
+const KIND_QUOTE: u8 = 1;
+const KIND_TRADE: u8 = 2;
+const KIND_HEARTBEAT: u8 = 3;
+const KIND_STATUS: u8 = 4;
+const KIND_BOOK_SNAPSHOT: u8 = 5;
+const KIND_BOOK_UPDATE: u8 = 6;
+const KIND_BAR: u8 = 7;
+/// Кадр с номером в потоке клиента: после типа номер и сообщение целиком.
+const KIND_SEQUENCE: u8 = 8;
+
+/// Сделка.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Trade {
+    /// Тикер акции.
+    pub ticker: String,
+    /// Цена сделки.
+    pub price: Price,
+    /// Объем сделки.
+    pub size: Volume,
+    /// Время сделки.
+    pub timestamp: Timestamp,
+}
+
+/// Тип рыночных данных для подписки: `types=quotes,trades,depth,bars`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    /// `quotes`: обновления котировок.
+    Quote,
+    /// `trades`: сделки.
+    Trade,
+    /// `depth`: снимки и обновления стакана.
+    Depth,
+    /// `bars`: свечи.
+    Bar,
+}
+
+impl MessageKind {
+    /// Разбор списка типов через запятую: `quotes,trades`.
+    pub fn parse_list(s: &str) -> Result<Vec<MessageKind>, String> {
+        let mut kinds = vec![];
+        for kind in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let kind = kind.parse()?;
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+        if kinds.is_empty() {
+            return Err(format!("Не указаны типы сообщений: {}", s));
+        }
+        Ok(kinds)
+    }
+}
+
+impl std::fmt::Display for MessageKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            MessageKind::Quote => "quotes",
+            MessageKind::Trade => "trades",
+            MessageKind::Depth => "depth",
+            MessageKind::Bar => "bars",
+        })
+    }
+}
+
+impl std::str::FromStr for MessageKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "quotes" => Ok(MessageKind::Quote),
+            "trades" => Ok(MessageKind::Trade),
+            "depth" => Ok(MessageKind::Depth),
+            "bars" => Ok(MessageKind::Bar),
+            _ => Err(format!("Неизвестный тип сообщений: {}", s)),
+        }
+    }
+}
+
+/// Сообщение потока рыночных данных.
+///
+/// В первой версии текста передаются только котировки и heartbeat,
+/// остальные сообщения требуют `v2` или `bin`.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    /// Сделка.
+    Trade(Trade),
+    /// Обновление котировки.
+    Quote(StockQuote),
+    /// Полный снимок стакана.
+    BookSnapshot(BookSnapshot),
+    /// Изменения уровней стакана после снимка.
+    BookUpdate(BookUpdate),
+    /// Свеча за интервал.
+    Bar(Bar),
+    /// Признак активности потока при отсутствии данных.
+    Heartbeat,
+    /// Статус сервера, например окончание воспроизведения журнала.
+    Status {
+        /// Текст статуса.
+        message: String,
+    },
+}
+
+impl Message {
+    /// Тикер сделки или котировки.
+    pub fn ticker(&self) -> Option<&str> {
+        match self {
+            Message::Trade(trade) => Some(&trade.ticker),
+            Message::Quote(quote) => Some(&quote.ticker),
+            Message::BookSnapshot(snapshot) => Some(&snapshot.ticker),
+            Message::BookUpdate(update) => Some(&update.ticker),
+            Message::Bar(bar) => Some(&bar.ticker),
+            Message::Heartbeat | Message::Status { .. } => None,
+        }
+    }
+
+    /// Тип рыночных данных для фильтрации по подписке, у служебных сообщений его нет.
+    pub fn kind(&self) -> Option<MessageKind> {
+        match self {
+            Message::Trade(_) => Some(MessageKind::Trade),
+            Message::Quote(_) => Some(MessageKind::Quote),
+            Message::BookSnapshot(_) | Message::BookUpdate(_) => Some(MessageKind::Depth),
+            Message::Bar(_) => Some(MessageKind::Bar),
+            Message::Heartbeat | Message::Status { .. } => None,
+        }
+    }
+
+    /// Кодирование сообщения, `None` - сообщение не передается в этом формате.
+    pub fn encode(&self, format: QuoteFormat) -> Option<Vec<u8>> {
+        match (self, format) {
+            (Message::Quote(quote), _) => Some(quote.encode(format)),
+            (Message::Heartbeat, QuoteFormat::TextV1 | QuoteFormat::TextV2) => {
+                Some(crate::HEARTBEAT_MSG.as_bytes().to_vec())
+            }
+            (Message::Status { message }, QuoteFormat::TextV1 | QuoteFormat::TextV2) => Some(
+                format!("{}|{}", TEXT_V2_STATUS_MARKER, message.replace('\n', " ")).into_bytes(),
+            ),
+            (_, QuoteFormat::TextV1) => None,
+            (Message::Trade(trade), QuoteFormat::TextV2) => Some(
+                format!(
+                    "{}|{}|{}|{}|{}",
+                    TEXT_V2_TRADE_MARKER, trade.ticker, trade.price, trade.size, trade.timestamp
+                )
+                .into_bytes(),
+            ),
+            (Message::BookSnapshot(snapshot), QuoteFormat::TextV2) => Some(
+                format!(
+                    "{}|{}|{}|{}|{}|{}",
+                    TEXT_V2_BOOK_SNAPSHOT_MARKER,
+                    snapshot.ticker,
+                    snapshot.seq,
+                    snapshot.timestamp,
+                    format_levels(&snapshot.bids),
+                    format_levels(&snapshot.asks)
+                )
+                .into_bytes(),
+            ),
+            (Message::BookUpdate(update), QuoteFormat::TextV2) => {
+                let changes: Vec<String> = update
+                    .changes
+                    .iter()
+                    .map(|c| format!("{}:{}:{}", side_code(c.side), c.price, c.size))
+                    .collect();
+                Some(
+                    format!(
+                        "{}|{}|{}|{}|{}",
+                        TEXT_V2_BOOK_UPDATE_MARKER,
+                        update.ticker,
+                        update.seq,
+                        update.timestamp,
+                        changes.join(",")
+                    )
+                    .into_bytes(),
+                )
+            }
+            (Message::Bar(bar), QuoteFormat::TextV2) => Some(
+                format!(
+                    "{}|{}|{}|{}|{}|{}|{}|{}|{}",
+                    TEXT_V2_BAR_MARKER,
+                    bar.ticker,
+                    bar.interval,
+                    bar.start,
+                    bar.open,
+                    bar.high,
+                    bar.low,
+                    bar.close,
+                    bar.volume
+                )
+                .into_bytes(),
+            ),
+            (Message::Trade(trade), QuoteFormat::Binary) => {
+                let mut data = binary_header(KIND_TRADE);
+                put_ticker(&mut data, &trade.ticker);
+                data.extend_from_slice(&trade.price.units().to_le_bytes());
+                data.extend_from_slice(&trade.size.units().to_le_bytes());
+                data.extend_from_slice(&trade.timestamp.as_millis().to_le_bytes());
+                Some(data)
+            }
+            (Message::BookSnapshot(snapshot), QuoteFormat::Binary) => {
+                let mut data = binary_header(KIND_BOOK_SNAPSHOT);
+                put_ticker(&mut data, &snapshot.ticker);
+                data.extend_from_slice(&snapshot.seq.to_le_bytes());
+                data.extend_from_slice(&snapshot.timestamp.as_millis().to_le_bytes());
+                for levels in [&snapshot.bids, &snapshot.asks] {
+                    // Глубина стакана ограничена `MAX_BOOK_DEPTH`, уровни дальше не передаются
+                    let levels = &levels[..levels.len().min(u8::MAX as usize)];
+                    data.push(levels.len() as u8);
+                    for level in levels {
+                        data.extend_from_slice(&level.price.units().to_le_bytes());
+                        data.extend_from_slice(&level.size.units().to_le_bytes());
+                    }
+                }
+                Some(data)
+            }
+            (Message::BookUpdate(update), QuoteFormat::Binary) => {
+                let mut data = binary_header(KIND_BOOK_UPDATE);
+                put_ticker(&mut data, &update.ticker);
+                data.extend_from_slice(&update.seq.to_le_bytes());
+                data.extend_from_slice(&update.timestamp.as_millis().to_le_bytes());
+                let changes = &update.changes[..update.changes.len().min(u8::MAX as usize)];
+                data.push(changes.len() as u8);
+                for change in changes {
+                    data.push(side_code(change.side) as u8);
+                    data.extend_from_slice(&change.price.units().to_le_bytes());
+                    data.extend_from_slice(&change.size.units().to_le_bytes());
+                }
+                Some(data)
+            }
+            (Message::Bar(bar), QuoteFormat::Binary) => {
+                let mut data = binary_header(KIND_BAR);
+                put_ticker(&mut data, &bar.ticker);
+                data.extend_from_slice(&bar.interval.as_millis().to_le_bytes());
+                data.extend_from_slice(&bar.start.as_millis().to_le_bytes());
+                for price in [bar.open, bar.high, bar.low, bar.close] {
+                    data.extend_from_slice(&price.units().to_le_bytes());
+                }
+                data.extend_from_slice(&bar.volume.units().to_le_bytes());
+                Some(data)
+            }
+            (Message::Heartbeat, QuoteFormat::Binary) => Some(binary_header(KIND_HEARTBEAT)),
+            (Message::Status { message }, QuoteFormat::Binary) => {
+                let mut data = binary_header(KIND_STATUS);
+                let text = &message.as_bytes()[..message.len().min(u16::MAX as usize)];
+                data.extend_from_slice(&(text.len() as u16).to_le_bytes());
+                data.extend_from_slice(text);
+                Some(data)
+            }
+        }
+    }
+
+    /// Декодирование сообщения любого формата, формат определяется по маркеру версии.
+    /// Номер кадра, если он есть, пропускается, см. [`split_sequence`].
+    pub fn decode(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let (_, data) = split_sequence(data);
+        if !is_binary(data) {
+            return Self::from_text(std::str::from_utf8(data)?.trim());
+        }
+        let mut reader = BinaryReader { data: &data[1..] };
+        match reader.u8()? {
+            BINARY_VERSION_1 => Ok(Message::Quote(reader.quote()?)),
+            BINARY_VERSION => match reader.u8()? {
+                KIND_QUOTE => Ok(Message::Quote(reader.quote()?)),
+                KIND_TRADE => Ok(Message::Trade(Trade {
+                    ticker: reader.ticker()?,
+                    price: Price::from_units(reader.i64()?),
+                    size: Volume::new(reader.u64()?),
+                    timestamp: Timestamp::from_millis(reader.u64()?),
+                })),
+                KIND_BOOK_SNAPSHOT => Ok(Message::BookSnapshot(BookSnapshot {
+                    ticker: reader.ticker()?,
+                    seq: reader.u64()?,
+                    timestamp: Timestamp::from_millis(reader.u64()?),
+                    bids: reader.levels()?,
+                    asks: reader.levels()?,
+                })),
+                KIND_BOOK_UPDATE => {
+                    let ticker = reader.ticker()?;
+                    let seq = reader.u64()?;
+                    let timestamp = Timestamp::from_millis(reader.u64()?);
+                    let count = reader.u8()?;
+                    let mut changes = Vec::with_capacity(count as usize);
+                    for _ in 0..count {
+                        changes.push(LevelChange {
+                            side: parse_side(reader.u8()? as char)?,
+                            price: Price::from_units(reader.i64()?),
+                            size: Volume::new(reader.u64()?),
+                        });
+                    }
+                    Ok(Message::BookUpdate(BookUpdate {
+                        ticker,
+                        seq,
+                        timestamp,
+                        changes,
+                    }))
+                }
+                KIND_BAR => Ok(Message::Bar(Bar {
+                    ticker: reader.ticker()?,
+                    interval: BarInterval::from_millis(reader.u64()?)
+                        .ok_or("Нулевой интервал свечи")?,
+                    start: Timestamp::from_millis(reader.u64()?),
+                    open: Price::from_units(reader.i64()?),
+                    high: Price::from_units(reader.i64()?),
+                    low: Price::from_units(reader.i64()?),
+                    close: Price::from_units(reader.i64()?),
+                    volume: Volume::new(reader.u64()?),
+                })),
+                KIND_HEARTBEAT => Ok(Message::Heartbeat),
+                KIND_STATUS => {
+                    let len = u16::from_le_bytes(reader.array()?) as usize;
+                    Ok(Message::Status {
+                        message: String::from_utf8(reader.bytes(len)?.to_vec())?,
+                    })
+                }
+                kind => Err(format!("Неизвестный тип сообщения: {}", kind).into()),
+            },
+            version => {
+                Err(format!("Неподдерживаемая версия двоичного формата: {}", version).into())
+            }
+        }
+    }
+
+    fn from_text(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        if s == crate::HEARTBEAT_MSG {
+            return Ok(Message::Heartbeat);
+        }
+        if let Some(message) = s
+            .strip_prefix(TEXT_V2_STATUS_MARKER)
+            .and_then(|s| s.strip_prefix('|'))
+        {
+            return Ok(Message::Status {
+                message: message.to_string(),
+            });
+        }
+        if let Some(fields) = s
+            .strip_prefix(TEXT_V2_BOOK_SNAPSHOT_MARKER)
+            .and_then(|s| s.strip_prefix('|'))
+        {
+            let l: Vec<&str> = fields.split('|').collect();
+            if l.len() != 5 {
+                return Err(format!("Expected 5 book fields, got {}: {:?}", l.len(), l).into());
+            }
+            return Ok(Message::BookSnapshot(BookSnapshot {
+                ticker: l[0].to_string(),
+                seq: l[1].parse()?,
+                timestamp: l[2].parse()?,
+                bids: parse_levels(l[3])?,
+                asks: parse_levels(l[4])?,
+            }));
+        }
+        if let Some(fields) = s
+            .strip_prefix(TEXT_V2_BOOK_UPDATE_MARKER)
+            .and_then(|s| s.strip_prefix('|'))
+        {
+            let l: Vec<&str> = fields.split('|').collect();
+            if l.len() != 4 {
+                return Err(format!("Expected 4 update fields, got {}: {:?}", l.len(), l).into());
+            }
+            let mut changes = vec![];
+            for change in l[3].split(',').filter(|c| !c.is_empty()) {
+                let c: Vec<&str> = change.split(':').collect();
+                if c.len() != 3 {
+                    return Err(format!("Некорректное изменение стакана: {}", change).into());
+                }
+                changes.push(LevelChange {
+                    side: parse_side(c[0].chars().next().unwrap_or_default())?,
+                    price: c[1].parse()?,
+                    size: c[2].parse()?,
+                });
+            }
+            return Ok(Message::BookUpdate(BookUpdate {
+                ticker: l[0].to_string(),
+                seq: l[1].parse()?,
+                timestamp: l[2].parse()?,
+                changes,
+            }));
+        }
+        if let Some(fields) = s
+            .strip_prefix(TEXT_V2_BAR_MARKER)
+            .and_then(|s| s.strip_prefix('|'))
+        {
+            let l: Vec<&str> = fields.split('|').collect();
+            if l.len() != 8 {
+                return Err(format!("Expected 8 bar fields, got {}: {:?}", l.len(), l).into());
+            }
+            return Ok(Message::Bar(Bar {
+                ticker: l[0].to_string(),
+                interval: l[1].parse()?,
+                start: l[2].parse()?,
+                open: l[3].parse()?,
+                high: l[4].parse()?,
+                low: l[5].parse()?,
+                close: l[6].parse()?,
+                volume: l[7].parse()?,
+            }));
+        }
+        if let Some(fields) = s
+            .strip_prefix(TEXT_V2_TRADE_MARKER)
+            .and_then(|s| s.strip_prefix('|'))
+        {
+            let l: Vec<&str> = fields.split('|').collect();
+            if l.len() != 4 {
+                return Err(format!("Expected 4 trade fields, got {}: {:?}", l.len(), l).into());
+            }
+            return Ok(Message::Trade(Trade {
+                ticker: l[0].to_string(),
+                price: l[1].parse()?,
+                size: l[2].parse()?,
+                timestamp: l[3].parse()?,
+            }));
+        }
+        StockQuote::from_string(s).map(Message::Quote)
+    }
+}
+
+impl StockQuote {
+    /// Кодирование котировки для отправки в выбранном формате.
+    pub fn encode(&self, format: QuoteFormat) -> Vec<u8> {
+        match format {
+            QuoteFormat::TextV1 => self.to_string().into_bytes(),
+            QuoteFormat::TextV2 => format!(
+                "{}|{}|{}|{}|{}|{}|{}|{}|{}",
+                TEXT_V2_MARKER,
+                self.ticker,
+                self.price,
+                self.volume,
+                self.timestamp,
+                self.bid,
+                self.ask,
+                self.bid_size,
+                self.ask_size
+            )
+            .into_bytes(),
+            QuoteFormat::Binary => {
+                let mut data = binary_header(KIND_QUOTE);
+                put_ticker(&mut data, &self.ticker);
+                for field in [
+                    self.price.units().to_le_bytes(),
+                    self.volume.units().to_le_bytes(),
+                    self.timestamp.as_millis().to_le_bytes(),
+                    self.bid.units().to_le_bytes(),
+                    self.ask.units().to_le_bytes(),
+                    self.bid_size.units().to_le_bytes(),
+                    self.ask_size.units().to_le_bytes(),
+                ] {
+                    data.extend_from_slice(&field);
+                }
+                data
+            }
+        }
+    }
+
+    /// Декодирование котировки любого формата, другие сообщения - ошибка.
+    pub fn decode(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        match Message::decode(data)? {
+            Message::Quote(quote) => Ok(quote),
+            message => Err(format!("Ожидалась котировка: {:?}", message).into()),
+        }
+    }
+}
+
+/// Является ли сообщение двоичным.
+pub fn is_binary(data: &[u8]) -> bool {
+    data.first() == Some(&BINARY_MARKER)
+}
+
+/// Кадр потока с номером: закодированное сообщение без изменений после номера,
+/// `seq|<номер>|<сообщение>` для текста или двоичный заголовок с номером.
+pub fn sequenced(seq: u64, data: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(data.len() + 24);
+    if is_binary(data) {
+        frame.extend_from_slice(&[BINARY_MARKER, BINARY_VERSION, KIND_SEQUENCE]);
+        frame.extend_from_slice(&seq.to_le_bytes());
+    } else {
+        frame.extend_from_slice(format!("{}{}|", TEXT_SEQUENCE_MARKER, seq).as_bytes());
+    }
+    frame.extend_from_slice(data);
+    frame
+}
+
+/// Номер кадра потока и сообщение, кадр без номера возвращается как есть.
+pub fn split_sequence(data: &[u8]) -> (Option<u64>, &[u8]) {
+    if let [BINARY_MARKER, BINARY_VERSION, KIND_SEQUENCE, rest @ ..] = data
+        && let Some((seq, message)) = rest.split_first_chunk::<8>()
+    {
+        return (Some(u64::from_le_bytes(*seq)), message);
+    }
+    if let Some(rest) = data.strip_prefix(TEXT_SEQUENCE_MARKER.as_bytes())
+        && let Some(end) = rest.iter().position(|&b| b == b'|')
+        && let Some(seq) = std::str::from_utf8(&rest[..end])
+            .ok()
+            .and_then(|seq| seq.parse().ok())
+    {
+        return (Some(seq), &rest[end + 1..]);
+    }
+    (None, data)
+}
+
+/// Код стороны стакана в тексте и двоичном формате: `B` или `A`.
+fn side_code(side: Side) -> char {
+    match side {
+        Side::Bid => 'B',
+        Side::Ask => 'A',
+    }
+}
+
+fn parse_side(code: char) -> Result<Side, Box<dyn std::error::Error>> {
+    match code {
+        'B' => Ok(Side::Bid),
+        'A' => Ok(Side::Ask),
+        _ => Err(format!("Неизвестная сторона стакана: {}", code).into()),
+    }
+}
+
+/// Уровни в тексте: `цена:объем,цена:объем`.
+fn format_levels(levels: &[BookLevel]) -> String {
+    levels
+        .iter()
+        .map(|l| format!("{}:{}", l.price, l.size))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn parse_levels(s: &str) -> Result<Vec<BookLevel>, Box<dyn std::error::Error>> {
+    s.split(',')
+        .filter(|l| !l.is_empty())
+        .map(|level| {
+            let (price, size) = level
+                .split_once(':')
+                .ok_or_else(|| format!("Некорректный уровень стакана: {}", level))?;
+            Ok(BookLevel {
+                price: price.parse()?,
+                size: size.parse()?,
+            })
+        })
+        .collect()
+}
+
+fn binary_header(kind: u8) -> Vec<u8> {
+    let mut data = Vec::with_capacity(64);
+    data.extend_from_slice(&[BINARY_MARKER, BINARY_VERSION, kind]);
+    data
+}
+
+/// Длина и байты тикера, тикер длиннее 255 байт обрезается.
+fn put_ticker(data: &mut Vec<u8>, ticker: &str) {
+    let ticker = &ticker.as_bytes()[..ticker.len().min(u8::MAX as usize)];
+    data.push(ticker.len() as u8);
+    data.extend_from_slice(ticker);
+}
+
+/// Последовательное чтение полей двоичного сообщения.
+struct BinaryReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> BinaryReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Box<dyn std::error::Error>> {
+        if self.data.len() < len {
+            return Err("Обрезанное двоичное сообщение".into());
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Box<dyn std::error::Error>> {
+        Ok(self.bytes(N)?.try_into()?)
+    }
+
+    fn u8(&mut self) -> Result<u8, Box<dyn std::error::Error>> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64, Box<dyn std::error::Error>> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn i64(&mut self) -> Result<i64, Box<dyn std::error::Error>> {
+        Ok(i64::from_le_bytes(self.array()?))
+    }
+
+    fn ticker(&mut self) -> Result<String, Box<dyn std::error::Error>> {
+        let len = self.u8()? as usize;
+        Ok(String::from_utf8(self.bytes(len)?.to_vec())?)
+    }
+
+    fn levels(&mut self) -> Result<Vec<BookLevel>, Box<dyn std::error::Error>> {
+        let count = self.u8()?;
+        (0..count)
+            .map(|_| {
+                Ok(BookLevel {
+                    price: Price::from_units(self.i64()?),
+                    size: Volume::new(self.u64()?),
+                })
+            })
+            .collect()
+    }
+
+    fn quote(&mut self) -> Result<StockQuote, Box<dyn std::error::Error>> {
+        Ok(StockQuote {
+            ticker: self.ticker()?,
+            price: Price::from_units(self.i64()?),
+            volume: Volume::new(self.u64()?),
+            timestamp: Timestamp::from_millis(self.u64()?),
+            bid: Price::from_units(self.i64()?),
+            ask: Price::from_units(self.i64()?),
+            bid_size: Volume::new(self.u64()?),
+            ask_size: Volume::new(self.u64()?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_FORMATS: [QuoteFormat; 3] = [
+        QuoteFormat::TextV1,
+        QuoteFormat::TextV2,
+        QuoteFormat::Binary,
+    ];
+
+    fn quote() -> StockQuote {
+        StockQuote {
+            ticker: "AAPL".to_string(),
+            price: "150.25".parse().unwrap(),
+            volume: Volume::new(1200),
+            timestamp: Timestamp::from_millis(1_700_000_000_250),
+            bid: "150.24".parse().unwrap(),
+            ask: Price::from_units(1_502_600),
+            bid_size: Volume::new(300),
+            ask_size: Volume::new(500),
+        }
+    }
+
+    fn messages() -> Vec<Message> {
+        let timestamp = Timestamp::from_millis(1_700_000_000_250);
+        let level = |price: &str, size| BookLevel {
+            price: price.parse().unwrap(),
+            size: Volume::new(size),
+        };
+        vec![
+            Message::Quote(quote()),
+            Message::Trade(Trade {
+                ticker: "MSFT".to_string(),
+                price: "-0.0001".parse().unwrap(),
+                size: Volume::new(700),
+                timestamp,
+            }),
+            Message::BookSnapshot(BookSnapshot {
+                ticker: "AAPL".to_string(),
+                seq: 17,
+                timestamp,
+                bids: vec![level("150.24", 300), level("150.23", 1_000)],
+                asks: vec![level("150.26", 500)],
+            }),
+            Message::BookSnapshot(BookSnapshot {
+                ticker: "EMPTY".to_string(),
+                seq: 1,
+                timestamp,
+                bids: vec![],
+                asks: vec![],
+            }),
+            Message::BookUpdate(BookUpdate {
+                ticker: "AAPL".to_string(),
+                seq: 18,
+                timestamp,
+                changes: vec![
+                    LevelChange {
+                        side: Side::Bid,
+                        price: "150.24".parse().unwrap(),
+                        size: Volume::ZERO,
+                    },
+                    LevelChange {
+                        side: Side::Ask,
+                        price: "150.27".parse().unwrap(),
+                        size: Volume::new(200),
+                    },
+                ],
+            }),
+            Message::Bar(Bar {
+                ticker: "AAPL".to_string(),
+                interval: "1m".parse().unwrap(),
+                start: Timestamp::from_secs(1_700_000_040),
+                open: "150.1".parse().unwrap(),
+                high: "150.9".parse().unwrap(),
+                low: "149.95".parse().unwrap(),
+                close: "150.5".parse().unwrap(),
+                volume: Volume::new(12_345),
+            }),
+            Message::Status {
+                message: "Пропущено сообщений: 3".to_string(),
+            },
+            Message::Heartbeat,
+        ]
+    }
+
+    #[test]
+    fn text_v2_and_binary_round_trip() {
+        for format in [QuoteFormat::TextV2, QuoteFormat::Binary] {
+            for message in messages() {
+                let data = message.encode(format).unwrap();
+                assert_eq!(is_binary(&data), format == QuoteFormat::Binary);
+                let decoded = Message::decode(&data).unwrap();
+                assert_eq!(
+                    format!("{:?}", decoded),
+                    format!("{:?}", message),
+                    "{}",
+                    format
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn text_v1_quotes_heartbeat_and_status_only() {
+        for message in messages() {
+            let encoded = message.encode(QuoteFormat::TextV1);
+            match message {
+                Message::Quote(quote) => {
+                    let decoded = StockQuote::decode(&encoded.unwrap()).unwrap();
+                    // Первая версия без bid/ask и с целыми секундами
+                    assert_eq!(decoded.ticker, quote.ticker);
+                    assert_eq!(decoded.price, quote.price);
+                    assert_eq!(decoded.volume, quote.volume);
+                    assert_eq!(decoded.timestamp.as_secs(), quote.timestamp.as_secs());
+                    assert_eq!(decoded.bid, quote.price);
+                    assert_eq!(decoded.ask, quote.price);
+                }
+                Message::Heartbeat | Message::Status { .. } => {
+                    let decoded = Message::decode(&encoded.unwrap()).unwrap();
+                    assert_eq!(format!("{:?}", decoded), format!("{:?}", message));
+                }
+                _ => assert!(encoded.is_none(), "{:?}", message),
+            }
+        }
+    }
+
+    #[test]
+    fn binary_version_1_quote() {
+        let data = quote().encode(QuoteFormat::Binary);
+        let mut legacy = vec![BINARY_MARKER, BINARY_VERSION_1];
+        legacy.extend_from_slice(&data[3..]);
+        let decoded = StockQuote::decode(&legacy).unwrap();
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", quote()));
+    }
+
+    #[test]
+    fn status_newlines_in_text() {
+        let message = Message::Status {
+            message: "строка 1\nстрока 2".to_string(),
+        };
+        let data = message.encode(QuoteFormat::TextV2).unwrap();
+        assert!(matches!(
+            Message::decode(&data).unwrap(),
+            Message::Status { message } if message == "строка 1 строка 2"
+        ));
+    }
+
+    #[test]
+    fn decode_rejects_garbage() {
+        assert!(Message::decode(b"v2|AAPL|1").is_err());
+        assert!(Message::decode(&[BINARY_MARKER, 9]).is_err());
+        assert!(Message::decode(&[BINARY_MARKER, BINARY_VERSION, 99]).is_err());
+        let data = quote().encode(QuoteFormat::Binary);
+        assert!(Message::decode(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn deepest_book_fits_datagram() {
+        let levels: Vec<BookLevel> = (0..crate::MAX_BOOK_DEPTH)
+            .map(|i| BookLevel {
+                price: Price::from_units(9_999_999_999 - i as i64),
+                size: Volume::new(u64::MAX - i as u64),
+            })
+            .collect();
+        let snapshot = Message::BookSnapshot(BookSnapshot {
+            ticker: "LONGTICKER".to_string(),
+            seq: u64::MAX,
+            timestamp: Timestamp::from_millis(1_700_000_000_250),
+            bids: levels.clone(),
+            asks: levels,
+        });
+        for format in [QuoteFormat::TextV2, QuoteFormat::Binary] {
+            let data = sequenced(u64::MAX, &snapshot.encode(format).unwrap());
+            assert!(data.len() <= crate::MAX_DATAGRAM_SIZE, "{}", data.len());
+            assert_eq!(
+                format!("{:?}", Message::decode(&data).unwrap()),
+                format!("{:?}", snapshot)
+            );
+        }
+    }
+
+    #[test]
+    fn sequence_round_trip() {
+        for format in ALL_FORMATS {
+            let data = Message::Quote(quote()).encode(format).unwrap();
+            let frame = sequenced(42, &data);
+            assert_eq!(split_sequence(&frame), (Some(42), data.as_slice()));
+            assert!(matches!(Message::decode(&frame), Ok(Message::Quote(q)) if q.ticker == "AAPL"));
+        }
+    }
+
+    #[test]
+    fn frame_without_sequence() {
+        let data = Message::Quote(quote()).encode(QuoteFormat::TextV2).unwrap();
+        assert_eq!(split_sequence(&data), (None, data.as_slice()));
+        assert_eq!(split_sequence(b"seq|x|AAPL"), (None, &b"seq|x|AAPL"[..]));
+    }
+}
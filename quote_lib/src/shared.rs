@@ -0,0 +1,94 @@
+//! Сообщение для рассылки многим получателям, кодируется один раз на формат.
+
+use std::{
+    ops::Deref,
+    sync::{Arc, OnceLock},
+};
+
+use crate::{Message, QuoteFormat};
+
+/// Сообщение рассылки с представлениями в форматах передачи, общими для всех получателей:
+/// формат кодируется при первом запросе, остальные получатели разделяют тот же буфер.
+/// Так же один раз сериализуется JSON для WebSocket и SSE клиентов.
+#[derive(Debug)]
+pub struct SharedMessage {
+    message: Message,
+    /// Представления по форматам, `None` - сообщение не передается в формате.
+    encoded: [OnceLock<Option<Arc<[u8]>>>; 3],
+    /// JSON сообщения, `None` - сообщение не сериализуется.
+    json: OnceLock<Option<Arc<str>>>,
+}
+
+impl SharedMessage {
+    /// Сообщение для рассылки, еще не закодированное ни в одном формате.
+    pub fn new(message: Message) -> Arc<Self> {
+        Arc::new(Self {
+            message,
+            encoded: Default::default(),
+            json: OnceLock::new(),
+        })
+    }
+
+    /// Исходное сообщение.
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    /// Общий буфер сообщения в формате, см. [`Message::encode`].
+    pub fn encoded(&self, format: QuoteFormat) -> Option<Arc<[u8]>> {
+        let index = match format {
+            QuoteFormat::TextV1 => 0,
+            QuoteFormat::TextV2 => 1,
+            QuoteFormat::Binary => 2,
+        };
+        self.encoded[index]
+            .get_or_init(|| self.message.encode(format).map(Arc::from))
+            .clone()
+    }
+
+    /// Общий JSON сообщения для WebSocket и SSE клиентов.
+    pub fn json(&self) -> Option<Arc<str>> {
+        self.json
+            .get_or_init(|| match serde_json::to_string(&self.message) {
+                Ok(json) => Some(Arc::from(json)),
+                Err(e) => {
+                    log::error!("Ошибка сериализации сообщения в JSON: {}", e);
+                    None
+                }
+            })
+            .clone()
+    }
+}
+
+impl Deref for SharedMessage {
+    type Target = Message;
+
+    fn deref(&self) -> &Message {
+        &self.message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoded_once_per_format() {
+        let shared = SharedMessage::new(Message::Status {
+            message: "Воспроизведение завершено".to_string(),
+        });
+        for format in [
+            QuoteFormat::TextV1,
+            QuoteFormat::TextV2,
+            QuoteFormat::Binary,
+        ] {
+            let first = shared.encoded(format).unwrap();
+            assert!(Arc::ptr_eq(&first, &shared.encoded(format).unwrap()));
+            assert_eq!(Some(&*first), shared.message().encode(format).as_deref());
+        }
+
+        let json = shared.json().unwrap();
+        assert!(Arc::ptr_eq(&json, &shared.json().unwrap()));
+        assert_eq!(&*json, serde_json::to_string(shared.message()).unwrap());
+    }
+}
@@ -0,0 +1,228 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream},
+    sync::atomic::{AtomicU64, Ordering},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use quote_lib::{CLIENTS_CMD, END_MSG, KICK_CMD, SERVER_OK, STATS_CMD};
+
+use crate::command_handler::ServerContext;
+
+/// Счетчики работы сервера для команды `STATS`.
+pub(crate) struct ServerStats {
+    started: Instant,
+    /// Опубликовано котировок, в том числе индикаторов и составных инструментов.
+    quotes: AtomicU64,
+    /// Отправлено UDP датаграмм клиентам.
+    datagrams_sent: AtomicU64,
+    /// Датаграммы, которые не удалось отправить.
+    datagrams_failed: AtomicU64,
+}
+
+impl ServerStats {
+    pub(crate) fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            quotes: AtomicU64::new(0),
+            datagrams_sent: AtomicU64::new(0),
+            datagrams_failed: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn count_quote(&self) {
+        self.quotes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Учет отправки датаграммы клиенту, `sent` - отправка без ошибки.
+    pub(crate) fn count_datagram(&self, sent: bool) {
+        let counter = if sent {
+            &self.datagrams_sent
+        } else {
+            &self.datagrams_failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Административный TCP сервер: команды `STATS`, `CLIENTS` и `KICK <номер>`
+/// с ответами в формате управляющего соединения.
+pub(crate) fn start_admin_server(
+    addr: SocketAddr,
+    ctx: ServerContext,
+) -> Result<JoinHandle<()>, std::io::Error> {
+    let listner = crate::bind_tcp_listener(addr)?;
+    listner.set_nonblocking(true)?;
+    log::info!("Административный сервер запущен на {}", addr);
+
+    Ok(thread::spawn(move || {
+        let mut handles = vec![];
+        while ctx.running.load(Ordering::SeqCst) {
+            match listner.accept() {
+                Ok((stream, _)) => {
+                    let ctx = ctx.clone();
+                    handles.push(thread::spawn(move || handle_admin_client(stream, ctx)));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => {
+                    log::debug!("Error accepting connection: {}", e);
+                }
+            }
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        log::info!("Административный сервер остановлен на {}", addr);
+    }))
+}
+
+fn handle_admin_client(stream: TcpStream, ctx: ServerContext) {
+    let peer_addr = match stream.peer_addr() {
+        Ok(addr) => addr,
+        Err(e) => {
+            log::error!("Failed to get peer address: {}", e);
+            return;
+        }
+    };
+    // Таймаут чтения, чтобы соединение закрывалось при остановке сервера
+    if let Err(e) = stream
+        .set_nonblocking(false)
+        .and_then(|_| stream.set_read_timeout(Some(Duration::from_secs(1))))
+    {
+        log::error!("Failed to set read timeout: {}", e);
+        return;
+    }
+    let mut writer = match stream.try_clone() {
+        Ok(s) => s,
+        Err(e) => {
+            log::error!("Failed to clone stream: {}", e);
+            return;
+        }
+    };
+    log::info!("Новое административное соединение: {}", peer_addr);
+
+    let mut reader = BufReader::new(stream);
+    // Строка накапливается между таймаутами чтения
+    let mut line = String::new();
+    while ctx.running.load(Ordering::SeqCst) {
+        match reader.read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {
+                let command = line.trim();
+                if !command.is_empty() {
+                    log::info!("Административный запрос {}: {}", peer_addr, command);
+                    let response = admin_response(command, &ctx);
+                    if let Err(e) = writer.write_all(response.as_bytes()) {
+                        log::error!("Error sending response: {} ", e);
+                        break;
+                    }
+                }
+                line.clear();
+            }
+            Err(e)
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut => {}
+            Err(e) => {
+                log::error!("Failed to read from stream: {}", e);
+                break;
+            }
+        }
+    }
+    log::info!("Административное соединение закрыто: {}", peer_addr);
+}
+
+/// Ответ на команду: `OK`, `OK` со строками до `END` или текст ошибки.
+fn admin_response(command: &str, ctx: &ServerContext) -> String {
+    let parts: Vec<&str> = command.split_whitespace().collect();
+    let result = match parts.as_slice() {
+        [STATS_CMD] => Ok(Some(stats(ctx))),
+        [CLIENTS_CMD] => Ok(Some(clients(ctx))),
+        [KICK_CMD, id] => kick(id, ctx).map(|_| None),
+        _ => Err(format!("Неизвестная команда: {}", command)),
+    };
+    let mut response = match result {
+        Ok(None) => SERVER_OK.to_string(),
+        Ok(Some(body)) => {
+            let mut response = SERVER_OK.to_string();
+            for line in body {
+                response.push('\n');
+                response.push_str(&line);
+            }
+            response.push('\n');
+            response.push_str(END_MSG);
+            response
+        }
+        Err(message) => message,
+    };
+    response.push('\n');
+    response
+}
+
+/// Строки `<параметр> <значение>` о работе сервера.
+fn stats(ctx: &ServerContext) -> Vec<String> {
+    let stats = &ctx.stats;
+    let streams = ctx
+        .client_manager
+        .lock()
+        .unwrap()
+        .clients
+        .read()
+        .unwrap()
+        .len();
+    vec![
+        format!("uptime {}s", stats.started.elapsed().as_secs()),
+        format!("quotes {}", stats.quotes.load(Ordering::Relaxed)),
+        format!(
+            "datagrams_sent {}",
+            stats.datagrams_sent.load(Ordering::Relaxed)
+        ),
+        format!(
+            "datagrams_failed {}",
+            stats.datagrams_failed.load(Ordering::Relaxed)
+        ),
+        format!("queue_dropped {}", ctx.broadcast.dropped()),
+        format!("queue_disconnected {}", ctx.broadcast.disconnected()),
+        format!("streams {}", streams),
+    ]
+}
+
+/// Строка на поток клиента: `<номер> peer=<адрес> udp=<адрес> tickers=AAPL,MSFT
+/// last_ping=1.5s sent=<число> errors=<число>`, неизвестный адрес - `-`.
+fn clients(ctx: &ServerContext) -> Vec<String> {
+    let manager = ctx.client_manager.lock().unwrap();
+    let clients = manager.clients.read().unwrap();
+    let mut ids: Vec<&u64> = clients.keys().collect();
+    ids.sort();
+    let addr = |addr: Option<SocketAddr>| addr.map_or("-".to_string(), |addr| addr.to_string());
+    ids.into_iter()
+        .map(|id| {
+            let client = &clients[id];
+            format!(
+                "{} peer={} udp={} tickers={} last_ping={:.1}s sent={} errors={}",
+                id,
+                addr(client.peer_addr),
+                addr(client.udp_addr),
+                client.subscribed_tickers.join(","),
+                client.last_ping.elapsed().as_secs_f64(),
+                client.sent,
+                client.send_errors
+            )
+        })
+        .collect()
+}
+
+/// Принудительная остановка потока: клиент удаляется, поток отправки завершается
+/// на следующей проверке клиента.
+fn kick(id: &str, ctx: &ServerContext) -> Result<(), String> {
+    let id: u64 = id
+        .parse()
+        .map_err(|_| format!("Неверный номер клиента: {}", id))?;
+    if !ctx.client_manager.lock().unwrap().remove_client(id) {
+        return Err(format!("Клиент не найден: {}", id));
+    }
+    log::warn!("Поток клиента {} остановлен администратором", id);
+    Ok(())
+}
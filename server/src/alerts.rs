@@ -0,0 +1,305 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    net::TcpStream,
+    sync::{Arc, Mutex, atomic::Ordering},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crossbeam::channel::{self, Receiver, Sender};
+use quote_lib::{ALERT_CMD, Message, Price, StockQuote};
+
+use crate::{command_handler::ServerContext, quote_broadcast::Subscription};
+
+/// Условие уведомления: `above 200`, `below 150.5` или `pct-move 2.5`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum AlertCondition {
+    /// Цена не ниже уровня.
+    Above(Price),
+    /// Цена не выше уровня.
+    Below(Price),
+    /// Изменение цены от опорной не меньше указанного процента.
+    PctMove(f64),
+}
+
+impl AlertCondition {
+    /// Разбор условия из команды, уровень и процент должны быть положительными.
+    pub(crate) fn parse(kind: &str, value: &str) -> Option<Self> {
+        let condition = match kind {
+            "above" => AlertCondition::Above(value.parse().ok()?),
+            "below" => AlertCondition::Below(value.parse().ok()?),
+            "pct-move" => AlertCondition::PctMove(value.parse().ok()?),
+            _ => return None,
+        };
+        match condition {
+            AlertCondition::Above(price) | AlertCondition::Below(price) => {
+                (price > Price::ZERO).then_some(condition)
+            }
+            AlertCondition::PctMove(pct) => (pct.is_finite() && pct > 0.0).then_some(condition),
+        }
+    }
+
+    /// Достигнут ли уровень при цене, `None` - у `pct-move` уровня нет.
+    fn level_reached(&self, price: Price) -> Option<bool> {
+        match *self {
+            AlertCondition::Above(level) => Some(price >= level),
+            AlertCondition::Below(level) => Some(price <= level),
+            AlertCondition::PctMove(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for AlertCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlertCondition::Above(price) => write!(f, "above {}", price),
+            AlertCondition::Below(price) => write!(f, "below {}", price),
+            AlertCondition::PctMove(pct) => write!(f, "pct-move {}", pct),
+        }
+    }
+}
+
+/// Уведомление о цене по тикеру.
+struct Alert {
+    id: u64,
+    ticker: String,
+    condition: AlertCondition,
+    /// Повторять уведомление, иначе оно удаляется после срабатывания.
+    repeat: bool,
+    /// Условие выполнено, повторное уведомление ждет его сброса.
+    triggered: bool,
+    /// Опорная цена для `pct-move`, `None` - берется из первой котировки.
+    reference: Option<Price>,
+}
+
+impl Alert {
+    /// Уведомление по последней известной цене тикера: уровень, который уже
+    /// достигнут, считается сработавшим и уведомляет только после пересечения.
+    fn new(
+        id: u64,
+        ticker: String,
+        condition: AlertCondition,
+        repeat: bool,
+        last: Option<Price>,
+    ) -> Self {
+        let triggered = last
+            .and_then(|price| condition.level_reached(price))
+            .unwrap_or(false);
+        Self {
+            id,
+            ticker,
+            condition,
+            repeat,
+            triggered,
+            reference: last,
+        }
+    }
+
+    /// Проверка по новой цене, `true` - нужно отправить уведомление.
+    fn check(&mut self, price: Price) -> bool {
+        let matched = match self.condition {
+            AlertCondition::PctMove(pct) => {
+                let reference = *self.reference.get_or_insert(price);
+                let moved = (price.to_f64() / reference.to_f64() - 1.0).abs() * 100.0 >= pct;
+                if moved {
+                    // Следующее изменение отсчитывается от цены срабатывания
+                    self.reference = Some(price);
+                }
+                return moved;
+            }
+            _ => self.condition.level_reached(price).unwrap_or(false),
+        };
+        let fire = matched && !self.triggered;
+        self.triggered = matched;
+        fire
+    }
+
+    fn mode(&self) -> &'static str {
+        if self.repeat { "repeat" } else { "once" }
+    }
+}
+
+#[derive(Default)]
+struct AlertBook {
+    alerts: BTreeMap<u64, Alert>,
+    next_alert_id: u64,
+}
+
+impl AlertBook {
+    /// Проверка уведомлений тикера по котировке, возвращает строки уведомлений.
+    fn on_quote(&mut self, quote: &StockQuote) -> Vec<String> {
+        let mut lines = vec![];
+        let mut fired_once = vec![];
+        for alert in self.alerts.values_mut() {
+            if alert.ticker != quote.ticker || !alert.check(quote.price) {
+                continue;
+            }
+            lines.push(format!(
+                "{} {} TRIGGERED {} {} {} {}",
+                ALERT_CMD, alert.id, alert.ticker, alert.condition, quote.price, quote.timestamp
+            ));
+            if !alert.repeat {
+                fired_once.push(alert.id);
+            }
+        }
+        for id in fired_once {
+            self.alerts.remove(&id);
+        }
+        lines
+    }
+}
+
+/// Уведомления о цене управляющего соединения: условия проверяются по каждой
+/// котировке, срабатывания приходят строками `ALERT <номер> TRIGGERED ...` в то же соединение.
+pub(crate) struct AlertSession {
+    book: Arc<Mutex<AlertBook>>,
+    stop: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl AlertSession {
+    /// Запуск потока проверки уведомлений по рассылке котировок.
+    pub(crate) fn start(stream: Arc<Mutex<TcpStream>>, ctx: &ServerContext) -> Self {
+        let book = Arc::new(Mutex::new(AlertBook::default()));
+        let (stop, stopped) = channel::bounded(0);
+        let market = ctx.broadcast.subscribe_session();
+        let handle = {
+            let book = book.clone();
+            let ctx = ctx.clone();
+            thread::spawn(move || run_alerts(book, stopped, market, stream, ctx))
+        };
+        Self { book, stop, handle }
+    }
+
+    /// Новое уведомление, для `pct-move` опорная цена - последняя котировка тикера,
+    /// `above`/`below` срабатывают только при пересечении уровня после этой котировки.
+    /// Возвращает номер уведомления.
+    pub(crate) fn add(
+        &self,
+        ticker: String,
+        condition: AlertCondition,
+        repeat: bool,
+        ctx: &ServerContext,
+    ) -> u64 {
+        let reference = ctx.quote_cache.get(&ticker).map(|quote| quote.price);
+        let mut book = self.book.lock().unwrap();
+        book.next_alert_id += 1;
+        let id = book.next_alert_id;
+        book.alerts
+            .insert(id, Alert::new(id, ticker, condition, repeat, reference));
+        id
+    }
+
+    /// Удаление уведомления, `false` - такого нет или одноразовое уже сработало.
+    pub(crate) fn remove(&self, id: u64) -> bool {
+        self.book.lock().unwrap().alerts.remove(&id).is_some()
+    }
+
+    /// Действующие уведомления: `<номер> <тикер> <условие> once|repeat`.
+    pub(crate) fn list(&self) -> Vec<String> {
+        self.book
+            .lock()
+            .unwrap()
+            .alerts
+            .values()
+            .map(|alert| {
+                format!(
+                    "{} {} {} {}",
+                    alert.id,
+                    alert.ticker,
+                    alert.condition,
+                    alert.mode()
+                )
+            })
+            .collect()
+    }
+
+    /// Остановка проверки при закрытии соединения.
+    pub(crate) fn stop(self) {
+        let Self { stop, handle, .. } = self;
+        drop(stop);
+        handle.join().unwrap();
+    }
+}
+
+fn run_alerts(
+    book: Arc<Mutex<AlertBook>>,
+    stopped: Receiver<()>,
+    market: Subscription,
+    stream: Arc<Mutex<TcpStream>>,
+    ctx: ServerContext,
+) {
+    while ctx.running.load(Ordering::SeqCst) {
+        let lines = crossbeam::select! {
+            recv(stopped) -> _ => break,
+            recv(market.receiver()) -> message => match message.as_ref().map(|m| m.message()) {
+                Ok(Message::Quote(quote)) => book.lock().unwrap().on_quote(quote),
+                Ok(_) => continue,
+                Err(_) => break,
+            },
+            default(Duration::from_secs(1)) => continue,
+        };
+        if lines.is_empty() {
+            continue;
+        }
+
+        let result = {
+            let mut stream = stream.lock().unwrap();
+            lines
+                .iter()
+                .try_for_each(|line| writeln!(stream, "{}", line))
+                .and_then(|_| stream.flush())
+        };
+        if let Err(e) = result {
+            log::info!("Уведомления о цене не отправлены: {}", e);
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(value: &str) -> Price {
+        value.parse().unwrap()
+    }
+
+    fn alert(condition: AlertCondition, last: Option<&str>) -> Alert {
+        Alert::new(1, "AAPL".to_string(), condition, true, last.map(price))
+    }
+
+    #[test]
+    fn level_already_reached_fires_after_cross() {
+        let mut above = alert(AlertCondition::Above(price("200")), Some("210"));
+        assert!(!above.check(price("205")));
+        assert!(!above.check(price("199.99")));
+        assert!(above.check(price("200")));
+        assert!(!above.check(price("201")));
+
+        let mut below = alert(AlertCondition::Below(price("150")), Some("140"));
+        assert!(!below.check(price("145")));
+        assert!(!below.check(price("151")));
+        assert!(below.check(price("149")));
+    }
+
+    #[test]
+    fn level_not_reached_fires_on_first_match() {
+        let mut above = alert(AlertCondition::Above(price("200")), Some("190"));
+        assert!(!above.check(price("199")));
+        assert!(above.check(price("201")));
+
+        let mut unknown = alert(AlertCondition::Above(price("200")), None);
+        assert!(unknown.check(price("201")));
+    }
+
+    #[test]
+    fn pct_move_from_last_price() {
+        let mut alert = alert(AlertCondition::PctMove(2.0), Some("100"));
+        assert!(!alert.check(price("101.5")));
+        assert!(alert.check(price("98")));
+        assert!(!alert.check(price("99")));
+        assert!(alert.check(price("100")));
+    }
+}
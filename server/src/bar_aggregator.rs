@@ -0,0 +1,400 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+use quote_lib::{Bar, BarInterval, Message, Timestamp, Trade};
+
+/// Свеча в построении: время первой и последней сделки определяют open и close
+/// независимо от порядка прихода сделок.
+struct BarState {
+    bar: Bar,
+    first: Timestamp,
+    last: Timestamp,
+    /// Свеча закрыта и разослана, дальнейшие изменения рассылаются поправками.
+    published: bool,
+}
+
+impl BarState {
+    fn new(trade: &Trade, interval: BarInterval, start: Timestamp) -> Self {
+        Self {
+            bar: Bar {
+                ticker: trade.ticker.clone(),
+                interval,
+                start,
+                open: trade.price,
+                high: trade.price,
+                low: trade.price,
+                close: trade.price,
+                volume: trade.size,
+            },
+            first: trade.timestamp,
+            last: trade.timestamp,
+            published: false,
+        }
+    }
+
+    fn add(&mut self, trade: &Trade) {
+        let bar = &mut self.bar;
+        bar.high = bar.high.max(trade.price);
+        bar.low = bar.low.min(trade.price);
+        bar.volume += trade.size;
+        if trade.timestamp < self.first {
+            self.first = trade.timestamp;
+            bar.open = trade.price;
+        }
+        if trade.timestamp >= self.last {
+            self.last = trade.timestamp;
+            bar.close = trade.price;
+        }
+    }
+}
+
+struct AggregatorState {
+    /// Наибольшее время среди полученных сообщений.
+    watermark: Timestamp,
+    /// Открытые и последние закрытые свечи по тикеру и интервалу, по времени начала.
+    bars: HashMap<(String, BarInterval), BTreeMap<Timestamp, BarState>>,
+}
+
+/// Построение свечей по сделкам потока.
+///
+/// Время потока задается самым поздним временем котировок и сделок: свеча закрывается,
+/// когда оно уходит за конец интервала больше чем на `lateness`. Сделки, опоздавшие сильнее,
+/// меняют уже разосланную свечу, и она рассылается повторно как поправка,
+/// пока свеча хранится в истории.
+pub(crate) struct BarAggregator {
+    intervals: Vec<BarInterval>,
+    /// Ожидание опоздавших сделок перед закрытием свечи, мс.
+    lateness: u64,
+    /// Число хранимых свечей на тикер и интервал.
+    capacity: usize,
+    state: Mutex<AggregatorState>,
+}
+
+impl BarAggregator {
+    pub(crate) fn new(intervals: Vec<BarInterval>, lateness: u64, capacity: usize) -> Self {
+        Self {
+            intervals,
+            lateness,
+            capacity: capacity.max(1),
+            state: Mutex::new(AggregatorState {
+                watermark: Timestamp::from_millis(0),
+                bars: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Интервалы, по которым строятся свечи.
+    pub(crate) fn intervals(&self) -> &[BarInterval] {
+        &self.intervals
+    }
+
+    /// Учет сообщения потока, возвращает закрытые свечи и поправки для рассылки.
+    pub(crate) fn update(&self, message: &Message) -> Vec<Bar> {
+        if self.intervals.is_empty() {
+            return vec![];
+        }
+        let timestamp = match message {
+            Message::Trade(trade) => trade.timestamp,
+            Message::Quote(quote) => quote.timestamp,
+            _ => return vec![],
+        };
+        let mut state = self.state.lock().unwrap();
+        state.watermark = state.watermark.max(timestamp);
+        let watermark = state.watermark;
+
+        let mut result = vec![];
+        for &interval in &self.intervals {
+            if let Message::Trade(trade) = message
+                && let Some(bar) = self.add_trade(&mut state, trade, interval)
+            {
+                result.push(bar);
+            }
+        }
+
+        // Закрытие свечей, время которых вышло
+        for ((_, interval), bars) in state.bars.iter_mut() {
+            let mut closed: Vec<Bar> = bars
+                .values_mut()
+                .rev()
+                .take_while(|state| !state.published)
+                .filter(|state| self.is_closed(state.bar.start, *interval, watermark))
+                .map(|state| {
+                    state.published = true;
+                    state.bar.clone()
+                })
+                .collect();
+            closed.reverse();
+            result.extend(closed);
+        }
+        result
+    }
+
+    /// Добавление сделки в свечу интервала, возвращает поправку к закрытой свече.
+    fn add_trade(
+        &self,
+        state: &mut AggregatorState,
+        trade: &Trade,
+        interval: BarInterval,
+    ) -> Option<Bar> {
+        let start = interval.start_of(trade.timestamp);
+        let watermark = state.watermark;
+        let bars = state
+            .bars
+            .entry((trade.ticker.clone(), interval))
+            .or_default();
+        if bars.len() >= self.capacity
+            && bars
+                .first_key_value()
+                .is_some_and(|(first, _)| start < *first)
+        {
+            log::debug!(
+                "Сделка {} {} старше истории свечей {}",
+                trade.ticker,
+                trade.timestamp,
+                interval
+            );
+            return None;
+        }
+
+        let bar = bars
+            .entry(start)
+            .and_modify(|bar| bar.add(trade))
+            .or_insert_with(|| BarState::new(trade, interval, start));
+        let correction = if bar.published {
+            Some(bar.bar.clone())
+        } else if self.is_closed(start, interval, watermark) {
+            // Опоздавшая сделка в интервале без свечи
+            bar.published = true;
+            Some(bar.bar.clone())
+        } else {
+            None
+        };
+
+        while bars.len() > self.capacity {
+            bars.pop_first();
+        }
+        correction
+    }
+
+    fn is_closed(&self, start: Timestamp, interval: BarInterval, watermark: Timestamp) -> bool {
+        start.as_millis() + interval.as_millis() + self.lateness <= watermark.as_millis()
+    }
+
+    /// Последние `count` закрытых свечей, от старых к новым.
+    pub(crate) fn last(&self, ticker: &str, interval: BarInterval, count: usize) -> Vec<Bar> {
+        let bars = self.published(ticker, interval);
+        bars[bars.len().saturating_sub(count)..].to_vec()
+    }
+
+    /// Закрытые свечи, начинающиеся не раньше `timestamp`, от старых к новым.
+    pub(crate) fn since(
+        &self,
+        ticker: &str,
+        interval: BarInterval,
+        timestamp: Timestamp,
+    ) -> Vec<Bar> {
+        self.published(ticker, interval)
+            .into_iter()
+            .filter(|bar| bar.start >= timestamp)
+            .collect()
+    }
+
+    fn published(&self, ticker: &str, interval: BarInterval) -> Vec<Bar> {
+        let state = self.state.lock().unwrap();
+        state
+            .bars
+            .get(&(ticker.to_string(), interval))
+            .map(|bars| {
+                bars.values()
+                    .filter(|state| state.published)
+                    .map(|state| state.bar.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quote_lib::{Price, StockQuote, Volume};
+
+    use super::*;
+
+    fn second() -> BarInterval {
+        "1s".parse().unwrap()
+    }
+
+    fn trade(ms: u64, price: &str, size: u64) -> Message {
+        Message::Trade(Trade {
+            ticker: "AAPL".to_string(),
+            price: price.parse().unwrap(),
+            size: Volume::new(size),
+            timestamp: Timestamp::from_millis(ms),
+        })
+    }
+
+    fn quote(ms: u64) -> Message {
+        Message::Quote(StockQuote {
+            ticker: "AAPL".to_string(),
+            price: Price::from_units(1_000_000),
+            volume: Volume::ZERO,
+            timestamp: Timestamp::from_millis(ms),
+            bid: Price::from_units(999_900),
+            ask: Price::from_units(1_000_100),
+            bid_size: Volume::ZERO,
+            ask_size: Volume::ZERO,
+        })
+    }
+
+    /// Свечи в 1 секунду с ожиданием опоздавших сделок 500 мс.
+    fn aggregator() -> BarAggregator {
+        BarAggregator::new(vec![second()], 500, 10)
+    }
+
+    fn ohlcv(bar: &Bar) -> (String, String, String, String, u64) {
+        (
+            bar.open.to_string(),
+            bar.high.to_string(),
+            bar.low.to_string(),
+            bar.close.to_string(),
+            bar.volume.units(),
+        )
+    }
+
+    #[test]
+    fn bar_closes_after_lateness() {
+        let bars = aggregator();
+        for message in [
+            trade(10_000, "100", 10),
+            trade(10_400, "105", 20),
+            trade(10_900, "101", 30),
+        ] {
+            assert!(bars.update(&message).is_empty());
+        }
+        // Конец интервала 11000, но свеча ждет опоздавших до 11500
+        assert!(bars.update(&quote(11_499)).is_empty());
+        let closed = bars.update(&quote(11_500));
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].start, Timestamp::from_millis(10_000));
+        assert_eq!(
+            ohlcv(&closed[0]),
+            (
+                "100.00".into(),
+                "105.00".into(),
+                "100.00".into(),
+                "101.00".into(),
+                60
+            )
+        );
+        // Повторно не рассылается
+        assert!(bars.update(&quote(12_000)).is_empty());
+    }
+
+    #[test]
+    fn out_of_order_trades_before_close() {
+        let bars = aggregator();
+        bars.update(&trade(10_500, "102", 1));
+        // Сделка раньше первой становится open, позже последней не пришла - close прежний
+        bars.update(&trade(10_100, "99", 1));
+        bars.update(&trade(10_300, "104", 1));
+        let closed = bars.update(&quote(11_500));
+        assert_eq!(
+            ohlcv(&closed[0]),
+            (
+                "99.00".into(),
+                "104.00".into(),
+                "99.00".into(),
+                "102.00".into(),
+                3
+            )
+        );
+    }
+
+    #[test]
+    fn late_trade_corrects_published_bar() {
+        let bars = aggregator();
+        bars.update(&trade(10_000, "100", 10));
+        bars.update(&trade(10_900, "101", 10));
+        assert_eq!(bars.update(&quote(11_600)).len(), 1);
+
+        // Опоздала больше чем на lateness: поправка к разосланной свече
+        let corrections = bars.update(&trade(10_950, "97", 5));
+        assert_eq!(corrections.len(), 1);
+        assert_eq!(corrections[0].start, Timestamp::from_millis(10_000));
+        assert_eq!(
+            ohlcv(&corrections[0]),
+            (
+                "100.00".into(),
+                "101.00".into(),
+                "97.00".into(),
+                "97.00".into(),
+                25
+            )
+        );
+        // История отдает исправленную свечу
+        let history = bars.last("AAPL", second(), 10);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].volume, Volume::new(25));
+    }
+
+    #[test]
+    fn late_trade_in_empty_closed_interval() {
+        let bars = aggregator();
+        bars.update(&trade(10_000, "100", 10));
+        bars.update(&quote(11_600));
+
+        let late = bars.update(&trade(8_200, "95", 3));
+        assert_eq!(late.len(), 1);
+        assert_eq!(late[0].start, Timestamp::from_millis(8_000));
+        assert_eq!(
+            ohlcv(&late[0]),
+            (
+                "95.00".into(),
+                "95.00".into(),
+                "95.00".into(),
+                "95.00".into(),
+                3
+            )
+        );
+        let starts: Vec<u64> = bars
+            .last("AAPL", second(), 10)
+            .iter()
+            .map(|bar| bar.start.as_millis())
+            .collect();
+        assert_eq!(starts, [8_000, 10_000]);
+        assert_eq!(
+            bars.since("AAPL", second(), Timestamp::from_millis(9_000))
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn trade_older_than_history_is_dropped() {
+        let bars = BarAggregator::new(vec![second()], 0, 2);
+        for ms in [10_000, 11_000, 12_000, 13_000] {
+            bars.update(&trade(ms, "100", 1));
+        }
+        assert!(bars.update(&trade(9_500, "90", 1)).is_empty());
+        let starts: Vec<u64> = bars
+            .last("AAPL", second(), 10)
+            .iter()
+            .map(|bar| bar.start.as_millis())
+            .collect();
+        assert_eq!(starts, [12_000]);
+    }
+
+    #[test]
+    fn open_bar_not_in_history() {
+        let bars = aggregator();
+        bars.update(&trade(10_000, "100", 1));
+        assert!(bars.last("AAPL", second(), 10).is_empty());
+        assert!(
+            BarAggregator::new(vec![], 0, 10)
+                .update(&trade(10_000, "100", 1))
+                .is_empty()
+        );
+    }
+}
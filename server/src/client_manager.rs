@@ -1,15 +1,32 @@
 use std::{
     collections::HashMap,
+    net::SocketAddr,
     sync::{Arc, RwLock},
     time::{Duration, Instant},
 };
 
+use quote_lib::{BarInterval, Message, MessageKind};
+
+use crate::stream_filter::StreamFilter;
+
 #[derive(Debug)]
 pub(crate) struct ClientSession {
-    // pub id: u64,
-    // pub udp_addr: SocketAddr,
+    /// Адрес управляющего соединения, запустившего поток.
+    pub peer_addr: Option<SocketAddr>,
+    /// Адрес получателя UDP потока, `None` - поток по соединению клиента.
+    pub udp_addr: Option<SocketAddr>,
     pub subscribed_tickers: Vec<String>,
+    /// Типы рыночных данных, по умолчанию только котировки.
+    pub subscribed_kinds: Vec<MessageKind>,
+    /// Интервалы свечей, пустой список - все интервалы.
+    pub bar_intervals: Vec<BarInterval>,
+    /// Ограничения потока котировок по тикерам.
+    pub stream_filter: StreamFilter,
     pub last_ping: Instant,
+    /// Отправлено сообщений клиенту.
+    pub sent: u64,
+    /// Сообщения, которые не удалось отправить.
+    pub send_errors: u64,
 }
 
 pub(crate) struct ClientManager {
@@ -27,30 +44,31 @@ impl ClientManager {
 
     pub(crate) fn add_client(
         &mut self,
-        // udp_addr: SocketAddr,
+        udp_addr: Option<SocketAddr>,
         subscribed_tickers: Vec<String>,
     ) -> u64 {
         let id = self.next_client_id;
         self.next_client_id += 1;
 
         let session = ClientSession {
-            // id,
-            // udp_addr,
+            peer_addr: None,
+            udp_addr,
             subscribed_tickers,
+            subscribed_kinds: vec![MessageKind::Quote],
+            bar_intervals: vec![],
+            stream_filter: StreamFilter::default(),
             last_ping: Instant::now(),
+            sent: 0,
+            send_errors: 0,
         };
 
         self.clients.write().unwrap().insert(id, session);
         id
     }
 
-    pub(crate) fn remove_client(&mut self, id: u64) {
-        self.clients
-            .write()
-            .unwrap()
-            .remove(&id)
-            // .expect("Client not found")
-            ;
+    /// Удаление клиента, `false` - клиент не найден.
+    pub(crate) fn remove_client(&mut self, id: u64) -> bool {
+        self.clients.write().unwrap().remove(&id).is_some()
     }
 
     pub(crate) fn update_ping(&mut self, id: u64) -> bool {
@@ -62,6 +80,15 @@ impl ClientManager {
         }
     }
 
+    /// Клиент зарегистрирован и присылал Ping не дольше `timeout` назад.
+    pub(crate) fn is_active(&self, id: u64, timeout: Duration) -> bool {
+        self.clients
+            .read()
+            .unwrap()
+            .get(&id)
+            .is_some_and(|client| client.last_ping.elapsed() <= timeout)
+    }
+
     pub(crate) fn get_inactive_clients(&self, timeout: Duration) -> Vec<u64> {
         let now = Instant::now();
         self.clients
@@ -78,11 +105,110 @@ impl ClientManager {
             .collect()
     }
 
-    pub(crate) fn check_client_ticker(&self, id: u64, ticket: &String) -> Option<bool> {
+    /// Добавление тикеров в подписку, возвращает текущую подписку клиента.
+    pub(crate) fn subscribe(&mut self, id: u64, tickers: &[String]) -> Option<Vec<String>> {
+        let mut clients = self.clients.write().unwrap();
+        let client = clients.get_mut(&id)?;
+        for ticker in tickers {
+            if !client.subscribed_tickers.contains(ticker) {
+                client.subscribed_tickers.push(ticker.clone());
+            }
+        }
+        Some(client.subscribed_tickers.clone())
+    }
+
+    /// Удаление тикеров из подписки, возвращает текущую подписку клиента.
+    pub(crate) fn unsubscribe(&mut self, id: u64, tickers: &[String]) -> Option<Vec<String>> {
+        let mut clients = self.clients.write().unwrap();
+        let client = clients.get_mut(&id)?;
+        client.subscribed_tickers.retain(|t| !tickers.contains(t));
+        Some(client.subscribed_tickers.clone())
+    }
+
+    /// Замена типов рыночных данных в подписке, `false` - клиент не найден.
+    pub(crate) fn set_kinds(&mut self, id: u64, kinds: Vec<MessageKind>) -> bool {
+        match self.clients.write().unwrap().get_mut(&id) {
+            Some(client) => {
+                client.subscribed_kinds = kinds;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Замена интервалов свечей в подписке, `false` - клиент не найден.
+    pub(crate) fn set_bar_intervals(&mut self, id: u64, intervals: Vec<BarInterval>) -> bool {
+        match self.clients.write().unwrap().get_mut(&id) {
+            Some(client) => {
+                client.bar_intervals = intervals;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Замена ограничений потока котировок, `false` - клиент не найден.
+    pub(crate) fn set_stream_filter(&mut self, id: u64, filter: StreamFilter) -> bool {
+        match self.clients.write().unwrap().get_mut(&id) {
+            Some(client) => {
+                client.stream_filter = filter;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Адрес управляющего соединения клиента, `false` - клиент не найден.
+    pub(crate) fn set_peer_addr(&mut self, id: u64, peer_addr: SocketAddr) -> bool {
+        match self.clients.write().unwrap().get_mut(&id) {
+            Some(client) => {
+                client.peer_addr = Some(peer_addr);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Адрес получателя UDP потока клиента, `None` - поток по соединению клиента или клиент не найден.
+    pub(crate) fn udp_addr(&self, id: u64) -> Option<SocketAddr> {
+        self.clients.read().unwrap().get(&id)?.udp_addr
+    }
+
+    /// Учет отправленных клиенту и не отправленных из-за ошибки сообщений.
+    pub(crate) fn record_sent(&mut self, id: u64, sent: u64, failed: u64) {
+        if let Some(client) = self.clients.write().unwrap().get_mut(&id) {
+            client.sent += sent;
+            client.send_errors += failed;
+        }
+    }
+
+    /// Ограничения потока котировок клиента.
+    pub(crate) fn stream_filter(&self, id: u64) -> Option<StreamFilter> {
         self.clients
             .read()
             .unwrap()
             .get(&id)
-            .map(|client| client.subscribed_tickers.contains(ticket))
+            .map(|client| client.stream_filter.clone())
+    }
+
+    /// Нужно ли отправлять сообщение клиенту: рыночные данные - по подписке,
+    /// статусы - всем, heartbeat потоки формируют сами.
+    pub(crate) fn check_client_message(&self, id: u64, message: &Message) -> Option<bool> {
+        let clients = self.clients.read().unwrap();
+        let client = clients.get(&id)?;
+        Some(match (message.kind(), message.ticker()) {
+            (Some(kind), Some(ticker)) => {
+                client.subscribed_kinds.contains(&kind)
+                    && client.subscribed_tickers.iter().any(|t| t == ticker)
+                    && match message {
+                        Message::Bar(bar) => {
+                            client.bar_intervals.is_empty()
+                                || client.bar_intervals.contains(&bar.interval)
+                        }
+                        _ => true,
+                    }
+            }
+            _ => matches!(message, Message::Status { .. }),
+        })
     }
 }
@@ -1,25 +1,182 @@
 use std::{
+    borrow::Cow,
     io::{BufRead, BufReader, Write},
-    net::{TcpStream, UdpSocket},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket},
     sync::{
         Arc, Mutex,
         atomic::{AtomicBool, Ordering},
     },
     thread::{self, JoinHandle},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use crossbeam::channel::Receiver;
-use quote_lib::{PING_MSG, PONG_MSG, SERVER_OK, STREAM_CMD, StockQuote};
+use crossbeam::channel::RecvTimeoutError;
+use quote_lib::{
+    ALERT_CMD, ALERTS_CMD, BARS_CMD, BarInterval, CANCEL_CMD, DEPTH_CMD, END_MSG, FORMAT_OPTION,
+    HEARTBEAT_MSG, HISTORY_CMD, MULTICAST_TRANSPORT, Message, MessageKind, ORDER_CMD, PING_MSG,
+    PONG_MSG, POSITIONS_CMD, Price, QuoteFormat, REPLAY_CMD, SEQ_OPTION, SERVER_OK, SNAPSHOT_CMD,
+    STREAM_CMD, SharedMessage, StockQuote, TCP_TRANSPORT, TYPES_OPTION, Volume,
+};
 
-use crate::client_manager::ClientManager;
+use crate::{
+    admin::ServerStats,
+    alerts::{AlertCondition, AlertSession},
+    bar_aggregator::BarAggregator,
+    client_manager::ClientManager,
+    composites::CompositeEngine,
+    indicators::IndicatorEngine,
+    journal::{self, ReplayControl},
+    multicast::{self, MulticastGroup},
+    order_book::BookCache,
+    quote_broadcast::{QuoteBroadcast, Subscription},
+    quote_cache::QuoteCache,
+    quote_history::QuoteHistory,
+    stream_filter::{QuoteThrottle, StreamFilter},
+    trading::TradingSession,
+};
 
-pub(crate) fn handle_client(
-    stream: TcpStream,
-    client_manager: Arc<Mutex<ClientManager>>,
-    broadcast_receiver: Receiver<StockQuote>,
-    running: Arc<AtomicBool>,
-) {
+/// Общие данные сервера для обработчиков клиентов.
+#[derive(Clone)]
+pub(crate) struct ServerContext {
+    pub(crate) client_manager: Arc<Mutex<ClientManager>>,
+    pub(crate) broadcast: Arc<QuoteBroadcast>,
+    pub(crate) quote_cache: Arc<QuoteCache>,
+    pub(crate) quote_history: Arc<QuoteHistory>,
+    pub(crate) book_cache: Arc<BookCache>,
+    pub(crate) bar_aggregator: Arc<BarAggregator>,
+    pub(crate) indicators: Arc<IndicatorEngine>,
+    pub(crate) composites: Arc<CompositeEngine>,
+    /// Адрес для UDP сокетов отправки.
+    pub(crate) udp_bind: IpAddr,
+    pub(crate) multicast_groups: Arc<Vec<MulticastGroup>>,
+    /// Интервал heartbeat для потоков по TCP, `None` - не отправлять.
+    pub(crate) tcp_heartbeat: Option<Duration>,
+    /// Отправлять последние котировки в начале каждого нового потока.
+    pub(crate) send_initial_snapshot: bool,
+    /// Число уровней стакана с каждой стороны в снимках для новых подписчиков.
+    pub(crate) book_depth: usize,
+    /// Управление воспроизведением, если сервер воспроизводит журнал.
+    pub(crate) replay: Option<Arc<ReplayControl>>,
+    pub(crate) stats: Arc<ServerStats>,
+    pub(crate) running: Arc<AtomicBool>,
+}
+
+impl ServerContext {
+    /// Публикация сообщения от источника и рассчитанных по нему производных данных:
+    /// котировок индикаторов и закрытых по времени сообщения свечей.
+    pub(crate) fn publish(&self, message: &Message) {
+        self.publish_one(message);
+        for quote in self.indicators.update(message) {
+            self.publish_one(&Message::Quote(quote));
+        }
+        for bar in self.bar_aggregator.update(message) {
+            self.broadcast.send(&Message::Bar(bar));
+        }
+    }
+
+    /// Пересчет составных инструментов после такта источника и публикация их котировок
+    /// как обычных, в том числе с индикаторами по ним.
+    pub(crate) fn publish_composites(&self) {
+        for quote in self.composites.compute(&self.quote_cache) {
+            self.publish(&Message::Quote(quote));
+        }
+    }
+
+    /// Котировки попадают в кеш и историю, снимки и обновления стакана - в стакан,
+    /// все сообщения рассылаются подписчикам.
+    fn publish_one(&self, message: &Message) {
+        match message {
+            Message::Quote(quote) => {
+                self.stats.count_quote();
+                self.quote_cache.update(quote);
+                self.quote_history.push(quote);
+            }
+            Message::BookSnapshot(snapshot) => self.book_cache.apply_snapshot(snapshot),
+            Message::BookUpdate(update) => self.book_cache.apply_update(update),
+            _ => {}
+        }
+        self.broadcast.send(message);
+    }
+
+    /// Рассчитано ли сообщение сервером из других сообщений потока:
+    /// такие сообщения не пишутся в журнал и строятся заново при воспроизведении.
+    pub(crate) fn is_derived(&self, message: &Message) -> bool {
+        match message {
+            Message::Bar(_) => true,
+            Message::Quote(quote) => {
+                self.indicators.is_derived(&quote.ticker)
+                    || self.composites.is_derived(&quote.ticker)
+            }
+            _ => false,
+        }
+    }
+
+    /// Начальный снимок котировок для нового потока, пустой если отключен.
+    pub(crate) fn initial_snapshot(&self, tickers: &[String]) -> Vec<StockQuote> {
+        if self.send_initial_snapshot {
+            self.quote_cache.snapshot(tickers)
+        } else {
+            vec![]
+        }
+    }
+
+    /// Начальные сообщения нового потока: котировки, если снимок включен,
+    /// и снимки стакана при подписке на `depth` - без них обновления стакана не применить.
+    /// Подписка на рассылку должна быть оформлена до вызова, чтобы не потерять обновления.
+    pub(crate) fn initial_messages(
+        &self,
+        tickers: &[String],
+        kinds: &[MessageKind],
+    ) -> Vec<Message> {
+        let mut messages = vec![];
+        if kinds.contains(&MessageKind::Quote) {
+            messages.extend(
+                self.initial_snapshot(tickers)
+                    .into_iter()
+                    .map(Message::Quote),
+            );
+        }
+        if kinds.contains(&MessageKind::Depth) {
+            messages.extend(
+                self.book_cache
+                    .snapshots(tickers, self.book_depth)
+                    .into_iter()
+                    .map(Message::BookSnapshot),
+            );
+        }
+        messages
+    }
+}
+
+/// Результат обработки команды клиента.
+struct CommandOutput {
+    /// Ответ клиенту без `OK` и перевода строки.
+    response: Option<String>,
+    /// Строки после ответа, завершаются `END`.
+    body: Option<Vec<String>>,
+    /// Запущенный командой поток отправки котировок.
+    stream: Option<(u64, JoinHandle<()>)>,
+}
+
+/// Сессии управляющего соединения, создаются первой командой и живут до его закрытия.
+#[derive(Default)]
+struct ConnectionSessions {
+    trading: Option<TradingSession>,
+    alerts: Option<AlertSession>,
+}
+
+impl ConnectionSessions {
+    fn stop(self) {
+        if let Some(trading) = self.trading {
+            trading.stop();
+        }
+        if let Some(alerts) = self.alerts {
+            alerts.stop();
+        }
+    }
+}
+
+pub(crate) fn handle_client(stream: TcpStream, ctx: ServerContext) {
     let peer_addr = match stream.peer_addr() {
         Ok(addr) => addr,
         Err(e) => {
@@ -37,8 +194,9 @@ pub(crate) fn handle_client(
         }
     };
 
-    let mut wr_stream = match stream.try_clone() {
-        Ok(s) => s,
+    // Запись в соединение разделяется с потоком котировок по TCP
+    let wr_stream = match stream.try_clone() {
+        Ok(s) => Arc::new(Mutex::new(s)),
         Err(e) => {
             log::error!("Failed to clone stream: {}", e);
             return;
@@ -47,8 +205,9 @@ pub(crate) fn handle_client(
 
     let mut reader = BufReader::new(stream_clone);
     let mut handles = vec![];
+    let mut sessions = ConnectionSessions::default();
 
-    while running.load(Ordering::SeqCst) {
+    while ctx.running.load(Ordering::SeqCst) {
         let mut line = String::new();
         match reader.read_line(&mut line) {
             Ok(0) => {
@@ -62,25 +221,41 @@ pub(crate) fn handle_client(
                 }
 
                 log::info!("Запрос {}: {}", peer_addr, line);
+                // Поток котировок по TCP начинает писать только после ответа на команду
+                let mut writer = wr_stream.lock().unwrap();
                 let mut response = SERVER_OK.to_string();
-                if let Some((client_id, handle)) = process_command(
-                    line,
-                    &peer_addr,
-                    &client_manager,
-                    broadcast_receiver.clone(),
-                    running.clone(),
-                ) {
-                    log::info!("Запуск команды от клиента: {}", client_id);
-                    handles.push(handle);
+                if let Some(output) =
+                    process_command(line, &peer_addr, &ctx, &wr_stream, &mut sessions)
+                {
+                    if let Some((client_id, handle)) = output.stream {
+                        log::info!("Запуск команды от клиента: {}", client_id);
+                        ctx.client_manager
+                            .lock()
+                            .unwrap()
+                            .set_peer_addr(client_id, peer_addr);
+                        handles.push((client_id, handle));
+                    }
+                    if let Some(text) = output.response {
+                        response.push(' ');
+                        response.push_str(&text);
+                    }
+                    if let Some(body) = output.body {
+                        for line in body {
+                            response.push('\n');
+                            response.push_str(&line);
+                        }
+                        response.push('\n');
+                        response.push_str(END_MSG);
+                    }
                 } else {
                     response = format!("Неизвестная команда: {}", line);
                 }
                 response.push('\n');
-                if let Err(e) = wr_stream.write_all(response.as_bytes()) {
+                if let Err(e) = writer.write_all(response.as_bytes()) {
                     log::error!("Error sending response: {} ", e);
                     break;
                 }
-                let _ = wr_stream.flush();
+                let _ = writer.flush();
             }
             Err(e) => {
                 if e.kind() == std::io::ErrorKind::WouldBlock {
@@ -92,7 +267,18 @@ pub(crate) fn handle_client(
             }
         }
     }
-    for handle in handles {
+    sessions.stop();
+    // Потоки по закрытому соединению без heartbeat и котировок не заметят закрытия,
+    // UDP потоки завершаются сами по отсутствию Ping
+    {
+        let mut manager = ctx.client_manager.lock().unwrap();
+        for (client_id, _) in &handles {
+            if manager.udp_addr(*client_id).is_none() {
+                manager.remove_client(*client_id);
+            }
+        }
+    }
+    for (_, handle) in handles {
         handle.join().unwrap();
     }
     log::info!("Завершение потока обработки команд: {}", peer_addr);
@@ -100,61 +286,577 @@ pub(crate) fn handle_client(
 
 fn process_command(
     command: &str,
-    client_addr: &std::net::SocketAddr,
-    client_manager: &Arc<Mutex<ClientManager>>,
-    broadcast_sender: Receiver<StockQuote>,
-    running: Arc<AtomicBool>,
-) -> Option<(u64, JoinHandle<()>)> {
+    client_addr: &SocketAddr,
+    ctx: &ServerContext,
+    wr_stream: &Arc<Mutex<TcpStream>>,
+    sessions: &mut ConnectionSessions,
+) -> Option<CommandOutput> {
     let parts: Vec<&str> = command.split_whitespace().collect();
 
-    if parts.len() < 3 || parts[0] != STREAM_CMD {
+    let output = match parts.first().copied() {
+        Some(STREAM_CMD) => process_stream(&parts, client_addr, ctx, wr_stream),
+        Some(DEPTH_CMD) => process_depth(&parts, ctx, wr_stream),
+        Some(BARS_CMD) => process_bars(&parts, ctx, wr_stream),
+        Some(SNAPSHOT_CMD) => process_snapshot(&parts, ctx),
+        Some(HISTORY_CMD) => process_history(&parts, ctx),
+        Some(REPLAY_CMD) => process_replay(&parts, ctx),
+        Some(ORDER_CMD) => {
+            let session = sessions
+                .trading
+                .get_or_insert_with(|| TradingSession::start(wr_stream.clone(), ctx));
+            process_order(&parts, ctx, session)
+        }
+        Some(CANCEL_CMD) => process_cancel(&parts, sessions.trading.as_ref()?),
+        Some(POSITIONS_CMD) if parts.len() == 1 => {
+            let session = sessions
+                .trading
+                .get_or_insert_with(|| TradingSession::start(wr_stream.clone(), ctx));
+            Some(CommandOutput {
+                response: None,
+                body: Some(session.positions(ctx)),
+                stream: None,
+            })
+        }
+        Some(ALERT_CMD) if parts.get(1) == Some(&"DEL") => {
+            process_alert_del(&parts, sessions.alerts.as_ref()?)
+        }
+        Some(ALERT_CMD) => process_alert(&parts, ctx, wr_stream, &mut sessions.alerts),
+        Some(ALERTS_CMD) if parts.len() == 1 => Some(CommandOutput {
+            response: None,
+            body: Some(
+                sessions
+                    .alerts
+                    .as_ref()
+                    .map(AlertSession::list)
+                    .unwrap_or_default(),
+            ),
+            stream: None,
+        }),
+        _ => None,
+    };
+    if output.is_none() {
         log::error!("Некорректная команда от {}: {}", client_addr, command);
+    }
+    output
+}
+
+fn parse_tickers(tickers: &str) -> Vec<String> {
+    tickers
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Опции команды после обязательных аргументов.
+struct CommandOptions {
+    /// `format=v2`, без опции - первая версия текста.
+    format: QuoteFormat,
+    /// `types=quotes,trades,depth,bars`, без опции - только котировки.
+    kinds: Vec<MessageKind>,
+    /// `min-change=0.1%`, `max-rate=5`, `conflate=250ms`, в том числе для тикера: `max-rate:AAPL=2`.
+    filter: StreamFilter,
+    /// `seq` - номер сообщения в потоке клиента, только для UDP.
+    sequence: bool,
+}
+
+/// Разбор опций команды, неизвестная опция или значение - ошибка команды.
+fn parse_options(options: &[&str]) -> Option<CommandOptions> {
+    let mut result = CommandOptions {
+        format: QuoteFormat::default(),
+        kinds: vec![MessageKind::Quote],
+        filter: StreamFilter::default(),
+        sequence: false,
+    };
+    for option in options {
+        if *option == SEQ_OPTION {
+            result.sequence = true;
+        } else if let Some(format) = option.strip_prefix(FORMAT_OPTION) {
+            result.format = format.parse().ok()?;
+        } else if let Some(kinds) = option.strip_prefix(TYPES_OPTION) {
+            result.kinds = MessageKind::parse_list(kinds).ok()?;
+        } else if let Err(e) = result.filter.parse_option(option)? {
+            log::error!("{}", e);
+            return None;
+        }
+    }
+    if let Err(e) = result.filter.validate() {
+        log::error!("{}", e);
         return None;
     }
+    Some(result)
+}
 
-    let udp_url = parts[1];
-    let udp_addr = parse_udp_address(udp_url)?;
+/// Первая версия текста передает только котировки: сделки, стакан, свечи
+/// и статусы в ней не кодируются.
+fn check_format_kinds(format: QuoteFormat, kinds: &[MessageKind]) -> Result<(), String> {
+    match kinds.iter().find(|kind| **kind != MessageKind::Quote) {
+        Some(kind) if format == QuoteFormat::TextV1 => Err(format!(
+            "Тип {} не передается в формате {}, нужен {}v2 или {}bin",
+            kind, format, FORMAT_OPTION, FORMAT_OPTION
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Текстовый формат для построчной передачи, двоичный по TCP не поддерживается.
+/// Ограничения потока к ответам на запрос не применяются.
+fn parse_text_format(options: &[&str]) -> Option<QuoteFormat> {
+    parse_options(options)
+        .filter(|options| options.filter.is_empty() && !options.sequence)
+        .map(|options| options.format)
+        .filter(QuoteFormat::is_text)
+}
 
-    let tickers: Vec<String> = parts[2].split(',').map(|s| s.trim().to_string()).collect();
+/// Строки котировок для многострочного ответа.
+fn encode_lines(quotes: &[StockQuote], format: QuoteFormat) -> Vec<String> {
+    quotes
+        .iter()
+        .map(|q| String::from_utf8_lossy(&q.encode(format)).into_owned())
+        .collect()
+}
+
+/// `SNAPSHOT AAPL,MSFT [format=v2]` - последние котировки, по строке на котировку.
+fn process_snapshot(parts: &[&str], ctx: &ServerContext) -> Option<CommandOutput> {
+    if parts.len() < 2 {
+        return None;
+    }
+    let tickers = parse_tickers(parts[1]);
+    if tickers.is_empty() {
+        return None;
+    }
+    let format = parse_text_format(&parts[2..])?;
+
+    let quotes = ctx.quote_cache.snapshot(&tickers);
+    Some(CommandOutput {
+        response: None,
+        body: Some(encode_lines(&quotes, format)),
+        stream: None,
+    })
+}
+
+/// `HISTORY AAPL 100` - последние 100 котировок,
+/// `HISTORY AAPL since=1700000000` - котировки начиная с указанного времени,
+/// после можно указать `format=v2`.
+fn process_history(parts: &[&str], ctx: &ServerContext) -> Option<CommandOutput> {
+    if parts.len() < 3 {
+        return None;
+    }
+    let format = parse_text_format(&parts[3..])?;
+    let ticker = parts[1];
+    let quotes = match parts[2].strip_prefix("since=") {
+        Some(timestamp) => ctx.quote_history.since(ticker, timestamp.parse().ok()?),
+        None => ctx.quote_history.last(ticker, parts[2].parse().ok()?),
+    };
+    Some(CommandOutput {
+        response: None,
+        body: Some(encode_lines(&quotes, format)),
+        stream: None,
+    })
+}
+
+/// Управление воспроизведением журнала:
+/// `REPLAY PAUSE`, `REPLAY RESUME`, `REPLAY SPEED 2.5`, `REPLAY SEEK <время получения, мс>`, `REPLAY STATUS`.
+fn process_replay(parts: &[&str], ctx: &ServerContext) -> Option<CommandOutput> {
+    let control = ctx.replay.as_ref()?;
+    let mut response = None;
+    match (parts.get(1).copied()?, parts.get(2)) {
+        ("PAUSE", None) => control.set_paused(true),
+        ("RESUME", None) => control.set_paused(false),
+        ("SPEED", Some(speed)) => {
+            control.set_speed(journal::parse_replay_speed(speed).ok()?);
+        }
+        ("SEEK", Some(recv_ms)) => control.seek(recv_ms.parse().ok()?),
+        ("STATUS", None) => response = Some(control.status()),
+        _ => return None,
+    }
+    Some(CommandOutput {
+        response,
+        body: None,
+        stream: None,
+    })
+}
+
+/// `ORDER BUY AAPL 100` - рыночная заявка, `ORDER SELL AAPL 100 150.25` - лимитная,
+/// принимается только по тикеру с котировкой, ответ `OK <номер заявки>`.
+fn process_order(
+    parts: &[&str],
+    ctx: &ServerContext,
+    session: &TradingSession,
+) -> Option<CommandOutput> {
+    let (side, ticker, size, limit) = match parts {
+        [_, side, ticker, size] => (side, ticker, size, None),
+        [_, side, ticker, size, limit] => (side, ticker, size, Some(limit.parse().ok()?)),
+        _ => return None,
+    };
+    let side = side.parse().ok()?;
+    let size: Volume = size.parse().ok()?;
+    if size == Volume::ZERO || limit.is_some_and(|price: Price| price <= Price::ZERO) {
+        return None;
+    }
+    ctx.quote_cache.get(ticker)?;
+
+    let id = session.order(side, ticker.to_string(), size, limit, ctx);
+    Some(CommandOutput {
+        response: Some(id.to_string()),
+        body: None,
+        stream: None,
+    })
+}
+
+/// `CANCEL 3` - отмена ожидающей лимитной заявки.
+fn process_cancel(parts: &[&str], session: &TradingSession) -> Option<CommandOutput> {
+    let [_, id] = parts else {
+        return None;
+    };
+    if !session.cancel(id.parse().ok()?) {
+        return None;
+    }
+    Some(CommandOutput {
+        response: None,
+        body: None,
+        stream: None,
+    })
+}
+
+/// `ALERT AAPL above 200`, `ALERT AAPL below 150 repeat`, `ALERT AAPL pct-move 2.5 once` -
+/// уведомление по тикеру с котировкой, по умолчанию одноразовое, ответ `OK <номер>`.
+/// Повторяющееся уведомление об уровне срабатывает снова после выхода цены из условия.
+fn process_alert(
+    parts: &[&str],
+    ctx: &ServerContext,
+    wr_stream: &Arc<Mutex<TcpStream>>,
+    alerts: &mut Option<AlertSession>,
+) -> Option<CommandOutput> {
+    let (ticker, kind, value, repeat) = match parts {
+        [_, ticker, kind, value] => (ticker, kind, value, false),
+        [_, ticker, kind, value, "once"] => (ticker, kind, value, false),
+        [_, ticker, kind, value, "repeat"] => (ticker, kind, value, true),
+        _ => return None,
+    };
+    let condition = AlertCondition::parse(kind, value)?;
+    ctx.quote_cache.get(ticker)?;
+
+    let session = alerts.get_or_insert_with(|| AlertSession::start(wr_stream.clone(), ctx));
+    let id = session.add(ticker.to_string(), condition, repeat, ctx);
+    Some(CommandOutput {
+        response: Some(id.to_string()),
+        body: None,
+        stream: None,
+    })
+}
+
+/// `ALERT DEL 3` - удаление уведомления.
+fn process_alert_del(parts: &[&str], session: &AlertSession) -> Option<CommandOutput> {
+    let [_, _, id] = parts else {
+        return None;
+    };
+    if !session.remove(id.parse().ok()?) {
+        return None;
+    }
+    Some(CommandOutput {
+        response: None,
+        body: None,
+        stream: None,
+    })
+}
+
+/// `STREAM udp://127.0.0.1:34254 AAPL,MSFT`, `STREAM tcp AAPL,MSFT` или `STREAM multicast AAPL,MSFT`,
+/// после тикеров можно указать формат `format=v2` (`format=bin` только для UDP)
+/// и типы сообщений `types=quotes,trades,depth,bars` (свечи всех интервалов).
+/// Поток котировок ограничивается опциями `min-change=0.1%` (изменение цены от отправленной),
+/// `max-rate=5` (котировок тикера в секунду) или `conflate=250ms` (последняя котировка за интервал),
+/// опция для одного тикера: `min-change:AAPL=0.5`.
+/// Сделки, стакан и свечи (`types=`) требуют `format=v2` или `format=bin`.
+/// Опция `seq` нумерует сообщения UDP потока, чтобы клиент видел потери и перестановки.
+/// Формат multicast задается сервером, опция для него проверяется, но не влияет,
+/// ограничения потока и номера для multicast не поддерживаются.
+fn process_stream(
+    parts: &[&str],
+    client_addr: &SocketAddr,
+    ctx: &ServerContext,
+    wr_stream: &Arc<Mutex<TcpStream>>,
+) -> Option<CommandOutput> {
+    if parts.len() < 3 {
+        return None;
+    }
+
+    let tickers = parse_tickers(parts[2]);
     if tickers.is_empty() {
         return None;
     }
+    let CommandOptions {
+        format,
+        kinds,
+        filter,
+        sequence,
+    } = parse_options(&parts[3..])?;
 
+    if sequence && (parts[1] == MULTICAST_TRANSPORT || parts[1] == TCP_TRANSPORT) {
+        log::error!(
+            "Номера сообщений поддерживаются только для UDP от {}",
+            client_addr
+        );
+        return None;
+    }
+
+    if parts[1] == MULTICAST_TRANSPORT {
+        if !filter.is_empty() {
+            log::error!(
+                "Ограничения потока не поддерживаются для multicast от {}",
+                client_addr
+            );
+            return None;
+        }
+        let groups = multicast::groups_for_tickers(&ctx.multicast_groups, &tickers);
+        if groups.is_empty() {
+            log::error!(
+                "Нет multicast групп для тикеров {:?} от {}",
+                tickers,
+                client_addr
+            );
+            return None;
+        }
+        return Some(CommandOutput {
+            response: Some(multicast::format_reply(&groups)),
+            body: None,
+            stream: None,
+        });
+    }
+
+    if let Err(e) = check_format_kinds(format, &kinds) {
+        log::error!("{} от {}", e, client_addr);
+        return None;
+    }
+
+    if parts[1] == TCP_TRANSPORT {
+        if !format.is_text() {
+            log::error!(
+                "Формат {} не поддерживается по TCP от {}",
+                format,
+                client_addr
+            );
+            return None;
+        }
+        return Some(start_tcp_stream(
+            tickers,
+            kinds,
+            vec![],
+            filter,
+            format,
+            ctx,
+            wr_stream,
+        ));
+    }
+
+    let udp_url = parts[1];
+    let udp_addr = parse_udp_address(udp_url)?;
+    let Some(bind_addr) = udp_bind_address(ctx.udp_bind, &udp_addr) else {
+        log::error!(
+            "UDP адрес {} недоступен с адреса отправки {}",
+            udp_addr,
+            ctx.udp_bind
+        );
+        return None;
+    };
+
+    let receiver = ctx.broadcast.subscribe_client();
+    let initial = ctx.initial_messages(&tickers, &kinds);
     let client_id = {
-        let mut manager = client_manager.lock().unwrap();
-        manager.add_client(tickers)
+        let mut manager = ctx.client_manager.lock().unwrap();
+        let client_id = manager.add_client(Some(udp_addr), tickers);
+        manager.set_kinds(client_id, kinds);
+        manager.set_stream_filter(client_id, filter);
+        client_id
     };
 
     let handle = start_client_stream_thread(
         client_id,
+        bind_addr,
         udp_addr,
-        broadcast_sender,
-        client_manager.clone(),
-        running,
+        receiver,
+        initial,
+        UdpFraming { format, sequence },
+        ctx,
     );
 
-    Some((client_id, handle))
+    Some(CommandOutput {
+        response: None,
+        body: None,
+        stream: Some((client_id, handle)),
+    })
 }
 
-fn parse_udp_address(udp_url: &str) -> Option<std::net::SocketAddr> {
-    if let Some(addr_str) = udp_url.strip_prefix("udp://") {
-        addr_str.parse().ok()
-    } else {
-        udp_url.parse().ok()
+/// `DEPTH AAPL,MSFT` - поток стакана по управляющему TCP соединению во второй версии текста:
+/// сначала снимки стаканов, затем обновления и периодические снимки.
+fn process_depth(
+    parts: &[&str],
+    ctx: &ServerContext,
+    wr_stream: &Arc<Mutex<TcpStream>>,
+) -> Option<CommandOutput> {
+    if parts.len() != 2 {
+        return None;
+    }
+    let tickers = parse_tickers(parts[1]);
+    if tickers.is_empty() {
+        return None;
+    }
+    Some(start_tcp_stream(
+        tickers,
+        vec![MessageKind::Depth],
+        vec![],
+        StreamFilter::default(),
+        QuoteFormat::TextV2,
+        ctx,
+        wr_stream,
+    ))
+}
+
+/// `BARS 1m AAPL,MSFT` - поток закрытых свечей по управляющему TCP соединению,
+/// `BARS 1m AAPL 100` или `BARS 1m AAPL since=<время>` - история свечей.
+/// Свечи передаются во второй версии текста, интервал должен строиться сервером.
+fn process_bars(
+    parts: &[&str],
+    ctx: &ServerContext,
+    wr_stream: &Arc<Mutex<TcpStream>>,
+) -> Option<CommandOutput> {
+    let interval: BarInterval = parts.get(1)?.parse().ok()?;
+    if !ctx.bar_aggregator.intervals().contains(&interval) {
+        return None;
+    }
+    let tickers = parse_tickers(parts.get(2)?);
+    if tickers.is_empty() {
+        return None;
+    }
+
+    let Some(&depth) = parts.get(3) else {
+        return Some(start_tcp_stream(
+            tickers,
+            vec![MessageKind::Bar],
+            vec![interval],
+            StreamFilter::default(),
+            QuoteFormat::TextV2,
+            ctx,
+            wr_stream,
+        ));
+    };
+    if parts.len() > 4 || tickers.len() != 1 {
+        return None;
+    }
+    let bars = match depth.strip_prefix("since=") {
+        Some(timestamp) => ctx
+            .bar_aggregator
+            .since(&tickers[0], interval, timestamp.parse().ok()?),
+        None => ctx
+            .bar_aggregator
+            .last(&tickers[0], interval, depth.parse().ok()?),
+    };
+    let body = bars
+        .into_iter()
+        .filter_map(|bar| Message::Bar(bar).encode(QuoteFormat::TextV2))
+        .map(|data| String::from_utf8_lossy(&data).into_owned())
+        .collect();
+    Some(CommandOutput {
+        response: None,
+        body: Some(body),
+        stream: None,
+    })
+}
+
+/// Регистрация клиента и запуск потока по управляющему TCP соединению.
+fn start_tcp_stream(
+    tickers: Vec<String>,
+    kinds: Vec<MessageKind>,
+    bar_intervals: Vec<BarInterval>,
+    filter: StreamFilter,
+    format: QuoteFormat,
+    ctx: &ServerContext,
+    wr_stream: &Arc<Mutex<TcpStream>>,
+) -> CommandOutput {
+    // Подписка до снимка: обновления, уже вошедшие в снимок, клиент пропустит по номеру
+    let receiver = ctx.broadcast.subscribe_client();
+    let initial = ctx.initial_messages(&tickers, &kinds);
+    let client_id = {
+        let mut manager = ctx.client_manager.lock().unwrap();
+        let client_id = manager.add_client(None, tickers);
+        manager.set_kinds(client_id, kinds);
+        manager.set_bar_intervals(client_id, bar_intervals);
+        manager.set_stream_filter(client_id, filter);
+        client_id
+    };
+    let handle = start_client_tcp_stream_thread(
+        client_id,
+        wr_stream.clone(),
+        receiver,
+        initial,
+        format,
+        ctx,
+    );
+    CommandOutput {
+        response: None,
+        body: None,
+        stream: Some((client_id, handle)),
+    }
+}
+
+/// Разбор адреса клиента: `udp://127.0.0.1:34254`, `udp://[::1]:34254` или без схемы.
+/// IPv4-mapped адреса (`[::ffff:127.0.0.1]`) приводятся к IPv4.
+fn parse_udp_address(udp_url: &str) -> Option<SocketAddr> {
+    let addr_str = udp_url.strip_prefix("udp://").unwrap_or(udp_url);
+    let addr: SocketAddr = addr_str.parse().ok()?;
+    Some(SocketAddr::new(addr.ip().to_canonical(), addr.port()))
+}
+
+/// Подбор адреса для UDP сокета отправки под семейство адреса клиента.
+/// Неуказанный адрес (`0.0.0.0` или `::`) заменяется на неуказанный адрес нужного семейства,
+/// конкретный адрес другого семейства не подходит.
+fn udp_bind_address(bind: IpAddr, target: &SocketAddr) -> Option<SocketAddr> {
+    let ip = match (bind, target) {
+        (IpAddr::V4(_), SocketAddr::V4(_)) | (IpAddr::V6(_), SocketAddr::V6(_)) => bind,
+        (IpAddr::V4(ip), SocketAddr::V6(_)) if ip.is_unspecified() => {
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+        }
+        (IpAddr::V6(ip), SocketAddr::V4(_)) if ip.is_unspecified() => {
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, 0))
+}
+
+/// Оформление датаграмм UDP потока: формат сообщений и номера сообщений в потоке.
+struct UdpFraming {
+    format: QuoteFormat,
+    sequence: bool,
+}
+
+impl UdpFraming {
+    /// Датаграмма сообщения, номер увеличивается для каждой отправленной датаграммы.
+    fn frame<'a>(&self, data: &'a [u8], seq: &mut u64) -> Cow<'a, [u8]> {
+        if !self.sequence {
+            return Cow::Borrowed(data);
+        }
+        *seq += 1;
+        Cow::Owned(quote_lib::sequenced(*seq, data))
     }
 }
 
 fn start_client_stream_thread(
     client_id: u64,
-    udp_addr: std::net::SocketAddr,
-    receiver: Receiver<StockQuote>,
-    client_manager: Arc<Mutex<ClientManager>>,
-    running: Arc<AtomicBool>,
+    bind_addr: SocketAddr,
+    udp_addr: SocketAddr,
+    receiver: Subscription,
+    initial: Vec<Message>,
+    framing: UdpFraming,
+    ctx: &ServerContext,
 ) -> JoinHandle<()> {
+    let client_manager = ctx.client_manager.clone();
+    let stats = ctx.stats.clone();
+    let running = ctx.running.clone();
     thread::spawn(move || {
         log::info!("Запуск потока для {} на {}", client_id, udp_addr);
 
-        let udp_socket = match UdpSocket::bind("0.0.0.0:0") {
+        let udp_socket = match UdpSocket::bind(bind_addr) {
             Ok(s) => s,
             Err(e) => {
                 log::error!("Failed to bind UDP socket: {}", e);
@@ -179,40 +881,239 @@ fn start_client_stream_thread(
             handle_ping_messages(ping_socket, client_id, ping_manager, running);
         });
 
-        while let Ok(quote) = receiver.recv() {
-            if client_manager
+        let mut throttle = new_throttle(&client_manager, client_id);
+        let (mut sent, mut failed) = (0, 0);
+        let mut seq = 0;
+        for message in &initial {
+            throttle.record(message, Instant::now());
+            let Some(data) = message.encode(framing.format) else {
+                continue;
+            };
+            let result = udp_socket.send_to(&framing.frame(&data, &mut seq), udp_addr);
+            stats.count_datagram(result.is_ok());
+            match result {
+                Ok(_) => sent += 1,
+                Err(e) => {
+                    failed += 1;
+                    log::error!("Failed to send UDP data to {}: {}", udp_addr, e);
+                }
+            }
+        }
+        client_manager
+            .lock()
+            .unwrap()
+            .record_sent(client_id, sent, failed);
+
+        loop {
+            let (message, closed) = match receiver
+                .receiver()
+                .recv_timeout(throttle_timeout(&throttle))
+            {
+                Ok(message) => (Some(message), false),
+                Err(RecvTimeoutError::Timeout) => (None, false),
+                Err(RecvTimeoutError::Disconnected) => (None, true),
+            };
+            // Клиент перестал присылать Ping или удален командой KICK
+            if !client_manager
                 .lock()
                 .unwrap()
-                .get_inactive_clients(Duration::from_secs(3))
-                .contains(&client_id)
+                .is_active(client_id, Duration::from_secs(3))
             {
                 log::info!("Остановка потока для {} на {}", client_id, udp_addr);
                 break;
             }
-            log::debug!("Обрабатываем: {}", quote.ticker);
 
-            // проверяем, что клиент подписан на тикер
-            if !client_manager
+            // проверяем, что клиент подписан на тикер и тип сообщения
+            let mut messages: Vec<Arc<SharedMessage>> = message
+                .filter(|message| {
+                    client_manager
+                        .lock()
+                        .unwrap()
+                        .check_client_message(client_id, message)
+                        .unwrap_or(false)
+                })
+                .and_then(|message| throttle.offer(message, Instant::now()))
+                .into_iter()
+                .collect();
+            messages.extend(throttle.flush(Instant::now()));
+            messages.extend(queue_status(&receiver, client_id));
+
+            let (mut sent, mut failed) = (0, 0);
+            for message in messages {
+                let Some(data) = message.encoded(framing.format) else {
+                    continue;
+                };
+                log::debug!("Отправляем {:?} на адрес {}", message.ticker(), udp_addr);
+                let result = udp_socket.send_to(&framing.frame(&data, &mut seq), udp_addr);
+                stats.count_datagram(result.is_ok());
+                if let Err(e) = result {
+                    failed += 1;
+                    log::error!("Failed to send UDP data to {}: {}", udp_addr, e);
+                    break;
+                }
+                sent += 1;
+            }
+            if sent + failed > 0 {
+                client_manager
+                    .lock()
+                    .unwrap()
+                    .record_sent(client_id, sent, failed);
+            }
+            if failed > 0 || closed {
+                break;
+            }
+        }
+
+        handle.join().unwrap();
+        log::info!("Поток остановлен для {} на {}", client_id, udp_addr);
+    })
+}
+
+/// Поток отправки котировок по управляющему TCP соединению, по строке на котировку.
+/// Клиент считается активным, пока соединение открыто, при простое отправляется heartbeat.
+fn start_client_tcp_stream_thread(
+    client_id: u64,
+    stream: Arc<Mutex<TcpStream>>,
+    receiver: Subscription,
+    initial: Vec<Message>,
+    format: QuoteFormat,
+    ctx: &ServerContext,
+) -> JoinHandle<()> {
+    let client_manager = ctx.client_manager.clone();
+    let heartbeat = ctx.tcp_heartbeat;
+    let running = ctx.running.clone();
+    thread::spawn(move || {
+        log::info!("Запуск TCP потока для {}", client_id);
+        let mut last_send = Instant::now();
+        let mut throttle = new_throttle(&client_manager, client_id);
+
+        if !initial.is_empty() {
+            let mut stream = stream.lock().unwrap();
+            for message in &initial {
+                throttle.record(message, last_send);
+            }
+            let (mut sent, mut failed) = (0, 0);
+            for data in initial.iter().filter_map(|message| message.encode(format)) {
+                match stream
+                    .write_all(&data)
+                    .and_then(|_| stream.write_all(b"\n"))
+                {
+                    Ok(_) => sent += 1,
+                    Err(_) => failed += 1,
+                }
+            }
+            let _ = stream.flush();
+            client_manager
                 .lock()
                 .unwrap()
-                .check_client_ticker(client_id, &quote.ticker)
-                .unwrap_or(false)
+                .record_sent(client_id, sent, failed);
+        }
+
+        while running.load(Ordering::SeqCst) {
+            // Открытое соединение заменяет Ping
+            if !client_manager.lock().unwrap().update_ping(client_id) {
+                break;
+            }
+
+            let (message, closed) = match receiver
+                .receiver()
+                .recv_timeout(throttle_timeout(&throttle))
             {
-                continue;
+                Ok(message) => (
+                    client_manager
+                        .lock()
+                        .unwrap()
+                        .check_client_message(client_id, &message)
+                        .unwrap_or(false)
+                        .then_some(message),
+                    false,
+                ),
+                Err(RecvTimeoutError::Timeout) => (None, false),
+                Err(RecvTimeoutError::Disconnected) => (None, true),
+            };
+            let mut messages: Vec<Arc<SharedMessage>> = message
+                .and_then(|message| throttle.offer(message, Instant::now()))
+                .into_iter()
+                .collect();
+            messages.extend(throttle.flush(Instant::now()));
+            messages.extend(queue_status(&receiver, client_id));
+            // Строки общие для всех клиентов с тем же форматом
+            let mut lines: Vec<Arc<[u8]>> = messages
+                .iter()
+                .filter_map(|message| message.encoded(format))
+                .collect();
+            if lines.is_empty() {
+                match heartbeat {
+                    _ if closed => break,
+                    Some(interval) if last_send.elapsed() >= interval => {
+                        lines.push(Arc::from(HEARTBEAT_MSG.as_bytes()))
+                    }
+                    _ => continue,
+                }
             }
 
-            let data = quote.to_string();
-            log::debug!("Отправляем {} на адрес {}", data, udp_addr);
-            if let Err(e) = udp_socket.send_to(data.as_bytes(), udp_addr) {
-                log::error!("Failed to send UDP data to {}: {}", udp_addr, e);
+            log::debug!("Отправляем {} строк клиенту {}", lines.len(), client_id);
+            let result = {
+                let mut stream = stream.lock().unwrap();
+                lines
+                    .iter()
+                    .try_for_each(|line| {
+                        stream.write_all(line).and_then(|_| stream.write_all(b"\n"))
+                    })
+                    .and_then(|_| stream.flush())
+            };
+            let count = lines.len() as u64;
+            let (sent, failed) = if result.is_ok() {
+                (count, 0)
+            } else {
+                (0, count)
+            };
+            client_manager
+                .lock()
+                .unwrap()
+                .record_sent(client_id, sent, failed);
+            if let Err(e) = result {
+                log::info!("TCP соединение клиента {} закрыто: {}", client_id, e);
                 break;
             }
-
-            thread::sleep(Duration::from_millis(100));
+            if closed {
+                break;
+            }
+            last_send = Instant::now();
         }
 
-        handle.join().unwrap();
-        log::info!("Поток остановлен для {} на {}", client_id, udp_addr);
+        client_manager.lock().unwrap().remove_client(client_id);
+        log::info!("TCP поток остановлен для {}", client_id);
+    })
+}
+
+/// Статус о сообщениях, пропущенных при переполнении очереди клиента.
+fn queue_status(receiver: &Subscription, client_id: u64) -> Option<Arc<SharedMessage>> {
+    let status = receiver.take_status()?;
+    if receiver.is_overflowed() {
+        log::warn!("Поток клиента {} отключен: очередь переполнена", client_id);
+    } else {
+        log::debug!("Клиенту {} не доставлена часть сообщений", client_id);
+    }
+    Some(SharedMessage::new(status))
+}
+
+/// Ограничения потока котировок клиента из подписки.
+fn new_throttle(client_manager: &Mutex<ClientManager>, client_id: u64) -> QuoteThrottle {
+    QuoteThrottle::new(
+        client_manager
+            .lock()
+            .unwrap()
+            .stream_filter(client_id)
+            .unwrap_or_default(),
+    )
+}
+
+/// Ожидание сообщения до ближайшего окончания интервала объединения, не дольше секунды.
+fn throttle_timeout(throttle: &QuoteThrottle) -> Duration {
+    throttle.next_flush().map_or(Duration::from_secs(1), |at| {
+        at.saturating_duration_since(Instant::now())
+            .min(Duration::from_secs(1))
     })
 }
 
@@ -298,3 +1199,124 @@ fn handle_ping_messages(
     }
     log::info!("Поток остановлен для Ping для клиента {}", client_id);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quote_broadcast::OverflowPolicy;
+
+    fn quote(price: &str) -> Message {
+        let price: Price = price.parse().unwrap();
+        Message::Quote(StockQuote {
+            ticker: "AAPL".to_string(),
+            price,
+            volume: Volume::new(100),
+            timestamp: quote_lib::Timestamp::from_millis(1_700_000_000_000),
+            bid: price,
+            ask: price,
+            bid_size: Volume::ZERO,
+            ask_size: Volume::ZERO,
+        })
+    }
+
+    /// Статус о пропусках в первой версии текста, как его получит клиент без опции формата.
+    fn v1_status(policy: OverflowPolicy) -> String {
+        let broadcast = QuoteBroadcast::new(1, policy);
+        let receiver = broadcast.subscribe_client();
+        for price in ["100", "101", "102"] {
+            broadcast.send(&quote(price));
+        }
+        while receiver.receiver().try_recv().is_ok() {}
+        let status = queue_status(&receiver, 1).unwrap();
+        let line = status.encoded(QuoteFormat::TextV1).unwrap();
+        match Message::decode(&line).unwrap() {
+            Message::Status { message } => message,
+            message => panic!("не статус: {:?}", message),
+        }
+    }
+
+    #[test]
+    fn v1_subscriber_receives_drop_report() {
+        assert_eq!(
+            v1_status(OverflowPolicy::DropOldest),
+            "Пропущено сообщений: 2"
+        );
+        assert_eq!(
+            v1_status(OverflowPolicy::Disconnect),
+            "Поток остановлен: очередь клиента переполнена, пропущено сообщений: 1"
+        );
+    }
+
+    #[test]
+    fn v1_stream_carries_only_quotes() {
+        let options = |options: &[&str]| {
+            let options = parse_options(options).unwrap();
+            check_format_kinds(options.format, &options.kinds)
+        };
+        assert!(options(&[]).is_ok());
+        assert!(options(&["format=v1", "types=quotes"]).is_ok());
+        assert!(options(&["types=trades"]).is_err());
+        assert!(options(&["format=v1", "types=quotes,depth"]).is_err());
+        assert!(options(&["types=bars"]).is_err());
+        assert!(options(&["format=v2", "types=trades,depth,bars"]).is_ok());
+        assert!(options(&["format=bin", "types=quotes,trades"]).is_ok());
+    }
+
+    #[test]
+    fn parse_udp_address_ipv6_loopback() {
+        assert_eq!(
+            parse_udp_address("udp://[::1]:34254"),
+            Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 34254))
+        );
+    }
+
+    #[test]
+    fn parse_udp_address_ipv4_mapped_to_ipv4() {
+        assert_eq!(
+            parse_udp_address("udp://[::ffff:127.0.0.1]:34254"),
+            Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 34254))
+        );
+        assert_eq!(
+            parse_udp_address("127.0.0.1:34254"),
+            Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 34254))
+        );
+    }
+
+    #[test]
+    fn udp_bind_address_unspecified_switches_family() {
+        let v6_target = SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 34254);
+        let v4_target = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 34254);
+        assert_eq!(
+            udp_bind_address(IpAddr::V4(Ipv4Addr::UNSPECIFIED), &v6_target),
+            Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0))
+        );
+        assert_eq!(
+            udp_bind_address(IpAddr::V6(Ipv6Addr::UNSPECIFIED), &v4_target),
+            Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))
+        );
+    }
+
+    #[test]
+    fn udp_bind_address_same_family_keeps_bind() {
+        let target = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 34254);
+        let bind = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(
+            udp_bind_address(bind, &target),
+            Some(SocketAddr::new(bind, 0))
+        );
+    }
+
+    #[test]
+    fn udp_bind_address_concrete_other_family() {
+        let v6_target = SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 34254);
+        let v4_target = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 34254);
+        assert_eq!(
+            udp_bind_address(IpAddr::V4(Ipv4Addr::LOCALHOST), &v6_target),
+            None
+        );
+        assert_eq!(
+            udp_bind_address(IpAddr::V6(Ipv6Addr::LOCALHOST), &v4_target),
+            None
+        );
+    }
+}
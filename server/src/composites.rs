@@ -0,0 +1,258 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use quote_lib::{Price, StockQuote, Timestamp, Volume};
+
+use crate::quote_cache::QuoteCache;
+
+/// Составной инструмент: взвешенная сумма цен составляющих.
+pub(crate) struct Composite {
+    name: String,
+    /// Тикеры составляющих и их веса.
+    components: Vec<(String, f64)>,
+}
+
+/// Загрузка составных инструментов, строка файла:
+/// `TECH sum AAPL:0.5 MSFT:0.3 GOOGL:0.2` - взвешенная сумма,
+/// `IDX3 index 3 AAPL MSFT GOOGL` - ценовой индекс (сумма цен, деленная на делитель).
+/// Пустые строки и строки с `#` пропускаются, составляющие должны быть обычными тикерами.
+pub(crate) fn load_composites(path: &str) -> Result<Vec<Composite>, Box<dyn std::error::Error>> {
+    parse_composites(&std::fs::read_to_string(path)?)
+}
+
+fn parse_composites(content: &str) -> Result<Vec<Composite>, Box<dyn std::error::Error>> {
+    let mut composites: Vec<Composite> = vec![];
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let components = match parts.as_slice() {
+            [_, "sum", components @ ..] if !components.is_empty() => components
+                .iter()
+                .map(|component| {
+                    let (ticker, weight) = component
+                        .split_once(':')
+                        .ok_or_else(|| format!("Не указан вес составляющей: {}", component))?;
+                    let weight: f64 = weight.parse()?;
+                    if !weight.is_finite() {
+                        return Err(format!("Некорректный вес составляющей: {}", component).into());
+                    }
+                    Ok((ticker.to_string(), weight))
+                })
+                .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?,
+            [_, "index", divisor, tickers @ ..] if !tickers.is_empty() => {
+                let divisor: f64 = divisor.parse()?;
+                if !divisor.is_finite() || divisor <= 0.0 {
+                    return Err(format!("Некорректный делитель индекса: {}", line).into());
+                }
+                tickers
+                    .iter()
+                    .map(|ticker| (ticker.to_string(), 1.0 / divisor))
+                    .collect()
+            }
+            _ => {
+                return Err(
+                    format!("Некорректное описание составного инструмента: {}", line).into(),
+                );
+            }
+        };
+        composites.push(Composite {
+            name: parts[0].to_string(),
+            components,
+        });
+    }
+
+    for composite in &composites {
+        if let Some((ticker, _)) = composite
+            .components
+            .iter()
+            .find(|(ticker, _)| composites.iter().any(|c| &c.name == ticker))
+        {
+            return Err(format!(
+                "Составной инструмент {} ссылается на составной {}",
+                composite.name, ticker
+            )
+            .into());
+        }
+    }
+    Ok(composites)
+}
+
+/// Расчет составных инструментов по последним котировкам составляющих.
+pub(crate) struct CompositeEngine {
+    composites: Vec<Composite>,
+    /// Время последней рассчитанной котировки по каждому инструменту.
+    computed: Mutex<HashMap<String, Timestamp>>,
+}
+
+impl CompositeEngine {
+    pub(crate) fn new(composites: Vec<Composite>) -> Self {
+        Self {
+            composites,
+            computed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Является ли тикер составным инструментом.
+    pub(crate) fn is_derived(&self, ticker: &str) -> bool {
+        self.composites.iter().any(|c| c.name == ticker)
+    }
+
+    /// Котировки инструментов, у которых обновилась хотя бы одна составляющая.
+    /// Пока нет котировки по какой-либо составляющей, инструмент не рассчитывается.
+    /// При отрицательном весе bid и ask составляющей меняются местами.
+    pub(crate) fn compute(&self, cache: &QuoteCache) -> Vec<StockQuote> {
+        let mut computed = self.computed.lock().unwrap();
+        let mut quotes = vec![];
+        'composites: for composite in &self.composites {
+            let (mut price, mut bid, mut ask) = (0.0, 0.0, 0.0);
+            let mut timestamp = Timestamp::from_millis(0);
+            for (ticker, weight) in &composite.components {
+                let Some(quote) = cache.get(ticker) else {
+                    continue 'composites;
+                };
+                let (low, high) = if *weight >= 0.0 {
+                    (quote.bid, quote.ask)
+                } else {
+                    (quote.ask, quote.bid)
+                };
+                price += weight * (quote.bid.to_f64() + quote.ask.to_f64()) / 2.0;
+                bid += weight * low.to_f64();
+                ask += weight * high.to_f64();
+                timestamp = timestamp.max(quote.timestamp);
+            }
+            if computed
+                .get(&composite.name)
+                .is_some_and(|last| *last >= timestamp)
+            {
+                continue;
+            }
+            computed.insert(composite.name.clone(), timestamp);
+            quotes.push(StockQuote {
+                ticker: composite.name.clone(),
+                price: Price::from_f64(price),
+                volume: Volume::ZERO,
+                timestamp,
+                bid: Price::from_f64(bid),
+                ask: Price::from_f64(ask),
+                bid_size: Volume::ZERO,
+                ask_size: Volume::ZERO,
+            });
+        }
+        quotes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(value: &str) -> Price {
+        value.parse().unwrap()
+    }
+
+    fn quote(ticker: &str, bid: &str, ask: &str, ms: u64) -> StockQuote {
+        StockQuote {
+            ticker: ticker.to_string(),
+            price: price(bid),
+            volume: Volume::new(100),
+            timestamp: Timestamp::from_millis(ms),
+            bid: price(bid),
+            ask: price(ask),
+            bid_size: Volume::new(100),
+            ask_size: Volume::new(100),
+        }
+    }
+
+    fn engine(config: &str) -> CompositeEngine {
+        CompositeEngine::new(parse_composites(config).unwrap())
+    }
+
+    #[test]
+    fn parse_sum_and_index() {
+        let composites =
+            parse_composites("# индексы\n\nTECH sum AAPL:0.5 MSFT:-0.25\nIDX2 index 4 AAPL MSFT\n")
+                .unwrap();
+        assert_eq!(composites.len(), 2);
+        assert_eq!(composites[0].name, "TECH");
+        assert_eq!(
+            composites[0].components,
+            [("AAPL".to_string(), 0.5), ("MSFT".to_string(), -0.25)]
+        );
+        assert_eq!(
+            composites[1].components,
+            [("AAPL".to_string(), 0.25), ("MSFT".to_string(), 0.25)]
+        );
+    }
+
+    #[test]
+    fn parse_rejects_bad_config() {
+        for config in [
+            "TECH sum AAPL:NaN",
+            "TECH sum AAPL:inf",
+            "TECH sum AAPL:-inf",
+            "TECH sum AAPL",
+            "TECH sum AAPL:x",
+            "TECH sum",
+            "IDX index 0 AAPL",
+            "IDX index -2 AAPL",
+            "IDX index NaN AAPL",
+            "IDX index 2",
+            "TECH avg AAPL:1",
+            "TECH sum AAPL:1\nPAIR sum TECH:1 MSFT:-1",
+        ] {
+            assert!(parse_composites(config).is_err(), "{}", config);
+        }
+    }
+
+    #[test]
+    fn weighted_sum_waits_for_all_components() {
+        let engine = engine("TECH sum AAPL:0.5 MSFT:2");
+        let cache = QuoteCache::new();
+        cache.update(&quote("AAPL", "100", "102", 1_000));
+        assert!(engine.compute(&cache).is_empty());
+
+        cache.update(&quote("MSFT", "10", "11", 2_000));
+        let quotes = engine.compute(&cache);
+        assert_eq!(quotes.len(), 1);
+        let tech = &quotes[0];
+        assert_eq!(tech.ticker, "TECH");
+        assert_eq!(tech.bid, price("70"));
+        assert_eq!(tech.ask, price("73"));
+        assert_eq!(tech.price, price("71.5"));
+        assert_eq!(tech.timestamp, Timestamp::from_millis(2_000));
+
+        // Без новых котировок составляющих инструмент не пересчитывается
+        assert!(engine.compute(&cache).is_empty());
+        cache.update(&quote("AAPL", "101", "103", 3_000));
+        assert_eq!(engine.compute(&cache)[0].bid, price("70.5"));
+    }
+
+    #[test]
+    fn negative_weight_swaps_bid_and_ask() {
+        let engine = engine("PAIR sum AAPL:1 MSFT:-2");
+        let cache = QuoteCache::new();
+        cache.update(&quote("AAPL", "100", "101", 1_000));
+        cache.update(&quote("MSFT", "10", "12", 1_000));
+        let pair = &engine.compute(&cache)[0];
+        // bid: 100 - 2 * 12, ask: 101 - 2 * 10
+        assert_eq!(pair.bid, price("76"));
+        assert_eq!(pair.ask, price("81"));
+        assert_eq!(pair.price, price("78.5"));
+        assert!(pair.bid <= pair.ask);
+    }
+
+    #[test]
+    fn price_index_divides_sum() {
+        let engine = engine("IDX2 index 4 AAPL MSFT");
+        let cache = QuoteCache::new();
+        cache.update(&quote("AAPL", "100", "102", 1_000));
+        cache.update(&quote("MSFT", "20", "22", 1_000));
+        let index = &engine.compute(&cache)[0];
+        assert_eq!(index.bid, price("30"));
+        assert_eq!(index.ask, price("31"));
+        assert!(engine.is_derived("IDX2"));
+        assert!(!engine.is_derived("AAPL"));
+    }
+}
@@ -0,0 +1,320 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream},
+    sync::atomic::Ordering,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crossbeam::channel::RecvTimeoutError;
+use quote_lib::MessageKind;
+use serde::Serialize;
+
+use crate::command_handler::ServerContext;
+
+/// Запуск HTTP сервера:
+/// `GET /quotes/AAPL` - последняя котировка,
+/// `GET /quotes?tickers=AAPL,MSFT` - снимок котировок,
+/// `GET /stream?tickers=AAPL,MSFT` - поток котировок через Server-Sent Events,
+/// сделки и стакан добавляются параметром `types=quotes,trades,depth`.
+pub(crate) fn start_http_server(
+    addr: SocketAddr,
+    ctx: ServerContext,
+) -> Result<JoinHandle<()>, std::io::Error> {
+    let listner = crate::bind_tcp_listener(addr)?;
+    listner.set_nonblocking(true)?;
+    log::info!("HTTP сервер запущен на {}", addr);
+
+    Ok(thread::spawn(move || {
+        let mut handles = vec![];
+        while ctx.running.load(Ordering::SeqCst) {
+            match listner.accept() {
+                Ok((stream, _)) => {
+                    let ctx = ctx.clone();
+                    handles.push(thread::spawn(move || handle_http_client(stream, ctx)));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => {
+                    log::debug!("Error accepting connection: {}", e);
+                }
+            }
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        log::info!("HTTP сервер остановлен на {}", addr);
+    }))
+}
+
+fn handle_http_client(mut stream: TcpStream, ctx: ServerContext) {
+    let peer_addr = match stream.peer_addr() {
+        Ok(addr) => addr,
+        Err(e) => {
+            log::error!("Failed to get peer address: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = stream
+        .set_nonblocking(false)
+        .and_then(|_| stream.set_read_timeout(Some(Duration::from_secs(5))))
+    {
+        log::error!("Failed to configure stream: {}", e);
+        return;
+    }
+
+    let (method, target) = match read_request(&stream) {
+        Ok(request) => request,
+        Err(e) => {
+            log::error!("Failed to read HTTP request from {}: {}", peer_addr, e);
+            return;
+        }
+    };
+    log::info!("HTTP запрос {}: {} {}", peer_addr, method, target);
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let tickers = query_tickers(query);
+
+    let result = match (method.as_str(), path) {
+        ("GET", "/stream") if !tickers.is_empty() => match query_types(query) {
+            Ok(kinds) => stream_events(&mut stream, tickers, kinds, &ctx),
+            Err(message) => write_json(&mut stream, "400 Bad Request", &error(&message)),
+        },
+        ("GET", "/stream") => {
+            write_json(&mut stream, "400 Bad Request", &error("Не указаны тикеры"))
+        }
+        ("GET", "/quotes") if tickers.is_empty() => {
+            write_json(&mut stream, "200 OK", &ctx.quote_cache.all())
+        }
+        ("GET", "/quotes") => {
+            write_json(&mut stream, "200 OK", &ctx.quote_cache.snapshot(&tickers))
+        }
+        ("GET", path) if path.starts_with("/quotes/") => {
+            match ctx.quote_cache.get(&path["/quotes/".len()..]) {
+                Some(quote) => write_json(&mut stream, "200 OK", &quote),
+                None => write_json(&mut stream, "404 Not Found", &error("Нет котировки")),
+            }
+        }
+        ("GET", _) => write_json(&mut stream, "404 Not Found", &error("Неизвестный путь")),
+        _ => write_json(
+            &mut stream,
+            "405 Method Not Allowed",
+            &error("Поддерживается только GET"),
+        ),
+    };
+    if let Err(e) = result {
+        log::info!("HTTP соединение {} закрыто: {}", peer_addr, e);
+    }
+}
+
+/// Чтение строки запроса, заголовки пропускаются.
+fn read_request(stream: &TcpStream) -> Result<(String, String), std::io::Error> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Некорректный запрос: {}", line.trim()),
+        ));
+    };
+    let request = (method.to_string(), target.to_string());
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+    Ok(request)
+}
+
+/// Тикеры из параметра `tickers=AAPL,MSFT`.
+fn query_tickers(query: &str) -> Vec<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.strip_prefix("tickers="))
+        .flat_map(|value| {
+            percent_decode(value)
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Типы данных из параметра `types=quotes,trades`, без параметра - только котировки.
+fn query_types(query: &str) -> Result<Vec<MessageKind>, String> {
+    match query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("types="))
+    {
+        Some(value) => MessageKind::parse_list(&percent_decode(value)),
+        None => Ok(vec![MessageKind::Quote]),
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = value
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            decoded.push(byte);
+            i += 3;
+            continue;
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn error(message: &str) -> serde_json::Value {
+    serde_json::json!({ "error": message })
+}
+
+fn write_json<T: Serialize + ?Sized>(
+    stream: &mut TcpStream,
+    status: &str,
+    body: &T,
+) -> Result<(), std::io::Error> {
+    let body = serde_json::to_string(body)?;
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes())?;
+    stream.flush()
+}
+
+/// Клиент закрыл соединение: проверка без ожидания данных от клиента.
+fn peer_closed(stream: &TcpStream) -> bool {
+    let mut buf = [0; 1];
+    let result = stream
+        .set_nonblocking(true)
+        .and_then(|_| stream.peek(&mut buf));
+    let _ = stream.set_nonblocking(false);
+    match result {
+        Ok(size) => size == 0,
+        Err(e) => e.kind() != std::io::ErrorKind::WouldBlock,
+    }
+}
+
+/// Поток котировок в формате Server-Sent Events, пока клиент не закроет соединение.
+fn stream_events(
+    stream: &mut TcpStream,
+    tickers: Vec<String>,
+    kinds: Vec<MessageKind>,
+    ctx: &ServerContext,
+) -> Result<(), std::io::Error> {
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n",
+    )?;
+    let receiver = ctx.broadcast.subscribe_client();
+    for message in ctx.initial_messages(&tickers, &kinds) {
+        let event = serde_json::to_string(&message)?;
+        stream.write_all(format!("data: {}\n\n", event).as_bytes())?;
+    }
+    stream.flush()?;
+
+    let client_id = {
+        let mut manager = ctx.client_manager.lock().unwrap();
+        let client_id = manager.add_client(None, tickers);
+        manager.set_kinds(client_id, kinds);
+        if let Ok(peer_addr) = stream.peer_addr() {
+            manager.set_peer_addr(client_id, peer_addr);
+        }
+        client_id
+    };
+    log::info!("Запуск SSE потока для {}", client_id);
+
+    let mut last_send = Instant::now();
+    let mut last_check = Instant::now();
+    let mut result = Ok(());
+    while ctx.running.load(Ordering::SeqCst) {
+        // Открытое соединение заменяет Ping
+        if !ctx.client_manager.lock().unwrap().update_ping(client_id) {
+            break;
+        }
+        // Без heartbeat и подходящих котировок закрытие соединения не обнаружить записью
+        if last_check.elapsed() >= Duration::from_secs(1) {
+            if peer_closed(stream) {
+                log::info!("SSE клиент {} закрыл соединение", client_id);
+                break;
+            }
+            last_check = Instant::now();
+        }
+
+        let (message, closed) = match receiver.receiver().recv_timeout(Duration::from_secs(1)) {
+            Ok(message) => (
+                ctx.client_manager
+                    .lock()
+                    .unwrap()
+                    .check_client_message(client_id, &message)
+                    .unwrap_or(false)
+                    .then_some(message),
+                false,
+            ),
+            Err(RecvTimeoutError::Timeout) => (None, false),
+            Err(RecvTimeoutError::Disconnected) => (None, true),
+        };
+        let mut events = String::new();
+        let mut count = 0;
+        // JSON сообщения рассылки общий для всех клиентов
+        if let Some(message) = message {
+            let json = message.json().ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Сообщение не сериализуется в JSON",
+                )
+            })?;
+            events.push_str(&format!("data: {}\n\n", json));
+            count += 1;
+        }
+        // Статус о пропущенных при переполнении очереди сообщениях, в том числе перед отключением
+        if let Some(status) = receiver.take_status() {
+            events.push_str(&format!("data: {}\n\n", serde_json::to_string(&status)?));
+            count += 1;
+        }
+        if events.is_empty() {
+            match ctx.tcp_heartbeat {
+                _ if closed => break,
+                Some(interval) if last_send.elapsed() >= interval => {
+                    events.push_str(": heartbeat\n\n")
+                }
+                _ => continue,
+            }
+        }
+
+        result = stream
+            .write_all(events.as_bytes())
+            .and_then(|_| stream.flush());
+        let (sent, failed) = if result.is_ok() {
+            (count, 0)
+        } else {
+            (0, count)
+        };
+        ctx.client_manager
+            .lock()
+            .unwrap()
+            .record_sent(client_id, sent, failed);
+        if result.is_err() || closed {
+            break;
+        }
+        last_send = Instant::now();
+    }
+
+    ctx.client_manager.lock().unwrap().remove_client(client_id);
+    log::info!("SSE поток остановлен для {}", client_id);
+    result
+}
@@ -0,0 +1,383 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use quote_lib::{Message, Price, StockQuote, Timestamp, Volume};
+
+/// Индикатор, публикуемый как тикер `AAPL.VWAP`, `AAPL.SMA20`, `AAPL.EMA20`, `AAPL.VOL20`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Indicator {
+    /// Средневзвешенная по объему цена сделок с начала работы сервера.
+    Vwap,
+    /// Простое скользящее среднее середины котировки за `n` обновлений.
+    Sma(usize),
+    /// Экспоненциальное скользящее среднее середины котировки с периодом `n`.
+    Ema(usize),
+    /// Стандартное отклонение логарифмических доходностей середины котировки
+    /// за `n` обновлений, в процентах.
+    Volatility(usize),
+}
+
+impl Indicator {
+    /// Число хранимых значений середины котировки для расчета.
+    fn window(&self) -> usize {
+        match self {
+            Indicator::Vwap | Indicator::Ema(_) => 0,
+            Indicator::Sma(n) => *n,
+            Indicator::Volatility(n) => n + 1,
+        }
+    }
+}
+
+impl std::fmt::Display for Indicator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Indicator::Vwap => write!(f, "VWAP"),
+            Indicator::Sma(n) => write!(f, "SMA{}", n),
+            Indicator::Ema(n) => write!(f, "EMA{}", n),
+            Indicator::Volatility(n) => write!(f, "VOL{}", n),
+        }
+    }
+}
+
+impl std::str::FromStr for Indicator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "VWAP" {
+            return Ok(Indicator::Vwap);
+        }
+        let split = s.find(|c: char| c.is_ascii_digit()).unwrap_or(s.len());
+        let (name, period) = s.split_at(split);
+        let period: usize = period
+            .parse()
+            .map_err(|_| format!("Не указан период индикатора: {}", s))?;
+        match (name, period) {
+            (_, 0) | ("VOL", 1) => Err(format!("Некорректный период индикатора: {}", s)),
+            ("SMA", n) => Ok(Indicator::Sma(n)),
+            ("EMA", n) => Ok(Indicator::Ema(n)),
+            ("VOL", n) => Ok(Indicator::Volatility(n)),
+            _ => Err(format!("Неизвестный индикатор: {}", s)),
+        }
+    }
+}
+
+/// Данные тикера для расчета индикаторов.
+#[derive(Default)]
+struct Series {
+    /// Сумма цена * объем сделок в единицах цены.
+    notional: i128,
+    volume: u64,
+    /// Последние значения середины котировки.
+    mids: VecDeque<Price>,
+    /// Текущие значения EMA по периоду.
+    ema: HashMap<usize, f64>,
+}
+
+/// Расчет индикаторов по исходным тикерам: каждое обновление котировки или сделка
+/// дает котировки производных тикеров с ценой индикатора (bid = ask = цена, объем 0).
+pub(crate) struct IndicatorEngine {
+    indicators: Vec<Indicator>,
+    window: usize,
+    series: Mutex<HashMap<String, Series>>,
+}
+
+impl IndicatorEngine {
+    pub(crate) fn new(indicators: Vec<Indicator>) -> Self {
+        let window = indicators.iter().map(Indicator::window).max().unwrap_or(0);
+        Self {
+            indicators,
+            window,
+            series: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Является ли тикер индикатором: `AAPL.VWAP` при включенном `VWAP`.
+    pub(crate) fn is_derived(&self, ticker: &str) -> bool {
+        ticker
+            .rsplit_once('.')
+            .and_then(|(_, suffix)| suffix.parse::<Indicator>().ok())
+            .is_some_and(|indicator| self.indicators.contains(&indicator))
+    }
+
+    /// Пересчет индикаторов по сообщению исходного тикера.
+    pub(crate) fn update(&self, message: &Message) -> Vec<StockQuote> {
+        if self.indicators.is_empty() {
+            return vec![];
+        }
+        match message {
+            Message::Trade(trade) if !self.is_derived(&trade.ticker) => {
+                if !self.indicators.contains(&Indicator::Vwap) {
+                    return vec![];
+                }
+                let mut series = self.series.lock().unwrap();
+                let series = series.entry(trade.ticker.clone()).or_default();
+                series.notional += trade.price.units() as i128 * trade.size.units() as i128;
+                series.volume += trade.size.units();
+                if series.volume == 0 {
+                    return vec![];
+                }
+                let vwap = Price::from_units((series.notional / series.volume as i128) as i64);
+                vec![derived_quote(
+                    &trade.ticker,
+                    Indicator::Vwap,
+                    vwap,
+                    trade.timestamp,
+                )]
+            }
+            Message::Quote(quote) if !self.is_derived(&quote.ticker) => {
+                let mid = Price::from_units((quote.bid.units() + quote.ask.units()) / 2);
+                let mut series = self.series.lock().unwrap();
+                let series = series.entry(quote.ticker.clone()).or_default();
+                if self.window > 0 {
+                    if series.mids.len() == self.window {
+                        series.mids.pop_front();
+                    }
+                    series.mids.push_back(mid);
+                }
+
+                self.indicators
+                    .iter()
+                    .filter_map(|&indicator| {
+                        let value = match indicator {
+                            Indicator::Vwap => return None,
+                            Indicator::Sma(n) => sma(&series.mids, n)?,
+                            Indicator::Ema(n) => {
+                                let alpha = 2.0 / (n as f64 + 1.0);
+                                let ema = series
+                                    .ema
+                                    .entry(n)
+                                    .and_modify(|ema| {
+                                        *ema = alpha * mid.to_f64() + (1.0 - alpha) * *ema
+                                    })
+                                    .or_insert(mid.to_f64());
+                                Price::from_f64(*ema)
+                            }
+                            Indicator::Volatility(n) => volatility(&series.mids, n)?,
+                        };
+                        Some(derived_quote(
+                            &quote.ticker,
+                            indicator,
+                            value,
+                            quote.timestamp,
+                        ))
+                    })
+                    .collect()
+            }
+            _ => vec![],
+        }
+    }
+}
+
+fn derived_quote(
+    ticker: &str,
+    indicator: Indicator,
+    value: Price,
+    timestamp: Timestamp,
+) -> StockQuote {
+    StockQuote {
+        ticker: format!("{}.{}", ticker, indicator),
+        price: value,
+        volume: Volume::ZERO,
+        timestamp,
+        bid: value,
+        ask: value,
+        bid_size: Volume::ZERO,
+        ask_size: Volume::ZERO,
+    }
+}
+
+/// Среднее последних `n` значений, `None` - значений пока меньше.
+fn sma(mids: &VecDeque<Price>, n: usize) -> Option<Price> {
+    if mids.len() < n {
+        return None;
+    }
+    let sum: i128 = mids.iter().rev().take(n).map(|p| p.units() as i128).sum();
+    Some(Price::from_units((sum / n as i128) as i64))
+}
+
+/// Выборочное стандартное отклонение последних `n` логарифмических доходностей в процентах.
+fn volatility(mids: &VecDeque<Price>, n: usize) -> Option<Price> {
+    if mids.len() < n + 1 {
+        return None;
+    }
+    let returns: Vec<f64> = mids
+        .iter()
+        .skip(mids.len() - n - 1)
+        .collect::<Vec<_>>()
+        .windows(2)
+        .map(|w| (w[1].to_f64() / w[0].to_f64()).ln())
+        .collect();
+    let mean = returns.iter().sum::<f64>() / n as f64;
+    let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+    Some(Price::from_f64(variance.sqrt() * 100.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use quote_lib::Trade;
+
+    use super::*;
+
+    fn price(value: &str) -> Price {
+        value.parse().unwrap()
+    }
+
+    fn quote(ticker: &str, mid: &str) -> Message {
+        let mid = price(mid);
+        Message::Quote(StockQuote {
+            ticker: ticker.to_string(),
+            price: mid,
+            volume: Volume::new(100),
+            timestamp: Timestamp::from_millis(1_000),
+            bid: mid - Price::from_units(100),
+            ask: mid + Price::from_units(100),
+            bid_size: Volume::ZERO,
+            ask_size: Volume::ZERO,
+        })
+    }
+
+    fn trade(ticker: &str, value: &str, size: u64) -> Message {
+        Message::Trade(Trade {
+            ticker: ticker.to_string(),
+            price: price(value),
+            size: Volume::new(size),
+            timestamp: Timestamp::from_millis(1_000),
+        })
+    }
+
+    /// Значения индикаторов по очереди котировок или сделок, по строке на сообщение.
+    fn values(engine: &IndicatorEngine, messages: &[Message]) -> Vec<Vec<(String, Price)>> {
+        messages
+            .iter()
+            .map(|message| {
+                engine
+                    .update(message)
+                    .into_iter()
+                    .map(|quote| {
+                        assert_eq!(quote.bid, quote.price);
+                        assert_eq!(quote.ask, quote.price);
+                        (quote.ticker, quote.price)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn single(engine: &IndicatorEngine, mids: &[&str]) -> Vec<Option<Price>> {
+        let messages: Vec<Message> = mids.iter().map(|mid| quote("AAPL", mid)).collect();
+        values(engine, &messages)
+            .into_iter()
+            .map(|values| values.first().map(|(_, value)| *value))
+            .collect()
+    }
+
+    #[test]
+    fn parse_indicators() {
+        for name in ["VWAP", "SMA20", "EMA5", "VOL2"] {
+            let indicator: Indicator = name.parse().unwrap();
+            assert_eq!(indicator.to_string(), name);
+        }
+        for bad in ["SMA", "SMA0", "VOL1", "EMAx", "RSI14", "vwap"] {
+            assert!(bad.parse::<Indicator>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn vwap_over_all_trades() {
+        let engine = IndicatorEngine::new(vec![Indicator::Vwap]);
+        let values = values(
+            &engine,
+            &[
+                trade("AAPL", "10", 100),
+                trade("AAPL", "12", 300),
+                trade("MSFT", "300", 10),
+                quote("AAPL", "50"),
+                trade("AAPL", "11", 0),
+            ],
+        );
+        assert_eq!(values[0], [("AAPL.VWAP".to_string(), price("10"))]);
+        assert_eq!(values[1], [("AAPL.VWAP".to_string(), price("11.5"))]);
+        assert_eq!(values[2], [("MSFT.VWAP".to_string(), price("300"))]);
+        assert!(values[3].is_empty());
+        assert_eq!(values[4], [("AAPL.VWAP".to_string(), price("11.5"))]);
+    }
+
+    #[test]
+    fn sma_window_and_warm_up() {
+        let engine = IndicatorEngine::new(vec![Indicator::Sma(3)]);
+        assert_eq!(
+            single(&engine, &["10", "11", "12", "15", "9"]),
+            [
+                None,
+                None,
+                Some(price("11")),
+                // 38 / 3 с отбрасыванием долей меньше шага цены
+                Some(price("12.6666")),
+                Some(price("12")),
+            ]
+        );
+    }
+
+    #[test]
+    fn ema_starts_from_first_mid() {
+        // Период 3: коэффициент 2 / (3 + 1) = 0.5
+        let engine = IndicatorEngine::new(vec![Indicator::Ema(3)]);
+        assert_eq!(
+            single(&engine, &["10", "12", "8", "8"]),
+            [
+                Some(price("10")),
+                Some(price("11")),
+                Some(price("9.5")),
+                Some(price("8.75")),
+            ]
+        );
+    }
+
+    #[test]
+    fn volatility_of_log_returns() {
+        // ln(110/100) = 0.0953, ln(99/110) = -0.1054, выборочное отклонение 0.141896
+        let engine = IndicatorEngine::new(vec![Indicator::Volatility(2)]);
+        assert_eq!(
+            single(&engine, &["100", "110", "99", "99"]),
+            [None, None, Some(price("14.1896")), Some(price("7.4501"))]
+        );
+    }
+
+    #[test]
+    fn indicators_share_longest_window() {
+        let engine = IndicatorEngine::new(vec![
+            Indicator::Sma(2),
+            Indicator::Volatility(3),
+            Indicator::Ema(2),
+        ]);
+        let values = values(
+            &engine,
+            &[
+                quote("AAPL", "10"),
+                quote("AAPL", "20"),
+                quote("AAPL", "10"),
+                quote("AAPL", "20"),
+            ],
+        );
+        let names = |values: &[(String, Price)]| -> Vec<String> {
+            values.iter().map(|(name, _)| name.clone()).collect()
+        };
+        assert_eq!(names(&values[0]), ["AAPL.EMA2"]);
+        assert_eq!(names(&values[1]), ["AAPL.SMA2", "AAPL.EMA2"]);
+        assert_eq!(names(&values[3]), ["AAPL.SMA2", "AAPL.VOL3", "AAPL.EMA2"]);
+        assert_eq!(values[3][0].1, price("15"));
+    }
+
+    #[test]
+    fn derived_tickers_are_not_inputs() {
+        let engine = IndicatorEngine::new(vec![Indicator::Ema(3), Indicator::Vwap]);
+        assert!(engine.is_derived("AAPL.EMA3"));
+        assert!(engine.is_derived("AAPL.VWAP"));
+        assert!(!engine.is_derived("AAPL.EMA5"));
+        assert!(!engine.is_derived("AAPL"));
+        assert!(engine.update(&quote("AAPL.EMA3", "10")).is_empty());
+        assert!(engine.update(&trade("AAPL.VWAP", "10", 5)).is_empty());
+    }
+}
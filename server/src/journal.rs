@@ -0,0 +1,534 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crossbeam::channel::RecvTimeoutError;
+use quote_lib::{Message, QuoteFormat};
+
+use crate::command_handler::ServerContext;
+
+/// Заголовок файла сегмента журнала.
+const SEGMENT_MAGIC: &[u8; 4] = b"MSJ1";
+/// Расширение файлов сегментов.
+const SEGMENT_EXT: &str = "msj";
+
+/// Запись журнала: время получения сообщения сервером (мс) и само сообщение (котировка или сделка).
+/// В файле: `u64` время получения, `u16` длина, сообщение в текстовой кодировке протокола
+/// (вторая версия, старые журналы первой версии тоже читаются).
+#[derive(Debug, Clone)]
+pub(crate) struct JournalRecord {
+    pub(crate) recv_ms: u64,
+    pub(crate) message: Message,
+}
+
+/// Запись журнала в каталог сегментами, новый сегмент после `segment_size` байт.
+pub(crate) struct JournalWriter {
+    dir: PathBuf,
+    segment_size: u64,
+    file: Option<BufWriter<File>>,
+    written: u64,
+    /// Номер следующего сегмента, различает сегменты с одинаковым временем начала.
+    next_segment: u64,
+}
+
+impl JournalWriter {
+    pub(crate) fn new(dir: &Path, segment_size: u64) -> Result<Self, std::io::Error> {
+        fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            segment_size,
+            file: None,
+            written: 0,
+            next_segment: 0,
+        })
+    }
+
+    pub(crate) fn append(&mut self, record: &JournalRecord) -> Result<(), std::io::Error> {
+        if self.file.is_none() || self.written >= self.segment_size {
+            self.rotate(record.recv_ms)?;
+        }
+        let Some(data) = record.message.encode(QuoteFormat::TextV2) else {
+            return Ok(());
+        };
+        let len = u16::try_from(data.len())
+            .map_err(|_| std::io::Error::new(ErrorKind::InvalidInput, "Слишком длинная запись"))?;
+        let Some(file) = self.file.as_mut() else {
+            return Ok(());
+        };
+        file.write_all(&record.recv_ms.to_le_bytes())?;
+        file.write_all(&len.to_le_bytes())?;
+        file.write_all(&data)?;
+        self.written += 10 + data.len() as u64;
+        Ok(())
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<(), std::io::Error> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Новый сегмент, имя - время получения первой записи и номер, сегменты сортируются по имени.
+    fn rotate(&mut self, first_ms: u64) -> Result<(), std::io::Error> {
+        self.flush()?;
+        let path = self.dir.join(format!(
+            "journal-{:020}-{:06}.{}",
+            first_ms, self.next_segment, SEGMENT_EXT
+        ));
+        self.next_segment += 1;
+        let mut file = BufWriter::new(File::create_new(&path)?);
+        file.write_all(SEGMENT_MAGIC)?;
+        log::info!("Новый сегмент журнала: {}", path.display());
+        self.file = Some(file);
+        self.written = SEGMENT_MAGIC.len() as u64;
+        Ok(())
+    }
+}
+
+/// Запуск потока записи журнала из общей рассылки, служебные и производные сообщения
+/// не записываются.
+pub(crate) fn start_journal_writer(
+    mut writer: JournalWriter,
+    ctx: ServerContext,
+) -> JoinHandle<()> {
+    let receiver = ctx.broadcast.subscribe();
+    thread::spawn(move || {
+        log::info!("Запуск потока записи журнала");
+        while ctx.running.load(Ordering::SeqCst) {
+            let message = match receiver.recv_timeout(Duration::from_secs(1)) {
+                Ok(message) if message.kind().is_some() && !ctx.is_derived(&message) => message,
+                Ok(_) | Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            let record = JournalRecord {
+                recv_ms: quote_lib::get_timestamp().as_millis(),
+                message: message.message().clone(),
+            };
+            let mut result = writer.append(&record);
+            // Сбрасываем на диск, когда котировки текущего тика записаны
+            if result.is_ok() && receiver.is_empty() {
+                result = writer.flush();
+            }
+            if let Err(e) = result {
+                log::error!("Failed to write journal: {}", e);
+                break;
+            }
+        }
+        if let Err(e) = writer.flush() {
+            log::error!("Failed to flush journal: {}", e);
+        }
+        log::info!("Поток записи журнала остановлен");
+    })
+}
+
+/// Последовательное чтение записей всех сегментов каталога.
+pub(crate) struct JournalReader {
+    segments: Vec<PathBuf>,
+    next_segment: usize,
+    file: Option<BufReader<File>>,
+}
+
+impl JournalReader {
+    pub(crate) fn open(dir: &Path) -> Result<Self, std::io::Error> {
+        let mut segments: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == SEGMENT_EXT))
+            .collect();
+        segments.sort();
+        if segments.is_empty() {
+            return Err(std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("Нет сегментов журнала в {}", dir.display()),
+            ));
+        }
+        Ok(Self {
+            segments,
+            next_segment: 0,
+            file: None,
+        })
+    }
+
+    /// Переход к первой записи, полученной не раньше `recv_ms`.
+    pub(crate) fn seek(&mut self, recv_ms: u64) -> Result<Option<JournalRecord>, std::io::Error> {
+        // Последний сегмент, начинающийся не позже искомого времени
+        self.next_segment = self
+            .segments
+            .iter()
+            .rposition(|path| segment_start(path).is_some_and(|start| start <= recv_ms))
+            .unwrap_or(0);
+        self.file = None;
+        while let Some(record) = self.next_record()? {
+            if record.recv_ms >= recv_ms {
+                return Ok(Some(record));
+            }
+        }
+        Ok(None)
+    }
+
+    pub(crate) fn next_record(&mut self) -> Result<Option<JournalRecord>, std::io::Error> {
+        loop {
+            if self.file.is_none() {
+                let Some(path) = self.segments.get(self.next_segment) else {
+                    return Ok(None);
+                };
+                let mut file = BufReader::new(File::open(path)?);
+                let mut magic = [0; 4];
+                file.read_exact(&mut magic)?;
+                if &magic != SEGMENT_MAGIC {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Некорректный сегмент журнала: {}", path.display()),
+                    ));
+                }
+                self.file = Some(file);
+                self.next_segment += 1;
+            }
+            let Some(file) = self.file.as_mut() else {
+                continue;
+            };
+            match read_record(file) {
+                Ok(Some(record)) => return Ok(Some(record)),
+                // Конец сегмента, в том числе оборванная последняя запись
+                Ok(None) => self.file = None,
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => self.file = None,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn segment_start(path: &Path) -> Option<u64> {
+    path.file_stem()?
+        .to_str()?
+        .strip_prefix("journal-")?
+        .split('-')
+        .next()?
+        .parse()
+        .ok()
+}
+
+fn read_record(file: &mut impl Read) -> Result<Option<JournalRecord>, std::io::Error> {
+    let mut recv_ms = [0; 8];
+    match file.read_exact(&mut recv_ms) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut len = [0; 2];
+    file.read_exact(&mut len)?;
+    let mut data = vec![0; u16::from_le_bytes(len) as usize];
+    file.read_exact(&mut data)?;
+    let message = Message::decode(&data)
+        .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+    Ok(Some(JournalRecord {
+        recv_ms: u64::from_le_bytes(recv_ms),
+        message,
+    }))
+}
+
+/// Наименьший множитель скорости воспроизведения.
+pub(crate) const MIN_REPLAY_SPEED: f64 = 0.01;
+
+/// Разбор множителя скорости воспроизведения из `--replay-speed` и `REPLAY SPEED`:
+/// конечное число не меньше [`MIN_REPLAY_SPEED`].
+pub(crate) fn parse_replay_speed(value: &str) -> Result<f64, String> {
+    let speed: f64 = value
+        .parse()
+        .map_err(|_| format!("Неверная скорость воспроизведения: {}", value))?;
+    if !speed.is_finite() || speed < MIN_REPLAY_SPEED {
+        return Err(format!(
+            "Скорость воспроизведения должна быть не меньше {}: {}",
+            MIN_REPLAY_SPEED, value
+        ));
+    }
+    Ok(speed)
+}
+
+/// Управление воспроизведением журнала из команды `REPLAY`.
+pub(crate) struct ReplayControl {
+    paused: AtomicBool,
+    speed: Mutex<f64>,
+    seek: Mutex<Option<u64>>,
+    /// Время получения последней воспроизведенной записи (мс).
+    position: Mutex<u64>,
+}
+
+impl ReplayControl {
+    pub(crate) fn new(speed: f64) -> Self {
+        Self {
+            paused: AtomicBool::new(false),
+            speed: Mutex::new(speed),
+            seek: Mutex::new(None),
+            position: Mutex::new(0),
+        }
+    }
+
+    pub(crate) fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::SeqCst);
+    }
+
+    pub(crate) fn set_speed(&self, speed: f64) {
+        *self.speed.lock().unwrap() = speed;
+    }
+
+    pub(crate) fn seek(&self, recv_ms: u64) {
+        *self.seek.lock().unwrap() = Some(recv_ms);
+    }
+
+    /// Состояние для ответа на `REPLAY STATUS`: `<позиция мс> <скорость> <PAUSED|PLAYING>`.
+    pub(crate) fn status(&self) -> String {
+        format!(
+            "{} {} {}",
+            self.position.lock().unwrap(),
+            self.speed.lock().unwrap(),
+            if self.paused.load(Ordering::SeqCst) {
+                "PAUSED"
+            } else {
+                "PLAYING"
+            }
+        )
+    }
+}
+
+/// Запуск потока воспроизведения журнала вместо генератора котировок.
+/// Интервалы между записями повторяют исходные с учетом множителя скорости.
+pub(crate) fn start_journal_replay(
+    mut reader: JournalReader,
+    control: Arc<ReplayControl>,
+    ctx: ServerContext,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        log::info!("Запуск потока воспроизведения журнала");
+        let mut next = reader.next_record();
+        let mut prev_ms = None;
+        let mut prev_at = Instant::now();
+        while ctx.running.load(Ordering::SeqCst) {
+            if let Some(recv_ms) = control.seek.lock().unwrap().take() {
+                log::info!("Переход по журналу к {}", recv_ms);
+                next = reader.seek(recv_ms);
+                prev_ms = None;
+            }
+            if control.paused.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(50));
+                prev_at = Instant::now();
+                continue;
+            }
+
+            let record = match &next {
+                Ok(Some(record)) => record.clone(),
+                Ok(None) => {
+                    // Конец журнала, ждем перехода по SEEK
+                    thread::sleep(Duration::from_millis(50));
+                    continue;
+                }
+                Err(e) => {
+                    log::error!("Failed to read journal: {}", e);
+                    break;
+                }
+            };
+
+            if let Some(prev_ms) = prev_ms {
+                let speed = *control.speed.lock().unwrap();
+                let gap = Duration::from_millis(record.recv_ms.saturating_sub(prev_ms));
+                let delay =
+                    Duration::try_from_secs_f64(gap.as_secs_f64() / speed).unwrap_or(Duration::MAX);
+                let remaining = delay.saturating_sub(prev_at.elapsed());
+                if !remaining.is_zero() {
+                    thread::sleep(remaining.min(Duration::from_millis(50)));
+                    continue;
+                }
+            }
+
+            ctx.publish(&record.message);
+            *control.position.lock().unwrap() = record.recv_ms;
+            prev_ms = Some(record.recv_ms);
+            prev_at = Instant::now();
+            next = reader.next_record();
+            // Записи одного такта источника получены в одну миллисекунду
+            if !matches!(&next, Ok(Some(next)) if next.recv_ms == record.recv_ms) {
+                ctx.publish_composites();
+            }
+            if matches!(next, Ok(None)) {
+                log::info!("Журнал воспроизведен до конца");
+                ctx.publish(&Message::Status {
+                    message: "Журнал воспроизведен до конца".to_string(),
+                });
+            }
+        }
+        ctx.broadcast.close();
+        log::info!("Поток воспроизведения журнала остановлен");
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+
+    use quote_lib::{Price, StockQuote, Timestamp, Trade, Volume};
+
+    use super::*;
+
+    /// Временный каталог журнала, удаляется после теста.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("journal-test-{}-{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&dir);
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn record(recv_ms: u64) -> JournalRecord {
+        let price = Price::from_units(1_500_000 + recv_ms as i64);
+        let timestamp = Timestamp::from_millis(1_700_000_000_000 + recv_ms);
+        let message = if recv_ms.is_multiple_of(2) {
+            Message::Quote(StockQuote {
+                ticker: "AAPL".to_string(),
+                price,
+                volume: Volume::new(recv_ms),
+                timestamp,
+                bid: price,
+                ask: price,
+                bid_size: Volume::new(100),
+                ask_size: Volume::new(200),
+            })
+        } else {
+            Message::Trade(Trade {
+                ticker: "MSFT".to_string(),
+                price,
+                size: Volume::new(recv_ms),
+                timestamp,
+            })
+        };
+        JournalRecord { recv_ms, message }
+    }
+
+    fn write(dir: &Path, segment_size: u64, times: &[u64]) {
+        let mut writer = JournalWriter::new(dir, segment_size).unwrap();
+        for &recv_ms in times {
+            writer.append(&record(recv_ms)).unwrap();
+        }
+        writer.flush().unwrap();
+    }
+
+    fn read_all(reader: &mut JournalReader) -> Vec<u64> {
+        let mut times = vec![];
+        while let Some(read) = reader.next_record().unwrap() {
+            assert_eq!(
+                format!("{:?}", read.message),
+                format!("{:?}", record(read.recv_ms).message)
+            );
+            times.push(read.recv_ms);
+        }
+        times
+    }
+
+    fn segments(dir: &Path) -> Vec<PathBuf> {
+        let mut segments: Vec<PathBuf> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        segments.sort();
+        segments
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let dir = TempDir::new("round-trip");
+        write(&dir.0, 1 << 20, &[1000, 1001, 1002, 1003]);
+        assert_eq!(segments(&dir.0).len(), 1);
+        let mut reader = JournalReader::open(&dir.0).unwrap();
+        assert_eq!(read_all(&mut reader), [1000, 1001, 1002, 1003]);
+        assert!(reader.next_record().unwrap().is_none());
+    }
+
+    #[test]
+    fn segments_rotate_by_size() {
+        let dir = TempDir::new("rotation");
+        let times: Vec<u64> = (1000..1010).collect();
+        // Каждая запись длиннее размера сегмента: по записи на сегмент
+        write(&dir.0, 16, &times);
+        let segments = segments(&dir.0);
+        assert_eq!(segments.len(), times.len());
+        assert_eq!(segment_start(&segments[3]), Some(1003));
+        let mut reader = JournalReader::open(&dir.0).unwrap();
+        assert_eq!(read_all(&mut reader), times);
+    }
+
+    #[test]
+    fn truncated_trailing_record_is_skipped() {
+        let dir = TempDir::new("truncated");
+        write(&dir.0, 1 << 20, &[1000, 1001, 1002]);
+        let path = segments(&dir.0).pop().unwrap();
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        let len = file.metadata().unwrap().len();
+        file.set_len(len - 5).unwrap();
+        drop(file);
+
+        let mut reader = JournalReader::open(&dir.0).unwrap();
+        assert_eq!(read_all(&mut reader), [1000, 1001]);
+
+        // Оборвано время получения последней записи
+        let last = record(1002).message.encode(QuoteFormat::TextV2).unwrap();
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 10 - last.len() as u64 + 4).unwrap();
+        drop(file);
+        let mut reader = JournalReader::open(&dir.0).unwrap();
+        assert_eq!(read_all(&mut reader), [1000, 1001]);
+    }
+
+    #[test]
+    fn seek_across_segments() {
+        let dir = TempDir::new("seek");
+        let times: Vec<u64> = (0..10).map(|i| 1000 + i * 100).collect();
+        write(&dir.0, 64, &times);
+        assert!(segments(&dir.0).len() > 2);
+        let mut reader = JournalReader::open(&dir.0).unwrap();
+
+        let found = reader.seek(1500).unwrap().unwrap();
+        assert_eq!(found.recv_ms, 1500);
+        assert_eq!(reader.next_record().unwrap().unwrap().recv_ms, 1600);
+
+        // Между записями - следующая запись, назад - с начала нужного сегмента
+        assert_eq!(reader.seek(1250).unwrap().unwrap().recv_ms, 1300);
+        assert_eq!(reader.seek(0).unwrap().unwrap().recv_ms, 1000);
+        assert!(reader.seek(5000).unwrap().is_none());
+        assert!(reader.next_record().unwrap().is_none());
+    }
+
+    #[test]
+    fn missing_or_foreign_segments() {
+        let dir = TempDir::new("foreign");
+        fs::create_dir_all(&dir.0).unwrap();
+        assert!(JournalReader::open(&dir.0).is_err());
+        fs::write(dir.0.join("journal-1-0.msj"), b"XXXX").unwrap();
+        let mut reader = JournalReader::open(&dir.0).unwrap();
+        assert!(reader.next_record().is_err());
+    }
+
+    #[test]
+    fn replay_speed_bounds() {
+        assert_eq!(parse_replay_speed("2.5"), Ok(2.5));
+        assert_eq!(parse_replay_speed("0.01"), Ok(MIN_REPLAY_SPEED));
+        for value in ["0", "-1", "1e-300", "NaN", "inf", "fast"] {
+            assert!(parse_replay_speed(value).is_err(), "{}", value);
+        }
+    }
+}
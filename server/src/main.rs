@@ -4,8 +4,10 @@
 //! Сервер стриминга котировок
 //! Пример запуска:
 //! cargo run -- --path tickers.txt --port 8080
+//! cargo run -- --path tickers.txt --port 8080 --bind :: --udp-bind ::
 
 use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     sync::{
         Arc, Mutex,
         atomic::{AtomicBool, Ordering},
@@ -14,11 +16,25 @@ use std::{
 };
 
 use clap::Parser;
-use crossbeam::channel;
 
+mod admin;
+mod alerts;
+mod bar_aggregator;
 mod client_manager;
 mod command_handler;
+mod composites;
+mod http_gateway;
+mod indicators;
+mod journal;
+mod multicast;
+mod order_book;
+mod quote_broadcast;
+mod quote_cache;
 mod quote_generator;
+mod quote_history;
+mod stream_filter;
+mod trading;
+mod ws_gateway;
 
 #[derive(clap::Parser)]
 struct Args {
@@ -27,6 +43,109 @@ struct Args {
 
     #[clap(long, default_value = "8080")]
     port: u16,
+
+    /// Адрес, на котором слушает TCP сервер (`::` - все интерфейсы IPv4 и IPv6).
+    #[clap(long, default_value = "127.0.0.1")]
+    bind: IpAddr,
+
+    /// Адрес, к которому привязывается UDP сокет отправки котировок.
+    #[clap(long, default_value = "0.0.0.0")]
+    udp_bind: IpAddr,
+
+    /// Файл с multicast группами, строка: `239.255.0.1:30001 AAPL,MSFT`.
+    #[clap(long)]
+    multicast_groups: Option<String>,
+
+    /// Интерфейс для multicast рассылки (`127.0.0.1` - только loopback).
+    #[clap(long, default_value = "0.0.0.0")]
+    multicast_if: Ipv4Addr,
+
+    /// Формат котировок в multicast группах: `v1`, `v2` или `bin`.
+    #[clap(long, default_value = "v1")]
+    multicast_format: quote_lib::QuoteFormat,
+
+    /// TTL multicast пакетов.
+    #[clap(long, default_value = "1")]
+    multicast_ttl: u32,
+
+    /// Интервал heartbeat в секундах для потоков по TCP, 0 - не отправлять.
+    #[clap(long, default_value = "2")]
+    tcp_heartbeat: u64,
+
+    /// Порт WebSocket шлюза для браузеров, без параметра шлюз не запускается.
+    #[clap(long)]
+    ws_port: Option<u16>,
+
+    /// Порт HTTP сервера (REST снимки и Server-Sent Events), без параметра не запускается.
+    #[clap(long)]
+    http_port: Option<u16>,
+
+    /// Порт административного сервера (`STATS`, `CLIENTS`, `KICK`), без параметра не запускается.
+    #[clap(long)]
+    admin_port: Option<u16>,
+
+    /// Адрес административного сервера, по умолчанию доступен только локально.
+    #[clap(long, default_value = "127.0.0.1")]
+    admin_bind: IpAddr,
+
+    /// Размер очереди сообщений каждого клиента.
+    #[clap(long, default_value = "8192")]
+    client_queue_size: usize,
+
+    /// Действие при переполнении очереди клиента: удалить самое старое сообщение,
+    /// оставить последнюю котировку каждого тикера или отключить поток клиента.
+    #[clap(long, value_enum, default_value = "drop-oldest")]
+    overflow_policy: quote_broadcast::OverflowPolicy,
+
+    /// Отправлять последние котировки в начале каждого нового потока.
+    #[clap(long)]
+    initial_snapshot: bool,
+
+    /// Число хранимых котировок на тикер для команды HISTORY, 0 - не хранить.
+    #[clap(long, default_value = "1000")]
+    history_size: usize,
+
+    /// Число уровней симулируемого стакана с каждой стороны, 0 - стакан не генерируется,
+    /// не больше [`quote_lib::MAX_BOOK_DEPTH`].
+    #[clap(long, default_value = "5", value_parser = order_book::parse_book_depth)]
+    book_depth: usize,
+
+    /// Индикаторы по каждому тикеру через запятую (`VWAP`, `SMA20`, `EMA20`, `VOL20`),
+    /// публикуются как тикеры `AAPL.VWAP`; пустая строка - без индикаторов.
+    #[clap(long, default_value = "VWAP,SMA20,EMA20,VOL20")]
+    indicators: String,
+
+    /// Файл составных инструментов, строка: `TECH sum AAPL:0.5 MSFT:0.5` или `IDX3 index 3 AAPL MSFT GOOGL`.
+    #[clap(long)]
+    composites: Option<String>,
+
+    /// Интервалы свечей через запятую, пустая строка - свечи не строятся.
+    #[clap(long, default_value = "1s,1m,5m")]
+    bar_intervals: String,
+
+    /// Ожидание опоздавших сделок перед закрытием свечи в миллисекундах.
+    #[clap(long, default_value = "1000")]
+    bar_lateness_ms: u64,
+
+    /// Число хранимых свечей на тикер и интервал для истории и поправок.
+    #[clap(long, default_value = "1000")]
+    bar_history: usize,
+
+    /// Каталог журнала котировок, без параметра журнал не ведется.
+    #[clap(long)]
+    journal_dir: Option<String>,
+
+    /// Размер сегмента журнала в байтах, после которого начинается новый сегмент.
+    #[clap(long, default_value = "67108864")]
+    journal_segment_size: u64,
+
+    /// Воспроизводить журнал из каталога вместо генерации котировок.
+    #[clap(long)]
+    replay: Option<String>,
+
+    /// Множитель скорости воспроизведения журнала.
+    #[clap(long, default_value = "1.0", value_parser = journal::parse_replay_speed)]
+    replay_speed: f64,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -50,32 +169,137 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let tickers = load_tickers(args.path).unwrap_or_else(|e| {
         log::error!("Ошибка загрузки файла: {}", e);
-        let q = vec![
-            "AAPL".to_string(),
-            "GOOGL".to_string(),
-            "MSFT".to_string(),
-            "AMZN".to_string(),
-            "TSLA".to_string(),
-        ];
+        let q = ["AAPL", "GOOGL", "MSFT", "AMZN", "TSLA"];
         log::info!("Используются предустановленные: {:?}", q);
-        q
+        q.iter()
+            .map(|ticker| quote_generator::Instrument {
+                ticker: ticker.to_string(),
+                tick_size: quote_lib::Price::DEFAULT_TICK,
+            })
+            .collect()
     });
 
     log::info!("Загружено {} тикетов", tickers.len());
 
     let client_manager = Arc::new(Mutex::new(client_manager::ClientManager::new()));
 
-    let (quote_sender, quote_receiver) = channel::unbounded();
+    let broadcast = Arc::new(quote_broadcast::QuoteBroadcast::new(
+        args.client_queue_size,
+        args.overflow_policy,
+    ));
+    let quote_cache = Arc::new(quote_cache::QuoteCache::new());
+    let quote_history = Arc::new(quote_history::QuoteHistory::new(args.history_size));
+    let book_cache = Arc::new(order_book::BookCache::new());
+    let bar_intervals = args
+        .bar_intervals
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::parse)
+        .collect::<Result<Vec<quote_lib::BarInterval>, _>>()?;
+    let indicators = args
+        .indicators
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::parse)
+        .collect::<Result<Vec<indicators::Indicator>, _>>()?;
+    let indicators = Arc::new(indicators::IndicatorEngine::new(indicators));
+    let composites = match &args.composites {
+        Some(path) => composites::load_composites(path)?,
+        None => vec![],
+    };
+    log::info!("Загружено {} составных инструментов", composites.len());
+    let composites = Arc::new(composites::CompositeEngine::new(composites));
+    let bar_aggregator = Arc::new(bar_aggregator::BarAggregator::new(
+        bar_intervals,
+        args.bar_lateness_ms,
+        args.bar_history,
+    ));
+
+    let multicast_groups = match &args.multicast_groups {
+        Some(path) => multicast::load_groups(path)?,
+        None => vec![],
+    };
+    let multicast_groups = Arc::new(multicast_groups);
+    if !multicast_groups.is_empty() {
+        log::info!("Загружено {} multicast групп", multicast_groups.len());
+        let handler = multicast::start_multicast_publisher(
+            multicast_groups.clone(),
+            args.multicast_if,
+            args.multicast_ttl,
+            args.multicast_format,
+            broadcast.subscribe(),
+            running.clone(),
+        )?;
+        handles.push(handler);
+    }
 
     let running_clone = running.clone();
-    let handler = start_quote_generator(tickers, quote_sender.clone(), running_clone);
+    let handler =
+        start_inactive_client_monitor(client_manager.clone(), broadcast.clone(), running_clone);
     handles.push(handler);
 
-    let running_clone = running.clone();
-    let handler = start_inactive_client_monitor(client_manager.clone(), running_clone);
+    let replay = args
+        .replay
+        .as_ref()
+        .map(|_| Arc::new(journal::ReplayControl::new(args.replay_speed)));
+
+    let ctx = command_handler::ServerContext {
+        client_manager,
+        broadcast,
+        quote_cache,
+        quote_history,
+        book_cache,
+        bar_aggregator,
+        indicators,
+        composites,
+        udp_bind: args.udp_bind,
+        multicast_groups,
+        tcp_heartbeat: (args.tcp_heartbeat > 0)
+            .then(|| std::time::Duration::from_secs(args.tcp_heartbeat)),
+        send_initial_snapshot: args.initial_snapshot,
+        book_depth: args.book_depth,
+        replay: replay.clone(),
+        stats: Arc::new(admin::ServerStats::new()),
+        running,
+    };
+
+    if let Some(dir) = &args.journal_dir {
+        let writer = journal::JournalWriter::new(dir.as_ref(), args.journal_segment_size)?;
+        let handler = journal::start_journal_writer(writer, ctx.clone());
+        handles.push(handler);
+    }
+
+    let handler = match (&args.replay, replay) {
+        (Some(dir), Some(control)) => {
+            log::info!("Воспроизведение журнала {} вместо генерации", dir);
+            let reader = journal::JournalReader::open(dir.as_ref())?;
+            journal::start_journal_replay(reader, control, ctx.clone())
+        }
+        _ => start_quote_generator(tickers, args.book_depth, ctx.clone()),
+    };
     handles.push(handler);
 
-    let handler = start_tcp_server(client_manager, quote_receiver.clone(), args.port, running)?;
+    if let Some(ws_port) = args.ws_port {
+        let handler =
+            ws_gateway::start_ws_server(SocketAddr::new(args.bind, ws_port), ctx.clone())?;
+        handles.push(handler);
+    }
+
+    if let Some(http_port) = args.http_port {
+        let handler =
+            http_gateway::start_http_server(SocketAddr::new(args.bind, http_port), ctx.clone())?;
+        handles.push(handler);
+    }
+
+    if let Some(admin_port) = args.admin_port {
+        let handler =
+            admin::start_admin_server(SocketAddr::new(args.admin_bind, admin_port), ctx.clone())?;
+        handles.push(handler);
+    }
+
+    let handler = start_tcp_server(SocketAddr::new(args.bind, args.port), ctx)?;
     handles.extend(handler);
 
     for handle in handles {
@@ -85,45 +309,77 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn load_tickers(path: String) -> Result<Vec<String>, std::io::Error> {
+/// Загрузка инструментов, строка файла: `AAPL` или `AAPL 0.05` с шагом цены.
+fn load_tickers(
+    path: String,
+) -> Result<Vec<quote_generator::Instrument>, Box<dyn std::error::Error>> {
     let content = std::fs::read_to_string(path)?;
-    Ok(content
-        .lines()
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
-        .collect())
+    let mut instruments = vec![];
+    for line in content.lines() {
+        let mut parts = line.split_whitespace();
+        let Some(ticker) = parts.next() else {
+            continue;
+        };
+        let tick_size = match parts.next() {
+            Some(tick) => tick.parse()?,
+            None => quote_lib::Price::DEFAULT_TICK,
+        };
+        if tick_size <= quote_lib::Price::ZERO {
+            return Err(format!("Некорректный шаг цены для {}: {}", ticker, tick_size).into());
+        }
+        instruments.push(quote_generator::Instrument {
+            ticker: ticker.to_string(),
+            tick_size,
+        });
+    }
+    Ok(instruments)
 }
 
 fn start_quote_generator(
-    tickers: Vec<String>,
-    quote_sender: channel::Sender<quote_lib::StockQuote>,
-    running: Arc<AtomicBool>,
+    tickers: Vec<quote_generator::Instrument>,
+    book_depth: usize,
+    ctx: command_handler::ServerContext,
 ) -> JoinHandle<()> {
     thread::spawn(move || {
         log::info!("Запуск потока генерации котировок");
-        let mut generator = quote_generator::QuoteGenerator::new(tickers);
-        while running.load(Ordering::SeqCst) {
-            let quotes = generator.generate_quotes();
-
-            for quote in quotes {
-                let _ = quote_sender.send(quote);
+        let mut generator = quote_generator::QuoteGenerator::new(tickers, book_depth);
+        while ctx.running.load(Ordering::SeqCst) {
+            for message in generator.generate_messages() {
+                ctx.publish(&message);
             }
+            ctx.publish_composites();
 
             thread::sleep(std::time::Duration::from_millis(500));
         }
+        ctx.publish(&quote_lib::Message::Status {
+            message: "Сервер остановлен".to_string(),
+        });
+        ctx.broadcast.close();
         log::info!("Поток генерации котировок остановлен");
     })
 }
 
 fn start_inactive_client_monitor(
     client_manager: Arc<Mutex<client_manager::ClientManager>>,
+    broadcast: Arc<quote_broadcast::QuoteBroadcast>,
     running: Arc<AtomicBool>,
 ) -> JoinHandle<()> {
     thread::spawn(move || {
         log::info!("Запуск потока удаления неактивных клиентов");
+        let mut reported = (0, 0);
         while running.load(Ordering::SeqCst) {
             thread::sleep(std::time::Duration::from_secs(5));
 
+            let overflow = (broadcast.dropped(), broadcast.disconnected());
+            if overflow != reported {
+                log::warn!(
+                    "Очереди клиентов: пропущено сообщений {}, отключено потоков {}",
+                    overflow.0,
+                    overflow.1
+                );
+                reported = overflow;
+            }
+
             let inactive_clients = client_manager
                 .lock()
                 .unwrap()
@@ -140,30 +396,36 @@ fn start_inactive_client_monitor(
     })
 }
 
+/// TCP сокет на адресе: на `::` принимает и IPv4 соединения независимо от настройки
+/// `IPV6_V6ONLY` в системе.
+pub(crate) fn bind_tcp_listener(addr: SocketAddr) -> std::io::Result<std::net::TcpListener> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(addr),
+        socket2::Type::STREAM,
+        Some(socket2::Protocol::TCP),
+    )?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(false)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(128)?;
+    Ok(socket.into())
+}
+
 fn start_tcp_server(
-    client_manager: Arc<Mutex<client_manager::ClientManager>>,
-    quote_receiver: channel::Receiver<quote_lib::StockQuote>,
-    port: u16,
-    running: Arc<AtomicBool>,
+    addr: SocketAddr,
+    ctx: command_handler::ServerContext,
 ) -> Result<Vec<JoinHandle<()>>, Box<dyn std::error::Error>> {
-    let listner = std::net::TcpListener::bind(format!("127.0.0.1:{}", port))?;
+    let listner = bind_tcp_listener(addr)?;
     listner.set_nonblocking(true)?;
-    log::info!("TSP сервер запущен на порту {}", port);
+    log::info!("TSP сервер запущен на {}", addr);
     let mut handles = vec![];
-    while running.load(Ordering::SeqCst) {
+    while ctx.running.load(Ordering::SeqCst) {
         match listner.accept() {
             Ok((stream, _)) => {
-                let client_manager = client_manager.clone();
-                let quote_receiver = quote_receiver.clone();
-                let running_clone = running.clone();
-                let handle = thread::spawn(move || {
-                    command_handler::handle_client(
-                        stream,
-                        client_manager,
-                        quote_receiver,
-                        running_clone,
-                    )
-                });
+                let ctx = ctx.clone();
+                let handle = thread::spawn(move || command_handler::handle_client(stream, ctx));
                 handles.push(handle);
             }
             Err(e) => {
@@ -171,6 +433,6 @@ fn start_tcp_server(
             }
         }
     }
-    log::info!("TSP сервер остановлен на порту {}", port);
+    log::info!("TSP сервер остановлен на {}", addr);
     Ok(handles)
 }
@@ -0,0 +1,261 @@
+use std::{
+    collections::HashSet,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crossbeam::channel::{Receiver, RecvTimeoutError};
+use quote_lib::{QuoteFormat, SharedMessage};
+use socket2::SockRef;
+
+/// Группа рассылки: адрес multicast группы и публикуемые в нее тикеры.
+#[derive(Debug, Clone)]
+pub(crate) struct MulticastGroup {
+    pub(crate) addr: SocketAddr,
+    pub(crate) tickers: Vec<String>,
+}
+
+/// Загрузка групп из файла, строка файла: `239.255.0.1:30001 AAPL,MSFT,GOOGL`.
+pub(crate) fn load_groups(path: &str) -> Result<Vec<MulticastGroup>, Box<dyn std::error::Error>> {
+    parse_groups(&std::fs::read_to_string(path)?)
+}
+
+fn parse_groups(content: &str) -> Result<Vec<MulticastGroup>, Box<dyn std::error::Error>> {
+    let mut groups = vec![];
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((addr, tickers)) = line.split_once(char::is_whitespace) else {
+            return Err(format!("Некорректная строка: {}", line).into());
+        };
+        let addr: SocketAddr = addr.parse()?;
+        if !addr.ip().is_multicast() {
+            return Err(format!("Адрес не является multicast: {}", addr).into());
+        }
+        let tickers: Vec<String> = tickers
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        if tickers.is_empty() {
+            return Err(format!("Не указаны тикеры группы: {}", line).into());
+        }
+        groups.push(MulticastGroup { addr, tickers });
+    }
+    Ok(groups)
+}
+
+/// Группы, в которых публикуется хотя бы один из тикеров, с запрошенными тикерами группы.
+pub(crate) fn groups_for_tickers(
+    groups: &[MulticastGroup],
+    tickers: &[String],
+) -> Vec<MulticastGroup> {
+    groups
+        .iter()
+        .filter_map(|group| {
+            let tickers: Vec<String> = tickers
+                .iter()
+                .filter(|t| group.tickers.contains(t))
+                .cloned()
+                .collect();
+            (!tickers.is_empty()).then_some(MulticastGroup {
+                addr: group.addr,
+                tickers,
+            })
+        })
+        .collect()
+}
+
+/// Ответ на `STREAM multicast`: `MULTICAST 239.255.0.1:30001=AAPL,MSFT ...`.
+pub(crate) fn format_reply(groups: &[MulticastGroup]) -> String {
+    let mut reply = quote_lib::MULTICAST_REPLY.to_string();
+    for group in groups {
+        reply.push_str(&format!(" {}={}", group.addr, group.tickers.join(",")));
+    }
+    reply
+}
+
+fn bind_socket(
+    group: &SocketAddr,
+    interface: Ipv4Addr,
+    ttl: u32,
+) -> Result<UdpSocket, std::io::Error> {
+    match group.ip() {
+        IpAddr::V4(_) => {
+            let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))?;
+            socket.set_multicast_ttl_v4(ttl)?;
+            socket.set_multicast_loop_v4(true)?;
+            SockRef::from(&socket).set_multicast_if_v4(&interface)?;
+            Ok(socket)
+        }
+        IpAddr::V6(_) => {
+            let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0))?;
+            socket.set_multicast_loop_v6(true)?;
+            SockRef::from(&socket).set_multicast_hops_v6(ttl)?;
+            Ok(socket)
+        }
+    }
+}
+
+/// Запуск потока публикации котировок в multicast группы.
+pub(crate) fn start_multicast_publisher(
+    groups: Arc<Vec<MulticastGroup>>,
+    interface: Ipv4Addr,
+    ttl: u32,
+    format: QuoteFormat,
+    receiver: Receiver<Arc<SharedMessage>>,
+    running: Arc<AtomicBool>,
+) -> Result<JoinHandle<()>, std::io::Error> {
+    let mut sockets = vec![];
+    let mut families = HashSet::new();
+    for group in groups.iter() {
+        if families.insert(group.addr.is_ipv4()) {
+            sockets.push((
+                group.addr.is_ipv4(),
+                bind_socket(&group.addr, interface, ttl)?,
+            ));
+        }
+    }
+
+    Ok(thread::spawn(move || {
+        log::info!("Запуск потока multicast рассылки: {} групп", groups.len());
+        while running.load(Ordering::SeqCst) {
+            let message = match receiver.recv_timeout(Duration::from_secs(1)) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            let Some(data) = message.encoded(format) else {
+                continue;
+            };
+            // Статусы без тикера отправляются во все группы
+            let ticker = message.ticker();
+            for group in groups
+                .iter()
+                .filter(|g| ticker.is_none_or(|t| g.tickers.iter().any(|gt| gt == t)))
+            {
+                let Some((_, socket)) = sockets
+                    .iter()
+                    .find(|(is_ipv4, _)| *is_ipv4 == group.addr.is_ipv4())
+                else {
+                    continue;
+                };
+                log::debug!("Отправляем {:?} в группу {}", ticker, group.addr);
+                if let Err(e) = socket.send_to(&data, group.addr) {
+                    log::error!("Failed to send multicast data to {}: {}", group.addr, e);
+                }
+            }
+        }
+        log::info!("Поток multicast рассылки остановлен");
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use quote_lib::{Message, Price, StockQuote, Timestamp, Volume};
+
+    use super::*;
+
+    const CONFIG: &str = "# группы\n\n239.255.0.1:30001 AAPL,MSFT\n[ff15::1]:30002  GOOGL, TSLA\n";
+
+    fn tickers(tickers: &[&str]) -> Vec<String> {
+        tickers.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_config() {
+        let groups = parse_groups(CONFIG).unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].addr, "239.255.0.1:30001".parse().unwrap());
+        assert_eq!(groups[0].tickers, ["AAPL", "MSFT"]);
+        assert_eq!(groups[1].addr, "[ff15::1]:30002".parse().unwrap());
+        assert_eq!(groups[1].tickers, ["GOOGL", "TSLA"]);
+    }
+
+    #[test]
+    fn parse_rejects_bad_config() {
+        for config in [
+            "239.255.0.1:30001",
+            "239.255.0.1:30001 ,",
+            "239.255.0.1 AAPL",
+            "127.0.0.1:30001 AAPL",
+            "[::1]:30001 AAPL",
+            "group AAPL",
+        ] {
+            assert!(parse_groups(config).is_err(), "{}", config);
+        }
+    }
+
+    #[test]
+    fn reply_lists_requested_tickers() {
+        let groups = parse_groups(CONFIG).unwrap();
+        let selected = groups_for_tickers(&groups, &tickers(&["MSFT", "TSLA", "IBM"]));
+        assert_eq!(
+            format_reply(&selected),
+            "MULTICAST 239.255.0.1:30001=MSFT [ff15::1]:30002=TSLA"
+        );
+        assert!(groups_for_tickers(&groups, &tickers(&["IBM"])).is_empty());
+    }
+
+    /// Публикация и прием на одном хосте только через loopback интерфейс.
+    #[test]
+    fn publish_over_loopback() {
+        let group: SocketAddr = "239.255.77.1:0".parse().unwrap();
+        let receiver_socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let group = SocketAddr::new(group.ip(), receiver_socket.local_addr().unwrap().port());
+        let IpAddr::V4(group_ip) = group.ip() else {
+            unreachable!()
+        };
+        receiver_socket
+            .join_multicast_v4(&group_ip, &Ipv4Addr::LOCALHOST)
+            .unwrap();
+        receiver_socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let (sender, receiver) = crossbeam::channel::unbounded();
+        let running = Arc::new(AtomicBool::new(true));
+        let handle = start_multicast_publisher(
+            Arc::new(vec![MulticastGroup {
+                addr: group,
+                tickers: tickers(&["AAPL"]),
+            }]),
+            Ipv4Addr::LOCALHOST,
+            0,
+            QuoteFormat::TextV2,
+            receiver,
+            running.clone(),
+        )
+        .unwrap();
+        let price = Price::from_units(1_502_500);
+        sender
+            .send(SharedMessage::new(Message::Quote(StockQuote {
+                ticker: "AAPL".to_string(),
+                price,
+                volume: Volume::new(100),
+                timestamp: Timestamp::from_millis(1_700_000_000_250),
+                bid: price,
+                ask: price,
+                bid_size: Volume::ZERO,
+                ask_size: Volume::ZERO,
+            })))
+            .unwrap();
+
+        let mut buf = [0; 1500];
+        let result = receiver_socket.recv_from(&mut buf);
+        running.store(false, Ordering::SeqCst);
+        drop(sender);
+        handle.join().unwrap();
+        let (size, _) = result.unwrap();
+        assert_eq!(
+            &buf[..size],
+            b"v2|AAPL|150.25|100|1700000000.250|150.25|150.25|0|0"
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::{collections::HashMap, sync::RwLock};
+
+use quote_lib::{BookApply, BookLevel, BookSnapshot, BookUpdate, MAX_BOOK_DEPTH, OrderBook, Side};
+
+/// Разбор глубины стакана из `--book-depth`: не больше [`MAX_BOOK_DEPTH`] уровней,
+/// чтобы снимок помещался в датаграмму.
+pub(crate) fn parse_book_depth(value: &str) -> Result<usize, String> {
+    let depth: usize = value
+        .parse()
+        .map_err(|_| format!("Неверная глубина стакана: {}", value))?;
+    if depth > MAX_BOOK_DEPTH {
+        return Err(format!(
+            "Глубина стакана должна быть не больше {}: {}",
+            MAX_BOOK_DEPTH, value
+        ));
+    }
+    Ok(depth)
+}
+
+/// Текущий стакан по каждому тикеру, собираемый из опубликованных снимков и обновлений.
+pub(crate) struct BookCache {
+    books: RwLock<HashMap<String, OrderBook>>,
+}
+
+impl BookCache {
+    pub(crate) fn new() -> Self {
+        Self {
+            books: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn apply_snapshot(&self, snapshot: &BookSnapshot) {
+        self.books
+            .write()
+            .unwrap()
+            .entry(snapshot.ticker.clone())
+            .or_default()
+            .apply_snapshot(snapshot);
+    }
+
+    /// Применение обновления, при пропуске стакан не отдается до следующего снимка.
+    pub(crate) fn apply_update(&self, update: &BookUpdate) {
+        let mut books = self.books.write().unwrap();
+        let book = books.entry(update.ticker.clone()).or_default();
+        if book.apply_update(update) == BookApply::Gap {
+            log::warn!(
+                "Пропуск обновлений стакана {}: {} после {}",
+                update.ticker,
+                update.seq,
+                book.seq()
+            );
+        }
+    }
+
+    /// Уровни одной стороны согласованного стакана от лучшей цены, `None` - стакана нет.
+    pub(crate) fn levels(&self, ticker: &str, side: Side) -> Option<Vec<BookLevel>> {
+        let books = self.books.read().unwrap();
+        let book = books.get(ticker).filter(|book| book.is_synced())?;
+        Some(match side {
+            Side::Bid => book.bids().collect(),
+            Side::Ask => book.asks().collect(),
+        })
+    }
+
+    /// Снимки согласованных стаканов по тикерам в порядке запроса.
+    pub(crate) fn snapshots(&self, tickers: &[String], depth: usize) -> Vec<BookSnapshot> {
+        let books = self.books.read().unwrap();
+        let timestamp = quote_lib::get_timestamp();
+        tickers
+            .iter()
+            .filter_map(|ticker| {
+                let book = books.get(ticker).filter(|book| book.is_synced())?;
+                Some(book.snapshot(ticker, depth, timestamp))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn book_depth_bounds() {
+        assert_eq!(parse_book_depth("0"), Ok(0));
+        assert_eq!(parse_book_depth("100"), Ok(MAX_BOOK_DEPTH));
+        for value in ["101", "-1", "deep"] {
+            assert!(parse_book_depth(value).is_err(), "{}", value);
+        }
+    }
+}
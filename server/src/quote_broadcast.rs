@@ -0,0 +1,312 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+
+use crossbeam::channel::{self, Receiver, Sender, TrySendError};
+use quote_lib::{Message, SharedMessage};
+
+/// Действие при переполнении очереди клиента.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum OverflowPolicy {
+    /// Удалить самое старое сообщение очереди.
+    DropOldest,
+    /// Оставить в очереди только последнюю котировку каждого тикера,
+    /// если это не освободило места - удалить самое старое сообщение.
+    Conflate,
+    /// Отключить поток клиента.
+    Disconnect,
+}
+
+/// Учет очереди клиента, общий для рассылки и потока отправки.
+#[derive(Default)]
+struct QueueState {
+    /// Удаленные сообщения, еще не сообщенные клиенту.
+    dropped: AtomicU64,
+    /// Поток отключен из-за переполнения очереди.
+    overflowed: AtomicBool,
+}
+
+/// Подписка клиента на рассылку через ограниченную очередь.
+pub(crate) struct Subscription {
+    receiver: Receiver<Arc<SharedMessage>>,
+    state: Arc<QueueState>,
+}
+
+impl Subscription {
+    pub(crate) fn receiver(&self) -> &Receiver<Arc<SharedMessage>> {
+        &self.receiver
+    }
+
+    /// Статус для клиента о потерянных при переполнении сообщениях, счетчик сбрасывается.
+    pub(crate) fn take_status(&self) -> Option<Message> {
+        let dropped = self.state.dropped.swap(0, Ordering::Relaxed);
+        if dropped == 0 {
+            return None;
+        }
+        let message = if self.is_overflowed() {
+            format!(
+                "Поток остановлен: очередь клиента переполнена, пропущено сообщений: {}",
+                dropped
+            )
+        } else {
+            format!("Пропущено сообщений: {}", dropped)
+        };
+        Some(Message::Status { message })
+    }
+
+    /// Поток отключен из-за переполнения очереди, оставшиеся сообщения еще можно получить.
+    pub(crate) fn is_overflowed(&self) -> bool {
+        self.state.overflowed.load(Ordering::Relaxed)
+    }
+}
+
+/// Ограниченная очередь подписчика: рассылка держит копию приемника,
+/// чтобы удалять сообщения при переполнении.
+struct BoundedQueue {
+    receiver: Receiver<Arc<SharedMessage>>,
+    state: Arc<QueueState>,
+    policy: OverflowPolicy,
+    /// Потери учитываются в общих счетчиках клиентов.
+    client: bool,
+}
+
+struct Subscriber {
+    sender: Sender<Arc<SharedMessage>>,
+    queue: Option<BoundedQueue>,
+}
+
+/// Рассылка сообщений от источника всем подписчикам: подписчики получают
+/// одно общее сообщение и разделяют его закодированные представления.
+/// Очереди клиентов ограничены, при переполнении действует `policy`.
+pub(crate) struct QuoteBroadcast {
+    subscribers: Mutex<Vec<Subscriber>>,
+    queue_size: usize,
+    policy: OverflowPolicy,
+    /// Всего удалено сообщений из очередей клиентов.
+    dropped: AtomicU64,
+    /// Всего отключено клиентов из-за переполнения.
+    disconnected: AtomicU64,
+}
+
+impl QuoteBroadcast {
+    pub(crate) fn new(queue_size: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            subscribers: Mutex::new(Vec::new()),
+            queue_size: queue_size.max(1),
+            policy,
+            dropped: AtomicU64::new(0),
+            disconnected: AtomicU64::new(0),
+        }
+    }
+
+    /// Подписка без ограничения очереди для внутренних потребителей:
+    /// журнала и multicast рассылки.
+    pub(crate) fn subscribe(&self) -> Receiver<Arc<SharedMessage>> {
+        let (sender, receiver) = channel::unbounded();
+        self.subscribers.lock().unwrap().push(Subscriber {
+            sender,
+            queue: None,
+        });
+        receiver
+    }
+
+    /// Подписка клиента с ограниченной очередью.
+    pub(crate) fn subscribe_client(&self) -> Subscription {
+        self.subscribe_bounded(self.policy, true)
+    }
+
+    /// Подписка сессии управляющего соединения (торговля, уведомления о цене):
+    /// очередь того же размера, что у клиентов, при переполнении остается последняя
+    /// котировка каждого тикера, сессия не отключается и не входит в счетчики клиентов.
+    pub(crate) fn subscribe_session(&self) -> Subscription {
+        self.subscribe_bounded(OverflowPolicy::Conflate, false)
+    }
+
+    fn subscribe_bounded(&self, policy: OverflowPolicy, client: bool) -> Subscription {
+        let (sender, receiver) = channel::bounded(self.queue_size);
+        let state = Arc::new(QueueState::default());
+        self.subscribers.lock().unwrap().push(Subscriber {
+            sender,
+            queue: Some(BoundedQueue {
+                receiver: receiver.clone(),
+                state: state.clone(),
+                policy,
+                client,
+            }),
+        });
+        Subscription { receiver, state }
+    }
+
+    /// Отправка сообщения всем подписчикам, отключившиеся подписчики удаляются.
+    pub(crate) fn send(&self, message: &Message) {
+        let message = SharedMessage::new(message.clone());
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| match &subscriber.queue {
+                None => subscriber.sender.send(message.clone()).is_ok(),
+                Some(queue) => self.push(&subscriber.sender, queue, &message),
+            });
+    }
+
+    /// Отправка в ограниченную очередь, `false` - подписчик отключается.
+    fn push(
+        &self,
+        sender: &Sender<Arc<SharedMessage>>,
+        queue: &BoundedQueue,
+        message: &Arc<SharedMessage>,
+    ) -> bool {
+        // Клиент закрыл подписку, копия приемника только у рассылки
+        if Arc::strong_count(&queue.state) == 1 {
+            return false;
+        }
+        let Err(TrySendError::Full(message)) = sender.try_send(message.clone()) else {
+            return true;
+        };
+
+        let dropped = match queue.policy {
+            OverflowPolicy::Disconnect => {
+                queue.state.overflowed.store(true, Ordering::Relaxed);
+                if queue.client {
+                    self.disconnected.fetch_add(1, Ordering::Relaxed);
+                }
+                self.count_dropped(queue, 1);
+                return false;
+            }
+            OverflowPolicy::DropOldest => {
+                let dropped = queue.receiver.try_recv().map_or(0, |_| 1);
+                dropped + u64::from(sender.try_send(message).is_err())
+            }
+            OverflowPolicy::Conflate => {
+                let mut messages: Vec<_> = queue.receiver.try_iter().collect();
+                messages.push(message);
+                let total = messages.len();
+                let messages = conflate(messages, self.queue_size);
+                let kept = messages
+                    .into_iter()
+                    .take_while(|message| sender.try_send(message.clone()).is_ok())
+                    .count();
+                (total - kept) as u64
+            }
+        };
+        self.count_dropped(queue, dropped);
+        true
+    }
+
+    fn count_dropped(&self, queue: &BoundedQueue, dropped: u64) {
+        queue.state.dropped.fetch_add(dropped, Ordering::Relaxed);
+        if queue.client {
+            self.dropped.fetch_add(dropped, Ordering::Relaxed);
+        }
+    }
+
+    /// Всего удалено сообщений из очередей клиентов.
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Всего отключено клиентов из-за переполнения очереди.
+    pub(crate) fn disconnected(&self) -> u64 {
+        self.disconnected.load(Ordering::Relaxed)
+    }
+
+    /// Отключение всех подписчиков, их `recv` вернет ошибку.
+    pub(crate) fn close(&self) {
+        self.subscribers.lock().unwrap().clear();
+    }
+}
+
+/// Последняя котировка каждого тикера на месте последней, остальные сообщения без изменений;
+/// лишние сверх `capacity` удаляются с начала.
+fn conflate(messages: Vec<Arc<SharedMessage>>, capacity: usize) -> Vec<Arc<SharedMessage>> {
+    let mut seen = HashSet::new();
+    let mut result: Vec<_> = messages
+        .into_iter()
+        .rev()
+        .filter(|message| match message.message() {
+            Message::Quote(quote) => seen.insert(quote.ticker.clone()),
+            _ => true,
+        })
+        .collect();
+    result.truncate(capacity);
+    result.reverse();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use quote_lib::{Price, StockQuote, Timestamp, Volume};
+
+    use super::*;
+
+    fn quote(ticker: &str, units: i64) -> Message {
+        let price = Price::from_units(units);
+        Message::Quote(StockQuote {
+            ticker: ticker.to_string(),
+            price,
+            volume: Volume::ZERO,
+            timestamp: Timestamp::from_millis(0),
+            bid: price,
+            ask: price,
+            bid_size: Volume::ZERO,
+            ask_size: Volume::ZERO,
+        })
+    }
+
+    fn received(subscription: &Subscription) -> Vec<(String, i64)> {
+        subscription
+            .receiver()
+            .try_iter()
+            .filter_map(|message| match message.message() {
+                Message::Quote(quote) => Some((quote.ticker.clone(), quote.price.units())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn session_conflates_under_disconnect_policy() {
+        let broadcast = QuoteBroadcast::new(2, OverflowPolicy::Disconnect);
+        let session = broadcast.subscribe_session();
+        let client = broadcast.subscribe_client();
+        for units in 1..=5 {
+            broadcast.send(&quote("AAPL", units));
+            broadcast.send(&quote("MSFT", units * 10));
+        }
+        assert_eq!(
+            received(&session),
+            [("AAPL".to_string(), 5), ("MSFT".to_string(), 50)]
+        );
+        assert!(!session.is_overflowed());
+        assert!(client.is_overflowed());
+        // Потери сессии не входят в счетчики клиентов
+        assert_eq!(broadcast.disconnected(), 1);
+        assert_eq!(broadcast.dropped(), 1);
+
+        broadcast.send(&quote("AAPL", 6));
+        assert_eq!(received(&session), [("AAPL".to_string(), 6)]);
+    }
+
+    #[test]
+    fn drop_oldest_keeps_latest() {
+        let broadcast = QuoteBroadcast::new(2, OverflowPolicy::DropOldest);
+        let client = broadcast.subscribe_client();
+        for units in 1..=4 {
+            broadcast.send(&quote("AAPL", units));
+        }
+        assert_eq!(
+            received(&client),
+            [("AAPL".to_string(), 3), ("AAPL".to_string(), 4)]
+        );
+        assert!(matches!(
+            client.take_status(),
+            Some(Message::Status { message }) if message == "Пропущено сообщений: 2"
+        ));
+        assert!(client.take_status().is_none());
+        assert_eq!(broadcast.dropped(), 2);
+    }
+}
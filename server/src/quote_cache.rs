@@ -0,0 +1,43 @@
+use std::{collections::HashMap, sync::RwLock};
+
+use quote_lib::StockQuote;
+
+/// Последняя котировка по каждому тикеру.
+pub(crate) struct QuoteCache {
+    quotes: RwLock<HashMap<String, StockQuote>>,
+}
+
+impl QuoteCache {
+    pub(crate) fn new() -> Self {
+        Self {
+            quotes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn update(&self, quote: &StockQuote) {
+        self.quotes
+            .write()
+            .unwrap()
+            .insert(quote.ticker.clone(), quote.clone());
+    }
+
+    pub(crate) fn get(&self, ticker: &str) -> Option<StockQuote> {
+        self.quotes.read().unwrap().get(ticker).cloned()
+    }
+
+    /// Последние котировки по тикерам в порядке запроса, тикеры без котировок пропускаются.
+    pub(crate) fn snapshot(&self, tickers: &[String]) -> Vec<StockQuote> {
+        let quotes = self.quotes.read().unwrap();
+        tickers
+            .iter()
+            .filter_map(|ticker| quotes.get(ticker).cloned())
+            .collect()
+    }
+
+    /// Последние котировки по всем тикерам, отсортированные по тикеру.
+    pub(crate) fn all(&self) -> Vec<StockQuote> {
+        let mut quotes: Vec<StockQuote> = self.quotes.read().unwrap().values().cloned().collect();
+        quotes.sort_by(|a, b| a.ticker.cmp(&b.ticker));
+        quotes
+    }
+}
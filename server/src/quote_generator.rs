@@ -1,53 +1,318 @@
 use std::collections::HashMap;
 
-use quote_lib::StockQuote;
+use quote_lib::{
+    BookUpdate, LevelChange, Message, OrderBook, Price, Side, StockQuote, Trade, Volume,
+};
 use rand::Rng;
 
+/// Инструмент для генерации: тикер и шаг цены.
+pub(crate) struct Instrument {
+    pub(crate) ticker: String,
+    pub(crate) tick_size: Price,
+}
+
+/// Состояние рынка по инструменту: средняя цена, лучшие цены, стакан и последняя сделка.
+struct Market {
+    tick: Price,
+    mid: Price,
+    bid: Price,
+    ask: Price,
+    bid_size: Volume,
+    ask_size: Volume,
+    last_price: Price,
+    last_size: Volume,
+    book: OrderBook,
+}
+
 pub(crate) struct QuoteGenerator {
-    prices: HashMap<String, f64>,
+    markets: HashMap<String, Market>,
     tickers: Vec<String>,
+    /// Число уровней стакана с каждой стороны, 0 - стакан не генерируется.
+    book_depth: usize,
+    /// Номер такта для периодических снимков стакана.
+    tick_count: u64,
 }
 
+/// Вероятность обновления котировки по тикеру за один такт.
+const QUOTE_PROBABILITY: f64 = 0.7;
+/// Вероятность сделки по тикеру за один такт.
+const TRADE_PROBABILITY: f64 = 0.5;
+/// Полный снимок стакана рассылается раз в столько тактов, с первого такта.
+const BOOK_SNAPSHOT_INTERVAL: u64 = 20;
+/// Вероятность сохранить объем уровня стакана за лучшей ценой при обновлении.
+const LEVEL_KEEP_PROBABILITY: f64 = 0.7;
+
 impl QuoteGenerator {
-    pub(crate) fn new(tickers: Vec<String>) -> Self {
-        let mut prices = HashMap::new();
+    pub(crate) fn new(instruments: Vec<Instrument>, book_depth: usize) -> Self {
+        let mut markets = HashMap::new();
+        let mut tickers = Vec::new();
         let mut rng = rand::rng();
-        for ticker in &tickers {
-            prices.insert(ticker.clone(), rng.random_range(100.0..1000.0));
+        for instrument in instruments {
+            let tick = instrument.tick_size;
+            let mid = Price::from_f64(rng.random_range(100.0..1000.0)).round_to_tick(tick);
+            markets.insert(
+                instrument.ticker.clone(),
+                Market {
+                    tick,
+                    mid,
+                    bid: mid,
+                    ask: mid,
+                    bid_size: Volume::ZERO,
+                    ask_size: Volume::ZERO,
+                    last_price: mid,
+                    last_size: Volume::ZERO,
+                    book: OrderBook::new(),
+                },
+            );
+            tickers.push(instrument.ticker);
         }
 
-        QuoteGenerator { prices, tickers }
+        QuoteGenerator {
+            markets,
+            tickers,
+            book_depth,
+            tick_count: 0,
+        }
     }
 
-    pub(crate) fn generate_quotes(&mut self) -> Vec<StockQuote> {
-        let mut quotes = Vec::new();
+    /// Сообщения за один такт: обновления котировок и сделки по тикерам генерируются независимо,
+    /// за каждым изменением следует обновление стакана, периодически - полный снимок.
+    pub(crate) fn generate_messages(&mut self) -> Vec<Message> {
+        let mut messages = Vec::new();
+        let mut rng = rand::rng();
+
+        if self.book_depth > 0 && self.tick_count.is_multiple_of(BOOK_SNAPSHOT_INTERVAL) {
+            let timestamp = quote_lib::get_timestamp();
+            for ticker in &self.tickers {
+                if let Some(market) = self.markets.get(ticker) {
+                    let snapshot = market.book.snapshot(ticker, self.book_depth, timestamp);
+                    messages.push(Message::BookSnapshot(snapshot));
+                }
+            }
+        }
+        self.tick_count += 1;
 
         for ticker in &self.tickers.clone() {
-            if let Some(quote) = self.generate_quote(ticker) {
-                quotes.push(quote);
+            if rng.random_bool(QUOTE_PROBABILITY)
+                && let Some(quote) = self.generate_quote(ticker)
+            {
+                messages.push(Message::Quote(quote));
+                messages.extend(self.rebuild_book(ticker).map(Message::BookUpdate));
+            }
+            if rng.random_bool(TRADE_PROBABILITY)
+                && let Some(trade) = self.generate_trade(ticker)
+            {
+                let update = self.fill_book_level(&trade);
+                messages.push(Message::Trade(trade));
+                messages.extend(update.map(Message::BookUpdate));
             }
         }
-        quotes
+        messages
     }
 
     fn generate_quote(&mut self, ticker: &str) -> Option<StockQuote> {
-        let price = self.prices.get_mut(ticker)?;
-        *price += rand::rng().random_range(-5.0..5.0);
+        let market = self.markets.get_mut(ticker)?;
+        let tick = market.tick;
+        let mut rng = rand::rng();
+
+        let change = Price::from_f64(rng.random_range(-5.0..5.0)).round_to_tick(tick);
+        market.mid += change;
 
-        if *price < 1.0 {
-            *price = 1.0;
+        let min_price = Price::from_f64(1.0).round_to_tick(tick).max(tick);
+        if market.mid < min_price {
+            market.mid = min_price;
         }
 
-        let volume: f64 = match ticker {
-            "AAPL" | "MSFT" | "GOOGL" | "TSLA" => 1000_f64 + (rand::random::<f64>() * 5000.0),
-            _ => 100_f64 + (rand::random::<f64>() * 1000.0),
-        };
+        // Спред в 1-4 шага вокруг средней цены
+        let spread_ticks = rng.random_range(1..=4);
+        market.bid = (market.mid - tick.checked_mul(spread_ticks / 2)?).max(tick);
+        market.ask = market.bid + tick.checked_mul(spread_ticks)?;
+        market.bid_size = Volume::new(rng.random_range(1..=50) * 100);
+        market.ask_size = Volume::new(rng.random_range(1..=50) * 100);
 
         Some(StockQuote {
             ticker: ticker.to_string(),
-            price: *price,
-            volume,
+            price: market.last_price,
+            volume: market.last_size,
             timestamp: quote_lib::get_timestamp(),
+            bid: market.bid,
+            ask: market.ask,
+            bid_size: market.bid_size,
+            ask_size: market.ask_size,
         })
     }
+
+    /// Сделка по текущей лучшей цене покупки или продажи, до появления котировки сделок нет.
+    fn generate_trade(&mut self, ticker: &str) -> Option<Trade> {
+        let market = self.markets.get_mut(ticker)?;
+        if market.bid_size == Volume::ZERO {
+            return None;
+        }
+        let mut rng = rand::rng();
+
+        let price = if rng.random_bool(0.5) {
+            market.bid
+        } else {
+            market.ask
+        };
+        let size = Volume::new(match ticker {
+            "AAPL" | "MSFT" | "GOOGL" | "TSLA" => rng.random_range(1000..6000),
+            _ => rng.random_range(100..1100),
+        });
+        market.last_price = price;
+        market.last_size = size;
+
+        Some(Trade {
+            ticker: ticker.to_string(),
+            price,
+            size,
+            timestamp: quote_lib::get_timestamp(),
+        })
+    }
+
+    /// Перестроение стакана вокруг новых лучших цен: лучшие уровни получают объемы котировки,
+    /// остальные уровни через шаг цены сохраняют объем или получают новый.
+    fn rebuild_book(&mut self, ticker: &str) -> Option<BookUpdate> {
+        let depth = self.book_depth;
+        if depth == 0 {
+            return None;
+        }
+        let market = self.markets.get_mut(ticker)?;
+        let mut rng = rand::rng();
+        let mut changes = Vec::new();
+
+        for (side, best, best_size) in [
+            (Side::Bid, market.bid, market.bid_size),
+            (Side::Ask, market.ask, market.ask_size),
+        ] {
+            let mut target = Vec::with_capacity(depth);
+            for i in 0..depth {
+                let offset = market.tick.checked_mul(i as i64)?;
+                let price = match side {
+                    Side::Bid => best - offset,
+                    Side::Ask => best + offset,
+                };
+                if price <= Price::ZERO {
+                    break;
+                }
+                let size = match market.book.level(side, price) {
+                    _ if i == 0 => best_size,
+                    Some(size) if rng.random_bool(LEVEL_KEEP_PROBABILITY) => size,
+                    _ => Volume::new(rng.random_range(1..=50) * 100),
+                };
+                target.push((price, size));
+            }
+
+            let current: Vec<Price> = match side {
+                Side::Bid => market.book.bids().map(|l| l.price).collect(),
+                Side::Ask => market.book.asks().map(|l| l.price).collect(),
+            };
+            for price in current {
+                if !target.iter().any(|(p, _)| *p == price) {
+                    changes.push(LevelChange {
+                        side,
+                        price,
+                        size: Volume::ZERO,
+                    });
+                }
+            }
+            for (price, size) in target {
+                if market.book.level(side, price) != Some(size) {
+                    changes.push(LevelChange { side, price, size });
+                }
+            }
+        }
+
+        Self::book_update(ticker, market, changes)
+    }
+
+    /// Сделка забирает объем с уровня своей цены, полностью исполненный уровень удаляется.
+    fn fill_book_level(&mut self, trade: &Trade) -> Option<BookUpdate> {
+        if self.book_depth == 0 {
+            return None;
+        }
+        let market = self.markets.get_mut(&trade.ticker)?;
+        let side = if trade.price == market.bid {
+            Side::Bid
+        } else {
+            Side::Ask
+        };
+        let size = market
+            .book
+            .level(side, trade.price)?
+            .saturating_sub(trade.size);
+        let change = LevelChange {
+            side,
+            price: trade.price,
+            size,
+        };
+        Self::book_update(&trade.ticker, market, vec![change])
+    }
+
+    /// Применение изменений к стакану генератора и обновление со следующим номером.
+    fn book_update(
+        ticker: &str,
+        market: &mut Market,
+        changes: Vec<LevelChange>,
+    ) -> Option<BookUpdate> {
+        if changes.is_empty() {
+            return None;
+        }
+        for change in &changes {
+            market
+                .book
+                .set_level(change.side, change.price, change.size);
+        }
+        Some(BookUpdate {
+            ticker: ticker.to_string(),
+            seq: market.book.next_seq(),
+            timestamp: quote_lib::get_timestamp(),
+            changes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prices_follow_instrument_tick() {
+        let ticks = [("AAPL", "0.05"), ("BRK", "0.25"), ("PENNY", "0.0001")];
+        let instruments = ticks
+            .iter()
+            .map(|(ticker, tick)| Instrument {
+                ticker: ticker.to_string(),
+                tick_size: tick.parse().unwrap(),
+            })
+            .collect();
+        let mut generator = QuoteGenerator::new(instruments, 5);
+        let tick = |ticker: &str| -> Price {
+            ticks
+                .iter()
+                .find(|(t, _)| *t == ticker)
+                .unwrap()
+                .1
+                .parse()
+                .unwrap()
+        };
+
+        for _ in 0..200 {
+            for message in generator.generate_messages() {
+                match message {
+                    Message::Quote(quote) => {
+                        let tick = tick(&quote.ticker);
+                        for price in [quote.price, quote.bid, quote.ask] {
+                            assert!(price.is_on_tick(tick), "{} {}", quote.ticker, price);
+                        }
+                        assert!(quote.bid < quote.ask);
+                    }
+                    Message::Trade(trade) => {
+                        assert!(trade.price.is_on_tick(tick(&trade.ticker)));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
 }
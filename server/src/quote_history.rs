@@ -0,0 +1,62 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::RwLock,
+};
+
+use quote_lib::{StockQuote, Timestamp};
+
+/// Ограниченная история последних котировок по каждому тикеру.
+pub(crate) struct QuoteHistory {
+    /// Максимальное число котировок на тикер.
+    capacity: usize,
+    quotes: RwLock<HashMap<String, VecDeque<StockQuote>>>,
+}
+
+impl QuoteHistory {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            quotes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn push(&self, quote: &StockQuote) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut quotes = self.quotes.write().unwrap();
+        let history = quotes
+            .entry(quote.ticker.clone())
+            .or_insert_with(|| VecDeque::with_capacity(self.capacity));
+        if history.len() == self.capacity {
+            history.pop_front();
+        }
+        history.push_back(quote.clone());
+    }
+
+    /// Последние `count` котировок тикера, от старых к новым.
+    pub(crate) fn last(&self, ticker: &str, count: usize) -> Vec<StockQuote> {
+        let quotes = self.quotes.read().unwrap();
+        let Some(history) = quotes.get(ticker) else {
+            return vec![];
+        };
+        history
+            .iter()
+            .skip(history.len().saturating_sub(count))
+            .cloned()
+            .collect()
+    }
+
+    /// Котировки тикера с временем не раньше `timestamp`, от старых к новым.
+    pub(crate) fn since(&self, ticker: &str, timestamp: Timestamp) -> Vec<StockQuote> {
+        let quotes = self.quotes.read().unwrap();
+        let Some(history) = quotes.get(ticker) else {
+            return vec![];
+        };
+        history
+            .iter()
+            .filter(|quote| quote.timestamp >= timestamp)
+            .cloned()
+            .collect()
+    }
+}
@@ -0,0 +1,437 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use quote_lib::{
+    CONFLATE_OPTION, MAX_RATE_OPTION, MIN_CHANGE_OPTION, Message, Price, SharedMessage,
+};
+
+/// Минимальное изменение цены: `0.05` - абсолютное, `0.1%` - от последней отправленной цены.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum PriceChange {
+    Absolute(Price),
+    Percent(f64),
+}
+
+impl PriceChange {
+    fn reached(&self, last: Price, price: Price) -> bool {
+        match self {
+            PriceChange::Absolute(min) => (price - last).units().abs() >= min.units(),
+            PriceChange::Percent(pct) => {
+                (price.to_f64() / last.to_f64() - 1.0).abs() * 100.0 >= *pct
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for PriceChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PriceChange::Absolute(price) => write!(f, "{}", price),
+            PriceChange::Percent(pct) => write!(f, "{}%", pct),
+        }
+    }
+}
+
+impl std::str::FromStr for PriceChange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let change = match s.strip_suffix('%') {
+            Some(pct) => pct
+                .parse()
+                .ok()
+                .filter(|pct: &f64| pct.is_finite() && *pct > 0.0)
+                .map(PriceChange::Percent),
+            None => s
+                .parse()
+                .ok()
+                .filter(|price: &Price| *price > Price::ZERO)
+                .map(PriceChange::Absolute),
+        };
+        change.ok_or_else(|| format!("Некорректное изменение цены: {}", s))
+    }
+}
+
+/// Ограничения потока котировок одного тикера, незаданное ограничение не действует.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct TickerFilter {
+    pub(crate) min_change: Option<PriceChange>,
+    /// Наибольшее число котировок в секунду, лишние отбрасываются.
+    pub(crate) max_rate: Option<u32>,
+    /// Интервал, за который отправляется только последняя котировка.
+    pub(crate) conflate: Option<Duration>,
+}
+
+impl TickerFilter {
+    /// Ограничения тикера поверх общих.
+    fn or(self, common: TickerFilter) -> TickerFilter {
+        TickerFilter {
+            min_change: self.min_change.or(common.min_change),
+            max_rate: self.max_rate.or(common.max_rate),
+            conflate: self.conflate.or(common.conflate),
+        }
+    }
+}
+
+/// Ограничения потока котировок подписки: общие и отдельные для тикеров.
+/// Действуют только на котировки, остальные сообщения отправляются без изменений.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct StreamFilter {
+    common: TickerFilter,
+    tickers: HashMap<String, TickerFilter>,
+}
+
+impl StreamFilter {
+    /// Нет ни одного ограничения.
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == StreamFilter::default()
+    }
+
+    /// Ограничения для тикера с учетом общих.
+    pub(crate) fn for_ticker(&self, ticker: &str) -> TickerFilter {
+        self.tickers
+            .get(ticker)
+            .map_or(self.common, |filter| filter.or(self.common))
+    }
+
+    /// Разбор опции команды: `min-change=0.1%`, `max-rate=5`, `conflate=250ms`
+    /// или то же для одного тикера: `min-change:AAPL=0.5`. `None` - опция не относится к фильтру,
+    /// `Some(Err)` - некорректное значение.
+    pub(crate) fn parse_option(&mut self, option: &str) -> Option<Result<(), String>> {
+        let (name, value) = option.split_once('=')?;
+        let (name, ticker) = match name.split_once(':') {
+            Some((name, ticker)) => (name, Some(ticker)),
+            None => (name, None),
+        };
+        if ![MIN_CHANGE_OPTION, MAX_RATE_OPTION, CONFLATE_OPTION].contains(&name) {
+            return None;
+        }
+        let filter = match ticker {
+            Some("") => return Some(Err(format!("Не указан тикер опции: {}", option))),
+            Some(ticker) => self.tickers.entry(ticker.to_string()).or_default(),
+            None => &mut self.common,
+        };
+        let result = match name {
+            MIN_CHANGE_OPTION => value.parse().map(|change| filter.min_change = Some(change)),
+            MAX_RATE_OPTION => match value.parse() {
+                Ok(rate) if rate > 0 => {
+                    filter.max_rate = Some(rate);
+                    Ok(())
+                }
+                _ => Err(format!("Некорректная частота котировок: {}", value)),
+            },
+            _ => parse_duration(value).map(|interval| filter.conflate = Some(interval)),
+        };
+        Some(result)
+    }
+
+    /// Проверка сочетания опций: частота и объединение котировок взаимоисключающие.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let conflicting = std::iter::once(self.common)
+            .chain(self.tickers.values().map(|filter| filter.or(self.common)))
+            .any(|filter| filter.max_rate.is_some() && filter.conflate.is_some());
+        if conflicting {
+            return Err(format!(
+                "Опции {} и {} не сочетаются",
+                MAX_RATE_OPTION, CONFLATE_OPTION
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Интервал `250ms` или `2s`.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let duration = if let Some(ms) = value.strip_suffix("ms") {
+        ms.parse().ok().map(Duration::from_millis)
+    } else if let Some(secs) = value.strip_suffix('s') {
+        secs.parse().ok().map(Duration::from_secs)
+    } else {
+        None
+    };
+    duration
+        .filter(|duration| !duration.is_zero())
+        .ok_or_else(|| format!("Некорректный интервал: {}", value))
+}
+
+/// Состояние тикера в потоке клиента.
+#[derive(Default)]
+struct TickerState {
+    /// Цена последней отправленной котировки.
+    last_price: Option<Price>,
+    last_sent: Option<Instant>,
+    /// Последняя котировка интервала объединения и время его окончания.
+    pending: Option<(Arc<SharedMessage>, Instant)>,
+}
+
+/// Применение ограничений подписки к котировкам в потоке отправки клиенту.
+pub(crate) struct QuoteThrottle {
+    filter: StreamFilter,
+    tickers: HashMap<String, TickerState>,
+}
+
+impl QuoteThrottle {
+    pub(crate) fn new(filter: StreamFilter) -> Self {
+        Self {
+            filter,
+            tickers: HashMap::new(),
+        }
+    }
+
+    /// Учет сообщения, отправленного без ограничений (начальный снимок).
+    pub(crate) fn record(&mut self, message: &Message, now: Instant) {
+        if let Message::Quote(quote) = message {
+            let state = self.tickers.entry(quote.ticker.clone()).or_default();
+            state.last_price = Some(quote.price);
+            state.last_sent = Some(now);
+        }
+    }
+
+    /// Новое сообщение потока, возвращает его, если отправлять сразу.
+    /// При объединении котировка ждет окончания интервала в [`QuoteThrottle::flush`].
+    pub(crate) fn offer(
+        &mut self,
+        message: Arc<SharedMessage>,
+        now: Instant,
+    ) -> Option<Arc<SharedMessage>> {
+        let Message::Quote(quote) = message.message() else {
+            return Some(message);
+        };
+        let filter = self.filter.for_ticker(&quote.ticker);
+        if filter == TickerFilter::default() {
+            return Some(message);
+        }
+        let state = self.tickers.entry(quote.ticker.clone()).or_default();
+
+        if let Some(interval) = filter.conflate {
+            let deadline = state.pending.as_ref().map_or(now + interval, |(_, at)| *at);
+            state.pending = Some((message, deadline));
+            return None;
+        }
+        if !changed_enough(&filter, state, quote.price) {
+            return None;
+        }
+        if let (Some(rate), Some(last_sent)) = (filter.max_rate, state.last_sent)
+            && now.duration_since(last_sent) < Duration::from_secs(1) / rate
+        {
+            return None;
+        }
+        state.last_price = Some(quote.price);
+        state.last_sent = Some(now);
+        Some(message)
+    }
+
+    /// Ближайшее окончание интервала объединения.
+    pub(crate) fn next_flush(&self) -> Option<Instant> {
+        self.tickers
+            .values()
+            .filter_map(|state| state.pending.as_ref().map(|(_, at)| *at))
+            .min()
+    }
+
+    /// Последние котировки закончившихся интервалов объединения, прошедшие по изменению цены.
+    pub(crate) fn flush(&mut self, now: Instant) -> Vec<Arc<SharedMessage>> {
+        let mut messages = vec![];
+        for (ticker, state) in &mut self.tickers {
+            if state.pending.as_ref().is_none_or(|(_, at)| *at > now) {
+                continue;
+            }
+            let Some((message, _)) = state.pending.take() else {
+                continue;
+            };
+            let Message::Quote(quote) = message.message() else {
+                continue;
+            };
+            if !changed_enough(&self.filter.for_ticker(ticker), state, quote.price) {
+                continue;
+            }
+            state.last_price = Some(quote.price);
+            state.last_sent = Some(now);
+            messages.push(message);
+        }
+        messages
+    }
+}
+
+fn changed_enough(filter: &TickerFilter, state: &TickerState, price: Price) -> bool {
+    match (filter.min_change, state.last_price) {
+        (Some(change), Some(last)) => change.reached(last, price),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quote_lib::{StockQuote, Timestamp, Volume};
+
+    use super::*;
+
+    fn price(value: &str) -> Price {
+        value.parse().unwrap()
+    }
+
+    fn quote(ticker: &str, value: &str) -> Arc<SharedMessage> {
+        let price = price(value);
+        SharedMessage::new(Message::Quote(StockQuote {
+            ticker: ticker.to_string(),
+            price,
+            volume: Volume::ZERO,
+            timestamp: Timestamp::from_millis(0),
+            bid: price,
+            ask: price,
+            bid_size: Volume::ZERO,
+            ask_size: Volume::ZERO,
+        }))
+    }
+
+    fn quote_price(message: &SharedMessage) -> String {
+        match message.message() {
+            Message::Quote(quote) => quote.price.to_string(),
+            message => panic!("не котировка: {:?}", message),
+        }
+    }
+
+    fn filter(options: &[&str]) -> Result<StreamFilter, String> {
+        let mut filter = StreamFilter::default();
+        for option in options {
+            filter.parse_option(option).expect("опция фильтра")?;
+        }
+        filter.validate()?;
+        Ok(filter)
+    }
+
+    #[test]
+    fn price_change_parse() {
+        assert_eq!("0.05".parse(), Ok(PriceChange::Absolute(price("0.05"))));
+        assert_eq!("0.1%".parse(), Ok(PriceChange::Percent(0.1)));
+        for bad in ["0", "-1", "0%", "-0.5%", "NaN%", "inf%", "abc", "%"] {
+            assert!(bad.parse::<PriceChange>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn price_change_reached() {
+        let absolute = PriceChange::Absolute(price("0.05"));
+        assert!(absolute.reached(price("100"), price("100.05")));
+        assert!(absolute.reached(price("100"), price("99.95")));
+        assert!(!absolute.reached(price("100"), price("100.04")));
+
+        let percent = PriceChange::Percent(1.0);
+        assert!(percent.reached(price("100"), price("101")));
+        assert!(percent.reached(price("100"), price("99")));
+        assert!(!percent.reached(price("100"), price("100.5")));
+    }
+
+    #[test]
+    fn parse_options() {
+        let filter = filter(&["min-change=0.1%", "max-rate=5", "min-change:AAPL=0.5"]).unwrap();
+        assert_eq!(
+            filter.for_ticker("AAPL"),
+            TickerFilter {
+                min_change: Some(PriceChange::Absolute(price("0.5"))),
+                max_rate: Some(5),
+                conflate: None,
+            }
+        );
+        assert_eq!(
+            filter.for_ticker("MSFT"),
+            TickerFilter {
+                min_change: Some(PriceChange::Percent(0.1)),
+                max_rate: Some(5),
+                conflate: None,
+            }
+        );
+        assert!(!filter.is_empty());
+        assert!(StreamFilter::default().is_empty());
+
+        let mut filter = StreamFilter::default();
+        assert!(filter.parse_option("format=v2").is_none());
+        assert!(filter.parse_option("seq").is_none());
+        assert!(filter.parse_option("min-change:=1").unwrap().is_err());
+        assert!(filter.parse_option("max-rate=0").unwrap().is_err());
+        assert!(filter.parse_option("max-rate=-1").unwrap().is_err());
+        assert!(filter.parse_option("conflate=0ms").unwrap().is_err());
+        assert!(filter.is_empty());
+    }
+
+    #[test]
+    fn max_rate_and_conflate_exclusive() {
+        assert!(filter(&["max-rate=5", "conflate=250ms"]).is_err());
+        assert!(filter(&["max-rate=5", "conflate:AAPL=1s"]).is_err());
+        assert!(filter(&["max-rate:AAPL=5", "conflate:AAPL=1s"]).is_err());
+        assert!(filter(&["max-rate:AAPL=5", "conflate:MSFT=1s"]).is_ok());
+    }
+
+    #[test]
+    fn parse_durations() {
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+        for bad in ["0s", "0ms", "2", "1.5s", "ms", "-1s", "2m"] {
+            assert!(parse_duration(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn min_change_from_last_sent_price() {
+        let mut throttle = QuoteThrottle::new(filter(&["min-change=0.5"]).unwrap());
+        let now = Instant::now();
+        let sent: Vec<String> = ["100", "100.3", "100.5", "100.9", "100", "99.6"]
+            .iter()
+            .filter_map(|value| throttle.offer(quote("AAPL", value), now))
+            .map(|message| quote_price(&message))
+            .collect();
+        assert_eq!(sent, ["100.00", "100.50", "100.00"]);
+    }
+
+    #[test]
+    fn snapshot_counts_as_sent() {
+        let mut throttle = QuoteThrottle::new(filter(&["min-change=1%"]).unwrap());
+        let now = Instant::now();
+        throttle.record(quote("AAPL", "100").message(), now);
+        assert!(throttle.offer(quote("AAPL", "100.5"), now).is_none());
+        assert!(throttle.offer(quote("AAPL", "101"), now).is_some());
+    }
+
+    #[test]
+    fn max_rate_per_ticker() {
+        let mut throttle = QuoteThrottle::new(filter(&["max-rate=2"]).unwrap());
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert!(throttle.offer(quote("AAPL", "100"), at(0)).is_some());
+        assert!(throttle.offer(quote("MSFT", "300"), at(100)).is_some());
+        assert!(throttle.offer(quote("AAPL", "101"), at(499)).is_none());
+        assert!(throttle.offer(quote("AAPL", "102"), at(500)).is_some());
+        let heartbeat = SharedMessage::new(Message::Heartbeat);
+        assert!(throttle.offer(heartbeat, at(501)).is_some());
+    }
+
+    #[test]
+    fn conflate_sends_last_quote_of_interval() {
+        let mut throttle =
+            QuoteThrottle::new(filter(&["conflate=250ms", "min-change:MSFT=1"]).unwrap());
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert!(throttle.offer(quote("AAPL", "100"), at(0)).is_none());
+        assert!(throttle.offer(quote("AAPL", "101"), at(100)).is_none());
+        assert!(throttle.offer(quote("MSFT", "300"), at(200)).is_none());
+        assert_eq!(throttle.next_flush(), Some(at(250)));
+        assert!(throttle.flush(at(249)).is_empty());
+
+        let sent: Vec<String> = throttle
+            .flush(at(250))
+            .iter()
+            .map(|m| quote_price(m))
+            .collect();
+        assert_eq!(sent, ["101.00"]);
+        assert_eq!(throttle.next_flush(), Some(at(450)));
+        assert_eq!(throttle.flush(at(450)).len(), 1);
+
+        // Котировка MSFT без достаточного изменения цены не отправляется
+        assert!(throttle.offer(quote("MSFT", "300.5"), at(500)).is_none());
+        assert!(throttle.flush(at(750)).is_empty());
+        assert_eq!(throttle.next_flush(), None);
+    }
+}
@@ -0,0 +1,519 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    net::TcpStream,
+    sync::{Arc, Mutex, atomic::Ordering},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crossbeam::channel::{self, Receiver, Sender};
+use quote_lib::{BookLevel, EXEC_MSG, Message, Price, Side, Volume};
+
+use crate::{command_handler::ServerContext, quote_broadcast::Subscription};
+
+/// Направление заявки: `BUY` или `SELL`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OrderSide {
+    Buy,
+    Sell,
+}
+
+impl OrderSide {
+    fn sign(self) -> i64 {
+        match self {
+            OrderSide::Buy => 1,
+            OrderSide::Sell => -1,
+        }
+    }
+
+    /// Сторона стакана, против которой исполняется заявка.
+    fn book_side(self) -> Side {
+        match self {
+            OrderSide::Buy => Side::Ask,
+            OrderSide::Sell => Side::Bid,
+        }
+    }
+
+    /// Допускает ли лимит исполнение по цене, у рыночной заявки лимита нет.
+    fn accepts(self, price: Price, limit: Option<Price>) -> bool {
+        match (self, limit) {
+            (_, None) => true,
+            (OrderSide::Buy, Some(limit)) => price <= limit,
+            (OrderSide::Sell, Some(limit)) => price >= limit,
+        }
+    }
+}
+
+impl std::fmt::Display for OrderSide {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            OrderSide::Buy => "BUY",
+            OrderSide::Sell => "SELL",
+        })
+    }
+}
+
+impl std::str::FromStr for OrderSide {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "BUY" => Ok(OrderSide::Buy),
+            "SELL" => Ok(OrderSide::Sell),
+            _ => Err(format!("Неизвестное направление заявки: {}", s)),
+        }
+    }
+}
+
+/// Заявка сессии, лимитная заявка ждет исполнения до отмены.
+struct Order {
+    id: u64,
+    ticker: String,
+    side: OrderSide,
+    /// Лимитная цена, `None` - рыночная заявка.
+    limit: Option<Price>,
+    /// Неисполненный остаток.
+    leaves: Volume,
+}
+
+/// Позиция по тикеру: количество со знаком (продажа - отрицательное),
+/// средняя цена открытия и зафиксированный результат.
+#[derive(Default)]
+struct Position {
+    qty: i64,
+    avg_price: Price,
+    realized: Price,
+}
+
+impl Position {
+    /// Учет сделки: наращивание позиции меняет среднюю цену,
+    /// сокращение фиксирует результат, переворот открывает позицию по цене сделки.
+    fn apply_fill(&mut self, side: OrderSide, size: Volume, price: Price) {
+        let qty = size.units() as i64 * side.sign();
+        if self.qty == 0 || self.qty.signum() == qty.signum() {
+            let total = (self.qty.abs() + qty.abs()) as i128;
+            let cost = self.avg_price.units() as i128 * self.qty.abs() as i128
+                + price.units() as i128 * qty.abs() as i128;
+            self.avg_price = Price::from_units((cost / total) as i64);
+            self.qty += qty;
+            return;
+        }
+
+        let closing = qty.abs().min(self.qty.abs());
+        let result = price_diff(price, self.avg_price) * (closing * self.qty.signum()) as i128;
+        self.realized = saturate(self.realized.units() as i128 + result);
+        self.qty += qty;
+        if self.qty == 0 {
+            self.avg_price = Price::ZERO;
+        } else if self.qty.signum() == qty.signum() {
+            self.avg_price = price;
+        }
+    }
+
+    /// Незафиксированный результат по цене оценки.
+    fn unrealized(&self, mark: Price) -> Price {
+        saturate(price_diff(mark, self.avg_price) * self.qty as i128)
+    }
+}
+
+fn price_diff(price: Price, base: Price) -> i128 {
+    price.units() as i128 - base.units() as i128
+}
+
+/// Результат в единицах цены, при переполнении - предельное значение со знаком.
+fn saturate(units: i128) -> Price {
+    i64::try_from(units)
+        .map(Price::from_units)
+        .unwrap_or_else(|_| {
+            log::warn!("Переполнение результата позиции: {} единиц цены", units);
+            Price::from_units(if units < 0 { i64::MIN } else { i64::MAX })
+        })
+}
+
+/// Заявки и позиции одной сессии.
+#[derive(Default)]
+struct Account {
+    orders: BTreeMap<u64, Order>,
+    positions: BTreeMap<String, Position>,
+    next_order_id: u64,
+}
+
+impl Account {
+    /// Исполнение заявки по уровням рынка от лучшей цены,
+    /// отчеты о сделках добавляются в `reports`.
+    fn execute(&mut self, order: &mut Order, levels: &[BookLevel], reports: &mut Vec<String>) {
+        for level in levels {
+            if order.leaves == Volume::ZERO || !order.side.accepts(level.price, order.limit) {
+                break;
+            }
+            let size = level.size.min(order.leaves);
+            order.leaves = order.leaves.saturating_sub(size);
+            self.positions
+                .entry(order.ticker.clone())
+                .or_default()
+                .apply_fill(order.side, size, level.price);
+            reports.push(format!(
+                "{} {} FILL {} {} {} {} {}",
+                EXEC_MSG, order.id, order.ticker, order.side, size, level.price, order.leaves
+            ));
+        }
+    }
+
+    /// Исполнение ожидающих заявок по тикеру после изменения рынка.
+    fn match_resting(&mut self, ticker: &str, ctx: &ServerContext) -> Vec<String> {
+        let ids: Vec<u64> = self
+            .orders
+            .values()
+            .filter(|order| order.ticker == ticker)
+            .map(|order| order.id)
+            .collect();
+        let mut reports = vec![];
+        for id in ids {
+            let Some(mut order) = self.orders.remove(&id) else {
+                continue;
+            };
+            let levels = market_levels(&order, ctx);
+            self.execute(&mut order, &levels, &mut reports);
+            if order.leaves > Volume::ZERO {
+                self.orders.insert(id, order);
+            }
+        }
+        reports
+    }
+}
+
+/// Уровни для исполнения заявки: противоположная сторона симулируемого стакана,
+/// без стакана - лучшая цена котировки на весь объем заявки.
+/// Учебные заявки не забирают ликвидность из стакана генератора.
+fn market_levels(order: &Order, ctx: &ServerContext) -> Vec<BookLevel> {
+    if let Some(levels) = ctx
+        .book_cache
+        .levels(&order.ticker, order.side.book_side())
+        .filter(|levels| !levels.is_empty())
+    {
+        return levels;
+    }
+    ctx.quote_cache
+        .get(&order.ticker)
+        .map(|quote| {
+            let price = match order.side {
+                OrderSide::Buy => quote.ask,
+                OrderSide::Sell => quote.bid,
+            };
+            vec![BookLevel {
+                price,
+                size: order.leaves,
+            }]
+        })
+        .unwrap_or_default()
+}
+
+/// Учебная торговая сессия управляющего соединения: заявки исполняются против
+/// сгенерированного рынка, отчеты об исполнении приходят строками `EXEC` в то же соединение.
+pub(crate) struct TradingSession {
+    account: Arc<Mutex<Account>>,
+    reports: Sender<String>,
+    handle: JoinHandle<()>,
+}
+
+impl TradingSession {
+    /// Запуск потока сессии: он пишет отчеты в соединение и исполняет
+    /// лимитные заявки при изменении котировок и стакана.
+    pub(crate) fn start(stream: Arc<Mutex<TcpStream>>, ctx: &ServerContext) -> Self {
+        let account = Arc::new(Mutex::new(Account::default()));
+        let (reports, receiver) = channel::unbounded();
+        let market = ctx.broadcast.subscribe_session();
+        let handle = {
+            let account = account.clone();
+            let ctx = ctx.clone();
+            thread::spawn(move || run_session(account, receiver, market, stream, ctx))
+        };
+        Self {
+            account,
+            reports,
+            handle,
+        }
+    }
+
+    /// Новая заявка: рыночная исполняется сразу, неисполненный остаток отменяется,
+    /// лимитная исполняется по доступным ценам, остаток ждет. Возвращает номер заявки.
+    pub(crate) fn order(
+        &self,
+        side: OrderSide,
+        ticker: String,
+        size: Volume,
+        limit: Option<Price>,
+        ctx: &ServerContext,
+    ) -> u64 {
+        let mut account = self.account.lock().unwrap();
+        account.next_order_id += 1;
+        let mut order = Order {
+            id: account.next_order_id,
+            ticker,
+            side,
+            limit,
+            leaves: size,
+        };
+        let mut reports = vec![format!(
+            "{} {} NEW {} {} {} {}",
+            EXEC_MSG,
+            order.id,
+            order.ticker,
+            order.side,
+            size,
+            limit.map_or("MKT".to_string(), |price| price.to_string())
+        )];
+        let levels = market_levels(&order, ctx);
+        account.execute(&mut order, &levels, &mut reports);
+
+        let id = order.id;
+        if order.leaves > Volume::ZERO {
+            if order.limit.is_some() {
+                account.orders.insert(id, order);
+            } else {
+                reports.push(canceled_report(&order));
+            }
+        }
+        for report in reports {
+            let _ = self.reports.send(report);
+        }
+        id
+    }
+
+    /// Отмена ожидающей заявки, `false` - заявки нет или она уже исполнена.
+    pub(crate) fn cancel(&self, id: u64) -> bool {
+        match self.account.lock().unwrap().orders.remove(&id) {
+            Some(order) => {
+                let _ = self.reports.send(canceled_report(&order));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Позиции по тикерам: `AAPL <количество> <средняя цена> <результат> <оценка>`
+    /// и итог `TOTAL <результат> <оценка>`, оценка - по середине последней котировки.
+    pub(crate) fn positions(&self, ctx: &ServerContext) -> Vec<String> {
+        let account = self.account.lock().unwrap();
+        let mut lines = vec![];
+        let (mut realized, mut unrealized) = (Price::ZERO, Price::ZERO);
+        for (ticker, position) in &account.positions {
+            let mark = ctx
+                .quote_cache
+                .get(ticker)
+                .map(|quote| Price::from_units((quote.bid.units() + quote.ask.units()) / 2))
+                .unwrap_or(position.avg_price);
+            let open = position.unrealized(mark);
+            realized = saturate(realized.units() as i128 + position.realized.units() as i128);
+            unrealized = saturate(unrealized.units() as i128 + open.units() as i128);
+            lines.push(format!(
+                "{} {} {} {} {}",
+                ticker, position.qty, position.avg_price, position.realized, open
+            ));
+        }
+        lines.push(format!("TOTAL {} {}", realized, unrealized));
+        lines
+    }
+
+    /// Остановка сессии при закрытии соединения, ожидающие заявки снимаются.
+    pub(crate) fn stop(self) {
+        let Self {
+            reports, handle, ..
+        } = self;
+        drop(reports);
+        handle.join().unwrap();
+    }
+}
+
+fn canceled_report(order: &Order) -> String {
+    format!(
+        "{} {} CANCELED {} {} {}",
+        EXEC_MSG, order.id, order.ticker, order.side, order.leaves
+    )
+}
+
+fn run_session(
+    account: Arc<Mutex<Account>>,
+    reports: Receiver<String>,
+    market: Subscription,
+    stream: Arc<Mutex<TcpStream>>,
+    ctx: ServerContext,
+) {
+    while ctx.running.load(Ordering::SeqCst) {
+        let lines = crossbeam::select! {
+            recv(reports) -> report => match report {
+                Ok(report) => vec![report],
+                Err(_) => break,
+            },
+            recv(market.receiver()) -> message => match message.as_ref().ok().and_then(|m| match m.message() {
+                Message::Quote(_) | Message::BookUpdate(_) | Message::BookSnapshot(_) => m.ticker(),
+                _ => None,
+            }) {
+                Some(ticker) => account.lock().unwrap().match_resting(ticker, &ctx),
+                None if message.is_err() => break,
+                None => continue,
+            },
+            default(Duration::from_secs(1)) => continue,
+        };
+        if lines.is_empty() {
+            continue;
+        }
+
+        let result = {
+            let mut stream = stream.lock().unwrap();
+            lines
+                .iter()
+                .try_for_each(|line| writeln!(stream, "{}", line))
+                .and_then(|_| stream.flush())
+        };
+        if let Err(e) = result {
+            log::info!("Отчеты об исполнении не отправлены: {}", e);
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(value: &str) -> Price {
+        value.parse().unwrap()
+    }
+
+    fn fill(position: &mut Position, side: OrderSide, size: u64, value: &str) {
+        position.apply_fill(side, Volume::new(size), price(value));
+    }
+
+    fn levels(levels: &[(&str, u64)]) -> Vec<BookLevel> {
+        levels
+            .iter()
+            .map(|&(value, size)| BookLevel {
+                price: price(value),
+                size: Volume::new(size),
+            })
+            .collect()
+    }
+
+    fn order(side: OrderSide, size: u64, limit: Option<&str>) -> Order {
+        Order {
+            id: 1,
+            ticker: "AAPL".to_string(),
+            side,
+            limit: limit.map(price),
+            leaves: Volume::new(size),
+        }
+    }
+
+    #[test]
+    fn adding_to_position_averages_price() {
+        let mut position = Position::default();
+        fill(&mut position, OrderSide::Buy, 100, "10");
+        fill(&mut position, OrderSide::Buy, 300, "12");
+        assert_eq!(position.qty, 400);
+        assert_eq!(position.avg_price, price("11.5"));
+        assert_eq!(position.realized, Price::ZERO);
+        assert_eq!(position.unrealized(price("12")), price("200"));
+    }
+
+    #[test]
+    fn reducing_position_realizes_result() {
+        let mut position = Position::default();
+        fill(&mut position, OrderSide::Buy, 400, "11.5");
+        fill(&mut position, OrderSide::Sell, 100, "13");
+        assert_eq!(position.qty, 300);
+        assert_eq!(position.avg_price, price("11.5"));
+        assert_eq!(position.realized, price("150"));
+
+        fill(&mut position, OrderSide::Sell, 300, "11");
+        assert_eq!(position.qty, 0);
+        assert_eq!(position.avg_price, Price::ZERO);
+        assert_eq!(position.realized, Price::ZERO);
+        assert_eq!(position.unrealized(price("50")), Price::ZERO);
+    }
+
+    #[test]
+    fn flip_long_to_short_opens_at_fill_price() {
+        let mut position = Position::default();
+        fill(&mut position, OrderSide::Buy, 300, "11.5");
+        fill(&mut position, OrderSide::Sell, 500, "11");
+        assert_eq!(position.qty, -200);
+        assert_eq!(position.avg_price, price("11"));
+        assert_eq!(position.realized, price("-150"));
+
+        // Короткая позиция зарабатывает на падении цены
+        assert_eq!(position.unrealized(price("9")), price("400"));
+        fill(&mut position, OrderSide::Buy, 50, "10");
+        assert_eq!(position.qty, -150);
+        assert_eq!(position.avg_price, price("11"));
+        assert_eq!(position.realized, price("-100"));
+
+        fill(&mut position, OrderSide::Sell, 150, "13");
+        assert_eq!(position.qty, -300);
+        assert_eq!(position.avg_price, price("12"));
+    }
+
+    #[test]
+    fn overflowing_result_saturates() {
+        let position = Position {
+            qty: i64::MAX,
+            avg_price: Price::ZERO,
+            realized: Price::ZERO,
+        };
+        assert_eq!(position.unrealized(price("2")), Price::from_units(i64::MAX));
+        assert_eq!(
+            position.unrealized(price("-2")),
+            Price::from_units(i64::MIN)
+        );
+    }
+
+    #[test]
+    fn market_order_walks_levels() {
+        let mut account = Account::default();
+        let mut order = order(OrderSide::Buy, 250, None);
+        let mut reports = vec![];
+        let asks = levels(&[("10", 100), ("10.01", 200), ("10.02", 300)]);
+        account.execute(&mut order, &asks, &mut reports);
+        assert_eq!(order.leaves, Volume::ZERO);
+        assert_eq!(
+            reports,
+            [
+                "EXEC 1 FILL AAPL BUY 100 10.00 150",
+                "EXEC 1 FILL AAPL BUY 150 10.01 0"
+            ]
+        );
+        let position = &account.positions["AAPL"];
+        assert_eq!(position.qty, 250);
+        assert_eq!(position.avg_price, price("10.006"));
+    }
+
+    #[test]
+    fn limit_order_stops_at_limit() {
+        let mut account = Account::default();
+        let asks = levels(&[("10", 100), ("10.01", 200), ("10.02", 300)]);
+        let mut buy = order(OrderSide::Buy, 400, Some("10.01"));
+        let mut reports = vec![];
+        account.execute(&mut buy, &asks, &mut reports);
+        assert_eq!(buy.leaves, Volume::new(100));
+        assert_eq!(reports.len(), 2);
+
+        let bids = levels(&[("10.04", 100)]);
+        let mut sell = order(OrderSide::Sell, 100, Some("10.05"));
+        let mut reports = vec![];
+        account.execute(&mut sell, &bids, &mut reports);
+        assert_eq!(sell.leaves, Volume::new(100));
+        assert!(reports.is_empty());
+    }
+
+    #[test]
+    fn order_side_accepts_limit() {
+        assert!(OrderSide::Buy.accepts(price("10"), None));
+        assert!(OrderSide::Buy.accepts(price("10"), Some(price("10"))));
+        assert!(!OrderSide::Buy.accepts(price("10.01"), Some(price("10"))));
+        assert!(OrderSide::Sell.accepts(price("10.01"), Some(price("10"))));
+        assert!(!OrderSide::Sell.accepts(price("9.99"), Some(price("10"))));
+        assert_eq!("SELL".parse(), Ok(OrderSide::Sell));
+        assert!("sell".parse::<OrderSide>().is_err());
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    net::{SocketAddr, TcpStream},
+    sync::atomic::Ordering,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crossbeam::channel::TryRecvError;
+use quote_lib::{MessageKind, SharedMessage, StockQuote};
+use serde::{Deserialize, Serialize};
+use tungstenite::{Message, WebSocket};
+
+use crate::command_handler::ServerContext;
+
+/// Сообщение от браузера: `{"type":"subscribe","tickers":["AAPL","MSFT"]}`,
+/// типы данных можно указать в подписке: `"types":["quotes","trades"]`.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum WsRequest {
+    Subscribe {
+        tickers: Vec<String>,
+        #[serde(default)]
+        types: Option<Vec<String>>,
+    },
+    Unsubscribe {
+        tickers: Vec<String>,
+    },
+}
+
+/// Ответ браузеру: котировка, текущая подписка или ошибка.
+/// Сделки и статусы из рассылки отправляются как есть в том же JSON виде.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum WsResponse<'a> {
+    Quote(&'a StockQuote),
+    Subscribed { tickers: Vec<String> },
+    Error { message: String },
+}
+
+/// Запуск WebSocket шлюза: JSON подписки и котировки из общей рассылки.
+pub(crate) fn start_ws_server(
+    addr: SocketAddr,
+    ctx: ServerContext,
+) -> Result<JoinHandle<()>, std::io::Error> {
+    let listner = crate::bind_tcp_listener(addr)?;
+    listner.set_nonblocking(true)?;
+    log::info!("WebSocket шлюз запущен на {}", addr);
+
+    Ok(thread::spawn(move || {
+        let mut handles = vec![];
+        while ctx.running.load(Ordering::SeqCst) {
+            match listner.accept() {
+                Ok((stream, _)) => {
+                    let ctx = ctx.clone();
+                    handles.push(thread::spawn(move || handle_ws_client(stream, ctx)));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => {
+                    log::debug!("Error accepting connection: {}", e);
+                }
+            }
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        log::info!("WebSocket шлюз остановлен на {}", addr);
+    }))
+}
+
+fn handle_ws_client(stream: TcpStream, ctx: ServerContext) {
+    let peer_addr = match stream.peer_addr() {
+        Ok(addr) => addr,
+        Err(e) => {
+            log::error!("Failed to get peer address: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = stream.set_nonblocking(false) {
+        log::error!("Failed to set blocking mode: {}", e);
+        return;
+    }
+    let mut ws = match tungstenite::accept(stream) {
+        Ok(ws) => ws,
+        Err(e) => {
+            log::error!("WebSocket handshake failed for {}: {}", peer_addr, e);
+            return;
+        }
+    };
+    // Короткий таймаут чтения, чтобы чередовать прием команд и отправку котировок
+    if let Err(e) = ws
+        .get_ref()
+        .set_read_timeout(Some(Duration::from_millis(100)))
+    {
+        log::error!("Failed to set read timeout: {}", e);
+        return;
+    }
+
+    let receiver = ctx.broadcast.subscribe_client();
+    let client_id = {
+        let mut manager = ctx.client_manager.lock().unwrap();
+        let client_id = manager.add_client(None, vec![]);
+        manager.set_peer_addr(client_id, peer_addr);
+        client_id
+    };
+    log::info!("Новый WebSocket клиент {}: {}", client_id, peer_addr);
+
+    while ctx.running.load(Ordering::SeqCst) {
+        // Открытое соединение заменяет Ping
+        if !ctx.client_manager.lock().unwrap().update_ping(client_id) {
+            break;
+        }
+
+        match ws.read() {
+            Ok(Message::Text(text)) => {
+                let (response, initial) = process_request(&text, client_id, &ctx);
+                let result = std::iter::once(response)
+                    .chain(initial.iter().map(WsResponse::Quote))
+                    .try_for_each(|response| send(&mut ws, &response));
+                if let Err(e) = result {
+                    log::error!("Error sending response to {}: {}", peer_addr, e);
+                    break;
+                }
+            }
+            Ok(Message::Close(_)) => {
+                log::info!("WebSocket клиент отключился: {}", peer_addr);
+                break;
+            }
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut => {}
+            Err(e) => {
+                log::info!("WebSocket соединение {} закрыто: {}", peer_addr, e);
+                break;
+            }
+        }
+
+        let mut result = Ok(());
+        let mut sent = 0;
+        loop {
+            match receiver.receiver().try_recv() {
+                Ok(message) => {
+                    if ctx
+                        .client_manager
+                        .lock()
+                        .unwrap()
+                        .check_client_message(client_id, &message)
+                        .unwrap_or(false)
+                    {
+                        result = send_shared(&mut ws, &message);
+                        if result.is_err() {
+                            break;
+                        }
+                        sent += 1;
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    result = Err(tungstenite::Error::ConnectionClosed);
+                    break;
+                }
+            }
+        }
+        // Пропущенные при переполнении очереди сообщения, в том числе перед отключением
+        if let Some(status) = receiver.take_status() {
+            let sent = send(&mut ws, &status);
+            if result.is_ok() {
+                result = sent;
+            }
+        }
+        let failed = u64::from(result.is_err());
+        if sent + failed > 0 {
+            ctx.client_manager
+                .lock()
+                .unwrap()
+                .record_sent(client_id, sent, failed);
+        }
+        if let Err(e) = result {
+            log::info!("WebSocket соединение {} закрыто: {}", peer_addr, e);
+            break;
+        }
+    }
+
+    ctx.client_manager.lock().unwrap().remove_client(client_id);
+    log::info!("Завершение WebSocket клиента {}: {}", client_id, peer_addr);
+}
+
+/// Обработка сообщения клиента, возвращает ответ и начальный снимок для новых тикеров.
+fn process_request<'a>(
+    text: &str,
+    client_id: u64,
+    ctx: &ServerContext,
+) -> (WsResponse<'a>, Vec<StockQuote>) {
+    let request = match serde_json::from_str::<WsRequest>(text) {
+        Ok(request) => request,
+        Err(e) => {
+            let response = WsResponse::Error {
+                message: format!("Некорректное сообщение: {}", e),
+            };
+            return (response, vec![]);
+        }
+    };
+    let mut manager = ctx.client_manager.lock().unwrap();
+    let (tickers, initial) = match request {
+        WsRequest::Subscribe { tickers, types } => {
+            if let Some(types) = types {
+                match MessageKind::parse_list(&types.join(",")) {
+                    Ok(kinds) => {
+                        manager.set_kinds(client_id, kinds);
+                    }
+                    Err(message) => return (WsResponse::Error { message }, vec![]),
+                }
+            }
+            (
+                manager.subscribe(client_id, &tickers),
+                ctx.initial_snapshot(&tickers),
+            )
+        }
+        WsRequest::Unsubscribe { tickers } => (manager.unsubscribe(client_id, &tickers), vec![]),
+    };
+    match tickers {
+        Some(tickers) => (WsResponse::Subscribed { tickers }, initial),
+        None => {
+            let response = WsResponse::Error {
+                message: "Клиент не найден".to_string(),
+            };
+            (response, vec![])
+        }
+    }
+}
+
+fn send<T: Serialize>(
+    ws: &mut WebSocket<TcpStream>,
+    response: &T,
+) -> Result<(), tungstenite::Error> {
+    let text = serde_json::to_string(response).map_err(|e| {
+        tungstenite::Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    })?;
+    ws.send(Message::text(text))
+}
+
+/// Отправка сообщения рассылки: JSON общий для всех клиентов, копируется только в кадр.
+fn send_shared(
+    ws: &mut WebSocket<TcpStream>,
+    message: &SharedMessage,
+) -> Result<(), tungstenite::Error> {
+    let json = message.json().ok_or_else(|| {
+        tungstenite::Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Сообщение не сериализуется в JSON",
+        ))
+    })?;
+    ws.send(Message::text(&*json))
+}